@@ -0,0 +1,200 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use db::{
+    pool::DbPool,
+    queries::notify::{self, PgListener},
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc::UnboundedReceiver, watch};
+use ws::{
+    broadcast::{
+        Broadcaster, BroadcasterFactory, ControlMessage, Follower, InProcessBroadcaster,
+        MessagePayload, Notification, Subscription,
+    },
+    connection_registry::ConnectionRegistry,
+    game::game_manager::GameManager,
+    keys::Keys,
+    metrics::WsMetrics,
+    pubsub::PubSub,
+    session_queue::Outbound,
+};
+
+use crate::{config::Config, game_store::GameStoreKind};
+
+/// Fan-out over Postgres `LISTEN`/`NOTIFY`, one channel per game, so a
+/// deployment can run on Postgres alone. Channel names are the same as on
+/// Redis.
+pub struct PgBroadcaster {
+    pool: DbPool,
+    registry: Arc<ConnectionRegistry>,
+    keys: Keys,
+    metrics: Arc<WsMetrics>,
+    follower: Follower,
+}
+
+impl PgBroadcaster {
+    pub fn new(
+        pool: DbPool,
+        registry: Arc<ConnectionRegistry>,
+        game_manager: Arc<GameManager>,
+        metrics: Arc<WsMetrics>,
+        keys: Keys,
+    ) -> Self {
+        Self {
+            pool,
+            follower: Follower::new(
+                registry.clone(),
+                game_manager,
+                metrics.clone(),
+                keys.clone(),
+                "postgres",
+            ),
+            registry,
+            keys,
+            metrics,
+        }
+    }
+
+    pub fn factory(pool: DbPool, keys: Keys) -> BroadcasterFactory {
+        Box::new(move |registry, game_manager, metrics| {
+            Ok(Arc::new(Self::new(
+                pool,
+                registry,
+                game_manager,
+                metrics,
+                keys,
+            )))
+        })
+    }
+
+    async fn notify(&self, channel: &str, payload: &str) -> anyhow::Result<()> {
+        let notified = notify::notify(&self.pool.0, channel, payload).await;
+        if notified.is_err() {
+            self.metrics.record_publish_error();
+        }
+
+        Ok(notified?)
+    }
+
+    async fn connect(&self) -> anyhow::Result<PgSubscription> {
+        let listener = notify::listener(&self.pool.0)
+            .await
+            .context("Failed to get Postgres listener connection")?;
+
+        Ok(PgSubscription(listener))
+    }
+}
+
+/// A dedicated Postgres connection running `LISTEN`.
+struct PgSubscription(PgListener);
+
+#[async_trait]
+impl Subscription for PgSubscription {
+    async fn listen(&mut self, channel: &str) -> anyhow::Result<()> {
+        self.0
+            .listen(channel)
+            .await
+            .context("Failed to listen on Postgres channel")
+    }
+
+    async fn unlisten(&mut self, channel: &str) -> anyhow::Result<()> {
+        self.0
+            .unlisten(channel)
+            .await
+            .context("Failed to unlisten Postgres channel")
+    }
+
+    /// `try_recv` yields None when the connection drops.
+    async fn recv(&mut self) -> anyhow::Result<Option<Notification>> {
+        let notification = self.0.try_recv().await?;
+
+        Ok(notification.map(|notification| Notification {
+            channel: notification.channel().to_string(),
+            payload: notification.payload().to_string(),
+        }))
+    }
+}
+
+#[async_trait]
+impl Broadcaster for PgBroadcaster {
+    async fn broadcast(
+        &self,
+        game_id: &str,
+        message: &Outbound,
+        exclude_session_id: &str,
+    ) -> anyhow::Result<()> {
+        self.registry
+            .broadcast_except(game_id, message, exclude_session_id);
+
+        let payload =
+            MessagePayload::new(self.follower.instance_id(), exclude_session_id, message)?;
+        self.notify(
+            &self.keys.game_channel(game_id),
+            &serde_json::to_string(&payload)?,
+        )
+        .await
+        .context("Failed to notify Postgres game channel")
+    }
+
+    async fn control(&self, message: &ControlMessage) -> anyhow::Result<()> {
+        message.apply(&self.registry);
+
+        self.notify(
+            &self.keys.control_channel(),
+            &serde_json::to_string(message)?,
+        )
+        .await
+        .context("Failed to notify Postgres control channel")
+    }
+
+    async fn run(&self, shutdown: watch::Receiver<bool>, game_events: UnboundedReceiver<String>) {
+        self.follower
+            .run(|| self.connect(), shutdown, game_events)
+            .await;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.follower.is_connected()
+    }
+}
+
+/// How game messages reach the other instances, `broadcaster` in the config.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadcasterKind {
+    #[default]
+    Redis,
+    Postgres,
+    InProcess,
+}
+
+impl FromStr for BroadcasterKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(Self::Redis),
+            "postgres" => Ok(Self::Postgres),
+            "in_process" => Ok(Self::InProcess),
+            other => anyhow::bail!(
+                "Unknown broadcaster '{}', expected redis, postgres or in_process",
+                other
+            ),
+        }
+    }
+}
+
+pub fn from_config(config: &Config, pool: &DbPool) -> BroadcasterFactory {
+    match config.broadcaster {
+        BroadcasterKind::Redis => PubSub::factory(
+            &config.redis.url,
+            config.ws.keys.clone(),
+            config.ws.redis.clone(),
+            matches!(config.game_store, GameStoreKind::Redis),
+        ),
+        BroadcasterKind::Postgres => PgBroadcaster::factory(pool.clone(), config.ws.keys.clone()),
+        BroadcasterKind::InProcess => InProcessBroadcaster::factory(),
+    }
+}
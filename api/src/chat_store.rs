@@ -0,0 +1,80 @@
+use std::{collections::HashSet, sync::Arc};
+
+use async_trait::async_trait;
+use db::{pool::DbPool, queries::chat};
+use uuid::Uuid;
+use ws::game::chat::{ChatStore, MemoryChatStore, RedisChatStore};
+
+use crate::{config::Config, game_store::GameStoreKind};
+
+/// Chat in Postgres, next to the games of `PgGameStore`. History and mutes
+/// are deleted with their game.
+pub struct PgChatStore {
+    pool: DbPool,
+}
+
+impl PgChatStore {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ChatStore for PgChatStore {
+    async fn append(&self, game_id: &str, record: &str, keep: usize) -> anyhow::Result<()> {
+        let mut tx = self.pool.0.begin().await?;
+
+        chat::append_chat_message(&mut tx, game_id, record).await?;
+        chat::trim_chat_messages(&mut tx, game_id, keep as i64).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn history(&self, game_id: &str) -> anyhow::Result<Vec<String>> {
+        Ok(chat::list_chat_messages(&self.pool.0, game_id).await?)
+    }
+
+    async fn set_muted(
+        &self,
+        game_id: &str,
+        muter: Uuid,
+        muted: Uuid,
+        on: bool,
+    ) -> anyhow::Result<()> {
+        if on {
+            chat::mute(&self.pool.0, game_id, muter, muted).await?;
+        } else {
+            chat::unmute(&self.pool.0, game_id, muter, muted).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn muted(&self, game_id: &str, muter: Uuid) -> anyhow::Result<HashSet<Uuid>> {
+        let muted = chat::list_muted(&self.pool.0, game_id, muter).await?;
+
+        Ok(muted.into_iter().collect())
+    }
+
+    async fn clear(&self, game_id: &str) -> anyhow::Result<()> {
+        let mut tx = self.pool.0.begin().await?;
+
+        chat::clear_chat(&mut tx, game_id).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Chat is kept wherever the games are, `game_store` in the config.
+pub fn from_config(config: &Config, pool: &DbPool) -> anyhow::Result<Arc<dyn ChatStore>> {
+    match config.game_store {
+        GameStoreKind::Redis => Ok(Arc::new(RedisChatStore::open(
+            &config.redis.url,
+            &config.ws,
+        )?)),
+        GameStoreKind::Memory => Ok(Arc::new(MemoryChatStore::new())),
+        GameStoreKind::Postgres => Ok(Arc::new(PgChatStore::new(pool.clone()))),
+    }
+}
@@ -0,0 +1,332 @@
+use std::{collections::HashMap, fmt::Display, net::IpAddr, str::FromStr};
+
+use anyhow::Context;
+use serde::Deserialize;
+use ws::config::WsConfig;
+
+use crate::{broadcast::BroadcasterKind, game_store::GameStoreKind, oidc::OidcSettings};
+
+/// The built-in `jwt_secret`, which anyone can read in the source.
+const DEFAULT_JWT_SECRET: &str = "secret";
+
+/// Every setting of the server, read once at startup and shared through app
+/// data. Values come from the defaults, then the TOML file named by
+/// `CONFIG_FILE` if set, then the environment, which wins.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub redis: RedisConfig,
+    pub auth: AuthConfig,
+    pub game_store: GameStoreKind,
+    pub broadcaster: BroadcasterKind,
+    /// Append account notifications to this file instead of logging them.
+    pub notifier_file: Option<String>,
+    /// Bearer token Prometheus scrapes `/metrics` with. `/metrics` is not
+    /// served without one.
+    pub metrics_token: Option<String>,
+    pub telemetry: TelemetryConfig,
+    pub ws: WsConfig,
+    /// OpenID Connect login providers, keyed by the name used in
+    /// `/auth/oidc/{provider}`.
+    pub oidc: HashMap<String, OidcSettings>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Required, there is no default database.
+    pub url: String,
+    pub max_connections: u32,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: String,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Signs session tokens and OIDC login state.
+    pub jwt_secret: String,
+    /// How long a session token stays valid.
+    pub token_lifetime_hours: i64,
+    /// Work factor of password hashes.
+    pub bcrypt_cost: u32,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub log_format: LogFormat,
+    /// Which events and spans are kept, in `RUST_LOG` syntax.
+    pub log_filter: String,
+    /// Base URL of an OTLP/HTTP collector to export spans to, e.g.
+    /// `http://localhost:4318`. Nothing is exported when unset.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines, for development.
+    #[default]
+    Text,
+    /// One JSON object per line with the enclosing spans, for production.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => anyhow::bail!("Unknown log format '{}', expected text or json", other),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_format: LogFormat::Text,
+            log_filter: "info".to_string(),
+            otlp_endpoint: None,
+            service_name: "tic-tac".to_string(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Whether only this machine can reach the server, as in development.
+    pub fn is_local(&self) -> bool {
+        self.host == "localhost" || self.host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 5,
+        }
+    }
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6379".to_string(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: DEFAULT_JWT_SECRET.to_string(),
+            token_lifetime_hours: 24,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+        }
+    }
+}
+
+impl Config {
+    /// Loads and validates the configuration. Errors name the setting at
+    /// fault.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = match std::env::var("CONFIG_FILE") {
+            Ok(path) => {
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read config file {}", path))?;
+                Self::from_toml(&text).with_context(|| format!("Invalid config file {}", path))?
+            }
+            Err(_) => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Overrides fields with `HOST`, `PORT`, `DATABASE_URL`,
+    /// `DATABASE_MAX_CONNECTIONS`, `REDIS_URL`, `JWT_SECRET`,
+    /// `JWT_LIFETIME_HOURS`, `BCRYPT_COST`, `GAME_STORE`, `BROADCASTER`,
+    /// `NOTIFIER_FILE`, `METRICS_TOKEN`, `LOG_FORMAT`, `RUST_LOG`, `OTEL_EXPORTER_OTLP_ENDPOINT`,
+    /// `OTEL_SERVICE_NAME`, the WebSocket settings of `WsConfig::apply_env`
+    /// and the OIDC providers. `OIDC_PROVIDERS` is a comma separated list of
+    /// provider names to add, and `OIDC_<NAME>_ISSUER`, `_CLIENT_ID`,
+    /// `_CLIENT_SECRET`, `_REDIRECT_URI` and `_SCOPES` set up each provider.
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Some(host) = env("HOST")? {
+            self.server.host = host;
+        }
+        if let Some(port) = env("PORT")? {
+            self.server.port = port;
+        }
+
+        if let Some(url) = env("DATABASE_URL")? {
+            self.database.url = url;
+        }
+        if let Some(max_connections) = env("DATABASE_MAX_CONNECTIONS")? {
+            self.database.max_connections = max_connections;
+        }
+        if let Some(url) = env("REDIS_URL")? {
+            self.redis.url = url;
+        }
+
+        if let Some(secret) = env("JWT_SECRET")? {
+            self.auth.jwt_secret = secret;
+        }
+        if let Some(hours) = env("JWT_LIFETIME_HOURS")? {
+            self.auth.token_lifetime_hours = hours;
+        }
+        if let Some(cost) = env("BCRYPT_COST")? {
+            self.auth.bcrypt_cost = cost;
+        }
+
+        if let Some(store) = env("GAME_STORE")? {
+            self.game_store = store;
+        }
+        if let Some(broadcaster) = env("BROADCASTER")? {
+            self.broadcaster = broadcaster;
+        }
+        if let Some(path) = env("NOTIFIER_FILE")? {
+            self.notifier_file = Some(path);
+        }
+        if let Some(token) = env("METRICS_TOKEN")? {
+            self.metrics_token = Some(token);
+        }
+
+        if let Some(format) = env("LOG_FORMAT")? {
+            self.telemetry.log_format = format;
+        }
+        if let Some(filter) = env("RUST_LOG")? {
+            self.telemetry.log_filter = filter;
+        }
+        if let Some(endpoint) = env("OTEL_EXPORTER_OTLP_ENDPOINT")? {
+            self.telemetry.otlp_endpoint = Some(endpoint);
+        }
+        if let Some(name) = env("OTEL_SERVICE_NAME")? {
+            self.telemetry.service_name = name;
+        }
+
+        if let Some(names) = env::<String>("OIDC_PROVIDERS")? {
+            for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                self.oidc.entry(name.to_string()).or_default();
+            }
+        }
+        for (name, provider) in &mut self.oidc {
+            let prefix = oidc_prefix(name);
+            if let Some(issuer) = env(&format!("{}_ISSUER", prefix))? {
+                provider.issuer = issuer;
+            }
+            if let Some(client_id) = env(&format!("{}_CLIENT_ID", prefix))? {
+                provider.client_id = client_id;
+            }
+            if let Some(secret) = env(&format!("{}_CLIENT_SECRET", prefix))? {
+                provider.client_secret = secret;
+            }
+            if let Some(uri) = env(&format!("{}_REDIRECT_URI", prefix))? {
+                provider.redirect_uri = uri;
+            }
+            if let Some(scopes) = env(&format!("{}_SCOPES", prefix))? {
+                provider.scopes = scopes;
+            }
+        }
+
+        self.ws.apply_env()
+    }
+
+    pub fn validate(&mut self) -> anyhow::Result<()> {
+        if self.database.url.is_empty() {
+            anyhow::bail!("DATABASE_URL must be set");
+        }
+        if self.database.max_connections == 0 {
+            anyhow::bail!("DATABASE_MAX_CONNECTIONS must be a positive number");
+        }
+        if self.auth.jwt_secret.is_empty() {
+            anyhow::bail!("JWT_SECRET must not be empty");
+        }
+        if self.auth.jwt_secret == DEFAULT_JWT_SECRET && !self.server.is_local() {
+            anyhow::bail!(
+                "JWT_SECRET must be set when HOST is not a loopback address, \
+                 the default lets anyone forge session tokens"
+            );
+        }
+        if self.auth.token_lifetime_hours <= 0 {
+            anyhow::bail!("JWT_LIFETIME_HOURS must be a positive number of hours");
+        }
+        if !(4..=31).contains(&self.auth.bcrypt_cost) {
+            anyhow::bail!("BCRYPT_COST must be between 4 and 31");
+        }
+        if self
+            .metrics_token
+            .as_ref()
+            .is_some_and(|token| token.is_empty())
+        {
+            anyhow::bail!("METRICS_TOKEN must not be empty");
+        }
+        for (name, provider) in &self.oidc {
+            let required = [
+                ("ISSUER", &provider.issuer),
+                ("CLIENT_ID", &provider.client_id),
+                ("CLIENT_SECRET", &provider.client_secret),
+                ("REDIRECT_URI", &provider.redirect_uri),
+            ];
+            for (key, value) in required {
+                if value.is_empty() {
+                    anyhow::bail!("{}_{} must be set", oidc_prefix(name), key);
+                }
+            }
+        }
+
+        self.ws.validate()
+    }
+}
+
+/// Prefix of the variables of the OIDC provider `name`.
+fn oidc_prefix(name: &str) -> String {
+    format!("OIDC_{}", name.to_uppercase())
+}
+
+fn env<T>(key: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("{} is invalid: {}", key, e)),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,141 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use db::{pool::DbPool, queries::game_state};
+use serde::Deserialize;
+use ws::game::{
+    events::{GameEvent, LoggedEvent},
+    game_state::GameState,
+    store::{GameChange, GameStore, MemoryGameStore, RedisGameStore},
+};
+
+use crate::config::Config;
+
+/// Games in Postgres. They outlive restarts and Redis, at the cost of a
+/// round trip to the database for every move.
+pub struct PgGameStore {
+    pool: DbPool,
+    /// How long a game is kept after its last change, like the Redis TTL.
+    ttl: Duration,
+}
+
+impl PgGameStore {
+    pub fn new(pool: DbPool, ttl: Duration) -> Self {
+        Self { pool, ttl }
+    }
+}
+
+#[async_trait]
+impl GameStore for PgGameStore {
+    async fn get(&self, game_id: &str) -> anyhow::Result<Option<GameState>> {
+        match game_state::get_game_state(&self.pool.0, game_id, self.ttl.as_secs_f64()).await? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Also deletes the expired games, which Postgres won't do by itself.
+    /// Creating games is what fills the tables, so it is also what empties
+    /// them.
+    async fn save(&self, game: &GameState, events: &[GameEvent]) -> anyhow::Result<()> {
+        game_state::delete_stale_game_states(&self.pool.0, self.ttl.as_secs_f64()).await?;
+
+        let mut tx = self.pool.0.begin().await?;
+
+        game_state::replace_game_state(&mut tx, &game.id, &serde_json::to_string(game)?).await?;
+        for event in events {
+            game_state::append_game_event(&mut tx, &game.id, &serde_json::to_string(event)?)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update(&self, game_id: &str, change: GameChange<'_>) -> anyhow::Result<GameState> {
+        let mut tx = self.pool.0.begin().await?;
+
+        let json = game_state::lock_game_state(&mut tx, game_id, self.ttl.as_secs_f64())
+            .await?
+            .context("Game not found")?;
+        let mut game: GameState = serde_json::from_str(&json)?;
+        let events = change(&mut game)?;
+
+        game_state::save_game_state(&mut tx, game_id, &serde_json::to_string(&game)?).await?;
+        for event in &events {
+            game_state::append_game_event(&mut tx, game_id, &serde_json::to_string(event)?).await?;
+        }
+
+        tx.commit().await?;
+        Ok(game)
+    }
+
+    async fn delete(&self, game_id: &str) -> anyhow::Result<()> {
+        game_state::delete_game_state(&self.pool.0, game_id).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<GameState>> {
+        game_state::list_game_states(&self.pool.0, self.ttl.as_secs_f64())
+            .await?
+            .iter()
+            .map(|json| Ok(serde_json::from_str(json)?))
+            .collect()
+    }
+
+    async fn events(&self, game_id: &str, after: Option<&str>) -> anyhow::Result<Vec<LoggedEvent>> {
+        let after = match after {
+            Some(id) => id.parse().context("Invalid event id")?,
+            None => 0,
+        };
+
+        game_state::list_game_events(&self.pool.0, game_id, after)
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(LoggedEvent {
+                    id: row.id.to_string(),
+                    event: serde_json::from_str(&row.event)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Where games are kept, `game_store` in the config.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameStoreKind {
+    #[default]
+    Redis,
+    Memory,
+    Postgres,
+}
+
+impl FromStr for GameStoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(Self::Redis),
+            "memory" => Ok(Self::Memory),
+            "postgres" => Ok(Self::Postgres),
+            other => anyhow::bail!(
+                "Unknown game store '{}', expected redis, memory or postgres",
+                other
+            ),
+        }
+    }
+}
+
+pub fn from_config(config: &Config, pool: &DbPool) -> anyhow::Result<Arc<dyn GameStore>> {
+    match config.game_store {
+        GameStoreKind::Redis => Ok(Arc::new(RedisGameStore::open(
+            &config.redis.url,
+            &config.ws,
+        )?)),
+        GameStoreKind::Memory => Ok(Arc::new(MemoryGameStore::new())),
+        GameStoreKind::Postgres => Ok(Arc::new(PgGameStore::new(pool.clone(), config.ws.game_ttl))),
+    }
+}
@@ -1,4 +1,12 @@
+pub mod broadcast;
+pub mod chat_store;
+pub mod config;
+pub mod game_store;
+pub mod metrics;
 pub mod middleware;
+pub mod notifier;
+pub mod oidc;
 pub mod routes;
 pub mod state;
+pub mod telemetry;
 pub mod utils;
@@ -1,45 +1,117 @@
-use std::{env, io::Result};
+use std::io::Result;
 
 use actix_web::{App, HttpResponse, HttpServer, web};
 use db::pool::DbPool;
 
 use dotenvy::dotenv;
-use tic_tac::routes::{self};
+use tic_tac::{
+    broadcast, chat_store,
+    config::Config,
+    game_store,
+    metrics::ApiMetrics,
+    notifier,
+    oidc::OidcProviders,
+    routes::{self},
+    telemetry::{self, RequestSpan},
+};
+use tracing_actix_web::TracingLogger;
 use ws::manager;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
     dotenv().ok();
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {:#}", e);
+        std::process::exit(1);
+    });
+    let telemetry = telemetry::init(&config.telemetry).unwrap_or_else(|e| {
+        eprintln!("Failed to set up telemetry: {:#}", e);
+        std::process::exit(1);
+    });
 
-    let db_pool = DbPool::new(&database_url)
+    let db_pool = DbPool::new(&config.database.url, config.database.max_connections)
         .await
         .expect("Failed to create DB pool");
 
-    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+    let game_store =
+        game_store::from_config(&config, &db_pool).expect("Failed to set up the game store");
+    let chat_store =
+        chat_store::from_config(&config, &db_pool).expect("Failed to set up the chat store");
+    let broadcaster = broadcast::from_config(&config, &db_pool);
 
     let ws_manager = web::Data::new(
-        manager::start_manager(&redis_url)
+        manager::start_manager(config.ws.clone(), game_store, chat_store, broadcaster)
             .await
             .expect("Failed to create ws manager"),
     );
 
+    let metrics_data = web::Data::new(
+        ApiMetrics::new(ws_manager.metrics.registry()).expect("Failed to register metrics"),
+    );
     let pool_data = web::Data::new(db_pool);
+    let notifier_data = web::Data::from(notifier::from_config(&config));
+    let oidc_data = web::Data::new(
+        OidcProviders::from_config(&config)
+            .await
+            .expect("Failed to set up OIDC providers"),
+    );
 
-    HttpServer::new(move || {
+    let shutdown_manager = ws_manager.clone();
+    let bind_address = (config.server.host.clone(), config.server.port);
+    let config_data = web::Data::new(config);
+
+    let server = HttpServer::new(move || {
         App::new()
+            .wrap(TracingLogger::<RequestSpan>::new())
+            .app_data(config_data.clone())
             .app_data(pool_data.clone())
             .app_data(ws_manager.clone())
+            .app_data(notifier_data.clone())
+            .app_data(metrics_data.clone())
+            .app_data(oidc_data.clone())
             .configure(routes::auth::config)
+            .configure(routes::admin::config)
             .configure(routes::game::config)
+            .configure(routes::health::config)
+            .configure(routes::metrics::config)
             .configure(|cfg| routes::websocket::config(cfg, ws_manager.clone()))
             .route(
                 "/ping",
                 web::get().to(|| async { HttpResponse::Ok().body("pong") }),
             )
     })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    .bind(bind_address)?
+    .disable_signals()
+    .run();
+
+    // Sockets are drained before the HTTP server stops, so players get a
+    // reconnect hint instead of a dropped connection.
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        shutdown_manager.shutdown().await;
+        handle.stop(true).await;
+    });
+
+    let result = server.await;
+    telemetry.shutdown();
+
+    result
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
@@ -0,0 +1,76 @@
+use db::pool::DbPool;
+use prometheus::{IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+
+/// Metrics of the HTTP side, registered next to the WebSocket ones so a
+/// single scrape of `/metrics` covers the server.
+pub struct ApiMetrics {
+    auth: IntCounterVec,
+    token_failures: IntCounterVec,
+    db_connections: IntGaugeVec,
+    db_max_connections: IntGauge,
+}
+
+impl ApiMetrics {
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let auth = IntCounterVec::new(
+            Opts::new(
+                "auth_attempts_total",
+                "Sign-ins and registrations, by method and outcome",
+            ),
+            &["method", "outcome"],
+        )?;
+        let token_failures = IntCounterVec::new(
+            Opts::new(
+                "token_verification_failures_total",
+                "Requests turned away by the session token check, by reason",
+            ),
+            &["reason"],
+        )?;
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database connections, by state"),
+            &["state"],
+        )?;
+        let db_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Most database connections the pool will open",
+        )?;
+
+        registry.register(Box::new(auth.clone()))?;
+        registry.register(Box::new(token_failures.clone()))?;
+        registry.register(Box::new(db_connections.clone()))?;
+        registry.register(Box::new(db_max_connections.clone()))?;
+
+        Ok(Self {
+            auth,
+            token_failures,
+            db_connections,
+            db_max_connections,
+        })
+    }
+
+    /// Counts a sign-in through `method`: `password`, `guest` or `oidc`, or
+    /// a `register`.
+    pub fn record_auth(&self, method: &str, success: bool) {
+        let outcome = if success { "success" } else { "failure" };
+        self.auth.with_label_values(&[method, outcome]).inc();
+    }
+
+    /// Counts a request rejected by `jwt_auth_fn`: `missing`, `invalid`,
+    /// `revoked` or `banned`.
+    pub fn record_token_failure(&self, reason: &str) {
+        self.token_failures.with_label_values(&[reason]).inc();
+    }
+
+    /// Takes the current utilization of `pool`, ahead of a scrape.
+    pub fn observe_pool(&self, pool: &DbPool) {
+        let stats = pool.stats();
+
+        self.db_connections
+            .with_label_values(&["idle"])
+            .set(stats.idle as i64);
+        self.db_connections
+            .with_label_values(&["in_use"])
+            .set(stats.in_use() as i64);
+        self.db_max_connections.set(stats.max as i64);
+    }
+}
@@ -2,19 +2,33 @@ use actix_web::{
     Error, HttpMessage, HttpRequest,
     body::BoxBody,
     dev::{ServiceRequest, ServiceResponse},
-    error::ErrorUnauthorized,
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    web,
 };
 use actix_web_lab::middleware::Next;
+use db::{
+    models::{ban::UserBan, user::Role},
+    pool::DbPool,
+    queries::{admin, auth},
+};
+use tracing_actix_web::RootSpan;
 use uuid::Uuid;
 
-use crate::utils::verify_jwt;
+use crate::{
+    config::Config,
+    metrics::ApiMetrics,
+    utils::{Claims, verify_jwt},
+};
 
 #[derive(Clone, Debug)]
 pub struct AuthenticatedUser {
     pub user_id: Uuid,
+    pub username: String,
+    pub is_guest: bool,
+    pub role: Role,
 }
 
-pub fn extract_user_from_request(req: &HttpRequest) -> Result<Uuid, Error> {
+pub fn extract_claims_from_request(req: &HttpRequest) -> Result<Claims, Error> {
     let auth_header = req
         .headers()
         .get("Authorization")
@@ -24,22 +38,112 @@ pub fn extract_user_from_request(req: &HttpRequest) -> Result<Uuid, Error> {
         .to_str()
         .map_err(|_| ErrorUnauthorized("Invalid authorization header"))?;
 
-    let token = if header_str.starts_with("Bearer ") {
-        header_str[7..].to_string()
+    let token = header_str.strip_prefix("Bearer ").unwrap_or(header_str);
+
+    let config = req
+        .app_data::<web::Data<Config>>()
+        .ok_or_else(|| ErrorInternalServerError("Configuration not loaded"))?;
+
+    verify_jwt(token, &config.auth).map_err(|_| ErrorUnauthorized("Invalid or expired token"))
+}
+
+pub fn extract_user_from_request(req: &HttpRequest) -> Result<Uuid, Error> {
+    Ok(extract_claims_from_request(req)?.sub)
+}
+
+pub async fn jwt_auth_fn(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let reject = |reason: &str, error: Error| {
+        if let Some(metrics) = req.app_data::<web::Data<ApiMetrics>>() {
+            metrics.record_token_failure(reason);
+        }
+        error
+    };
+
+    let reason = if req.headers().contains_key("Authorization") {
+        "invalid"
     } else {
-        header_str.to_string()
+        "missing"
     };
+    let claims = extract_claims_from_request(req.request()).map_err(|e| reject(reason, e))?;
+
+    let pool = req
+        .app_data::<web::Data<DbPool>>()
+        .ok_or_else(|| ErrorInternalServerError("Database pool not configured"))?;
 
-    let user_id = verify_jwt(&token).map_err(|_| ErrorUnauthorized("Invalid or expired token"))?;
+    // Tokens minted before the last password change are revoked.
+    let user = auth::get_user_by_id(&pool.0, claims.sub)
+        .await
+        .map_err(|_| reject("invalid", ErrorUnauthorized("Invalid or expired token")))?;
 
-    Ok(user_id)
+    if user.token_version != claims.ver {
+        return Err(reject(
+            "revoked",
+            ErrorUnauthorized("Session has been revoked"),
+        ));
+    }
+
+    let ban = admin::get_active_ban(&pool.0, user.id)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("Database error: {}", e)))?;
+    if let Some(ban) = ban {
+        return Err(reject("banned", ban_error(&ban)));
+    }
+
+    if let Some(span) = req.extensions().get::<RootSpan>() {
+        span.record("user_id", tracing::field::display(user.id));
+    }
+
+    req.extensions_mut().insert(AuthenticatedUser {
+        user_id: user.id,
+        username: user.username,
+        is_guest: user.is_guest,
+        role: claims.role,
+    });
+    next.call(req).await
 }
 
-pub async fn jwt_auth_fn(
+/// Must be wrapped inside `jwt_auth_fn`.
+pub async fn require_moderator(
     req: ServiceRequest,
     next: Next<BoxBody>,
 ) -> Result<ServiceResponse<BoxBody>, Error> {
-    let user_id = extract_user_from_request(req.request())?;
-    req.extensions_mut().insert(AuthenticatedUser { user_id });
+    check_role(&req, Role::Moderator)?;
     next.call(req).await
 }
+
+/// Must be wrapped inside `jwt_auth_fn`.
+pub async fn require_admin(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    check_role(&req, Role::Admin)?;
+    next.call(req).await
+}
+
+pub fn ban_error(ban: &UserBan) -> Error {
+    match ban.expires_at {
+        Some(expires_at) => ErrorForbidden(format!(
+            "Account is suspended until {}: {}",
+            expires_at.to_rfc3339(),
+            ban.reason
+        )),
+        None => ErrorForbidden(format!("Account is banned: {}", ban.reason)),
+    }
+}
+
+fn check_role(req: &ServiceRequest, required: Role) -> Result<(), Error> {
+    let role = req
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.role)
+        .ok_or_else(|| ErrorUnauthorized("Not authenticated"))?;
+
+    if role < required {
+        return Err(ErrorForbidden("Insufficient permissions"));
+    }
+
+    Ok(())
+}
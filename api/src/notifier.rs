@@ -0,0 +1,92 @@
+use std::{io::Write, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use db::models::user::User;
+
+use crate::config::Config;
+
+/// Delivers out-of-band account messages such as password reset tokens.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send_password_reset(
+        &self,
+        user: &User,
+        token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<()>;
+}
+
+/// Logs that a notification was due, without the reset token, which would
+/// let anyone reading the logs take over the account. Set `notifier_file`
+/// to get the tokens.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send_password_reset(
+        &self,
+        user: &User,
+        _token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        tracing::info!(
+            user_id = %user.id,
+            %expires_at,
+            "Password reset requested, set NOTIFIER_FILE to deliver the token"
+        );
+        Ok(())
+    }
+}
+
+/// Appends notifications to a file, one JSON object per line.
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send_password_reset(
+        &self,
+        user: &User,
+        token: &str,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let line = serde_json::json!({
+            "kind": "password_reset",
+            "user_id": user.id,
+            "username": user.username,
+            "token": token,
+            "expires_at": expires_at.to_rfc3339(),
+        })
+        .to_string();
+
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln!(file, "{}", line)?;
+            Ok(())
+        })
+        .await??;
+
+        Ok(())
+    }
+}
+
+/// The file notifier when `notifier_file` is set, otherwise notifications
+/// are logged.
+pub fn from_config(config: &Config) -> Arc<dyn Notifier> {
+    match &config.notifier_file {
+        Some(path) => Arc::new(FileNotifier::new(path.clone())),
+        None => Arc::new(LogNotifier),
+    }
+}
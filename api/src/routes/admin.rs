@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, Result, web};
+use actix_web_lab::middleware::from_fn;
+use chrono::{Duration, Utc};
+use db::{
+    models::user::Role,
+    pool::DbPool,
+    queries::{admin, auth},
+};
+use uuid::Uuid;
+use ws::manager::WsManager;
+
+use crate::middleware::{AuthenticatedUser, jwt_auth_fn, require_admin, require_moderator};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .service(
+                web::resource("/users/{user_id}/role")
+                    .wrap(from_fn(require_admin))
+                    .route(web::put().to(set_role)),
+            )
+            .route("/games", web::get().to(list_games))
+            .route("/sessions", web::get().to(list_sessions))
+            .route("/games/{game_id}/end", web::post().to(end_game))
+            .route("/games/{game_id}", web::delete().to(delete_game))
+            .route("/users/{user_id}/bans", web::get().to(list_bans))
+            .route("/users/{user_id}/ban", web::post().to(ban_user))
+            .route("/users/{user_id}/ban", web::delete().to(unban_user))
+            .wrap(from_fn(require_moderator))
+            .wrap(from_fn(jwt_auth_fn)),
+    );
+}
+
+#[derive(serde::Deserialize)]
+pub struct BanRequest {
+    pub reason: String,
+    /// Omit for a permanent ban.
+    pub duration_hours: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetRoleRequest {
+    pub role: Role,
+}
+
+async fn list_games(manager: web::Data<Arc<WsManager>>) -> Result<HttpResponse> {
+    let games = manager.game_manager.list_games().await.map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Failed to list games: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(games))
+}
+
+/// Sessions connected to this instance, with their outbound queue depth.
+async fn list_sessions(manager: web::Data<Arc<WsManager>>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(manager.registry.session_stats()))
+}
+
+async fn end_game(
+    manager: web::Data<Arc<WsManager>>,
+    game_id: web::Path<String>,
+) -> Result<HttpResponse> {
+    let game = manager
+        .game_manager
+        .end_game(&game_id)
+        .await
+        .map_err(|e| actix_web::error::ErrorNotFound(format!("Failed to end game: {}", e)))?;
+
+    manager.broadcast_game_state(&game).await.map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Failed to notify players: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(game))
+}
+
+async fn delete_game(
+    manager: web::Data<Arc<WsManager>>,
+    game_id: web::Path<String>,
+) -> Result<HttpResponse> {
+    manager
+        .close_game(&game_id, "Game was deleted")
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Failed to close sockets: {}", e))
+        })?;
+
+    manager
+        .game_manager
+        .delete_game(&game_id)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Failed to delete game: {}", e))
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn ban_user(
+    pool: web::Data<DbPool>,
+    manager: web::Data<Arc<WsManager>>,
+    user_id: web::Path<Uuid>,
+    body: web::Json<BanRequest>,
+    moderator: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    let target = auth::get_user_by_id(&pool.0, *user_id)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("User not found"))?;
+
+    if target.id == moderator.user_id || target.role >= moderator.role {
+        return Err(actix_web::error::ErrorForbidden(
+            "You can only ban users with a lower role",
+        ));
+    }
+
+    let reason = body.reason.trim();
+    if reason.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "A ban reason is required",
+        ));
+    }
+
+    let expires_at = match body.duration_hours {
+        Some(hours) if hours > 0 => Some(Utc::now() + Duration::hours(hours)),
+        Some(_) => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "duration_hours must be positive",
+            ));
+        }
+        None => None,
+    };
+
+    let ban = admin::ban_user(&pool.0, target.id, moderator.user_id, reason, expires_at)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    manager
+        .disconnect_user(target.id, reason)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!(
+                "Ban recorded but sockets were not closed: {}",
+                e
+            ))
+        })?;
+
+    Ok(HttpResponse::Ok().json(ban))
+}
+
+async fn list_bans(pool: web::Data<DbPool>, user_id: web::Path<Uuid>) -> Result<HttpResponse> {
+    let bans = admin::list_bans(&pool.0, *user_id).await.map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(bans))
+}
+
+async fn unban_user(pool: web::Data<DbPool>, user_id: web::Path<Uuid>) -> Result<HttpResponse> {
+    let removed = admin::unban_user(&pool.0, *user_id).await.map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+    })?;
+
+    if removed == 0 {
+        return Err(actix_web::error::ErrorNotFound("User is not banned"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn set_role(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<Uuid>,
+    body: web::Json<SetRoleRequest>,
+) -> Result<HttpResponse> {
+    let user = admin::set_user_role(&pool.0, *user_id, body.role)
+        .await
+        .map_err(|e| actix_web::error::ErrorNotFound(format!("Failed to update role: {}", e)))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": user.id,
+        "username": user.username,
+        "role": user.role
+    })))
+}
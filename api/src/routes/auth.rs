@@ -1,17 +1,70 @@
+use std::sync::Arc;
+
 use actix_web::{
-    HttpResponse, Result,
-    web::{self, Json, post},
+    HttpRequest, HttpResponse, Result,
+    cookie::{Cookie, SameSite, time},
+    http::header,
+    web::{self, Json, get, post},
+};
+use actix_web_lab::middleware::from_fn;
+use bcrypt::{hash, verify};
+use chrono::{Duration, Utc};
+use db::{
+    models::user::User,
+    pool::DbPool,
+    queries::{admin, auth, identity},
+};
+use uuid::Uuid;
+use ws::manager::WsManager;
+
+use crate::{
+    config::Config,
+    metrics::ApiMetrics,
+    middleware::{AuthenticatedUser, ban_error, jwt_auth_fn},
+    notifier::Notifier,
+    oidc::{ExternalIdentity, OidcProviders},
+    utils::{
+        OIDC_STATE_TTL_MINUTES, generate_jwt, generate_oidc_state, generate_random_token,
+        hash_reset_token, verify_oidc_state,
+    },
 };
-use bcrypt::{DEFAULT_COST, hash, verify};
-use db::{pool::DbPool, queries::auth};
 
-use crate::utils::generate_jwt;
+const RESET_TOKEN_TTL_MINUTES: i64 = 30;
+const GUEST_PREFIX: &str = "guest-";
+const MAX_USERNAME_CHARS: usize = 32;
+/// Holds the secret an OIDC state is bound to, from login start to callback.
+const OIDC_COOKIE: &str = "oidc_browser";
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
             .route("/register", post().to(register))
-            .route("/login", post().to(login)),
+            .route("/login", post().to(login))
+            .route("/guest", post().to(guest_login))
+            .route("/password/forgot", post().to(forgot_password))
+            .route("/password/reset", post().to(reset_password))
+            .service(
+                web::resource("/upgrade")
+                    .wrap(from_fn(jwt_auth_fn))
+                    .route(post().to(upgrade_guest)),
+            )
+            .service(
+                web::resource("/password/change")
+                    .wrap(from_fn(jwt_auth_fn))
+                    .route(post().to(change_password)),
+            )
+            .route("/oidc/{provider}/login", get().to(oidc_login))
+            .route("/oidc/{provider}/callback", get().to(oidc_callback))
+            .service(
+                web::resource("/oidc/{provider}/link")
+                    .wrap(from_fn(jwt_auth_fn))
+                    .route(post().to(oidc_link)),
+            )
+            .service(
+                web::resource("/identities")
+                    .wrap(from_fn(jwt_auth_fn))
+                    .route(get().to(list_identities)),
+            ),
     );
 }
 
@@ -27,13 +80,57 @@ pub struct LoginRequest {
     pub password: String,
 }
 
-async fn register(pool: web::Data<DbPool>, body: Json<RegisterRequest>) -> Result<HttpResponse> {
-    let username = body.username.trim();
+#[derive(serde::Deserialize)]
+pub struct UpgradeGuestRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub username: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+async fn register(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    metrics: web::Data<ApiMetrics>,
+    body: Json<RegisterRequest>,
+) -> Result<HttpResponse> {
+    let response = create_account(&pool, &config, &body).await;
+    metrics.record_auth("register", response.is_ok());
+
+    response
+}
+
+async fn create_account(
+    pool: &DbPool,
+    config: &Config,
+    body: &RegisterRequest,
+) -> Result<HttpResponse> {
+    let username = validate_username(&body.username)?;
     let password = body.password.trim();
 
-    let hash_pass = hash(password, DEFAULT_COST).map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!("Password hashing failed: {}", e))
-    })?;
+    let hash_pass = hash_password(password, config)?;
 
     let user = auth::create_user(&pool.0, username, &hash_pass)
         .await
@@ -52,12 +149,35 @@ async fn register(pool: web::Data<DbPool>, body: Json<RegisterRequest>) -> Resul
     })))
 }
 
-async fn login(pool: web::Data<DbPool>, body: Json<LoginRequest>) -> Result<HttpResponse> {
+async fn login(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    metrics: web::Data<ApiMetrics>,
+    body: Json<LoginRequest>,
+) -> Result<HttpResponse> {
+    let response = password_login(&pool, &config, &body).await;
+    metrics.record_auth("password", response.is_ok());
+
+    response
+}
+
+async fn password_login(
+    pool: &DbPool,
+    config: &Config,
+    body: &LoginRequest,
+) -> Result<HttpResponse> {
     let user = auth::get_user_by_username(&pool.0, &body.username)
         .await
         .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid username or password"))?;
 
-    let is_valid = verify(&body.password, &user.password)
+    // Guests have no password and can't log in this way.
+    let Some(password_hash) = user.password.as_deref() else {
+        return Err(actix_web::error::ErrorUnauthorized(
+            "Invalid username or password",
+        ));
+    };
+
+    let is_valid = verify(&body.password, password_hash)
         .map_err(|_| actix_web::error::ErrorInternalServerError("Password verification failed"))?;
 
     if !is_valid {
@@ -66,7 +186,369 @@ async fn login(pool: web::Data<DbPool>, body: Json<LoginRequest>) -> Result<Http
         ));
     }
 
-    let token = generate_jwt(user.id).map_err(|e| {
+    ensure_not_banned(pool, &user).await?;
+
+    let token = generate_jwt(&user, &config.auth).map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Token generation failed: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "user_id": user.id,
+        "username": user.username
+    })))
+}
+
+async fn guest_login(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    metrics: web::Data<ApiMetrics>,
+) -> Result<HttpResponse> {
+    let response = create_guest(&pool, &config).await;
+    metrics.record_auth("guest", response.is_ok());
+
+    response
+}
+
+async fn create_guest(pool: &DbPool, config: &Config) -> Result<HttpResponse> {
+    let username = format!(
+        "{}{}",
+        GUEST_PREFIX,
+        &Uuid::new_v4().simple().to_string()[..8]
+    );
+
+    // Guests whose token has expired are dropped as new ones arrive.
+    let token_lifetime = (config.auth.token_lifetime_hours * 3600) as f64;
+    auth::delete_stale_guests(&pool.0, token_lifetime)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    let user = auth::create_guest_user(&pool.0, &username)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    let token = generate_jwt(&user, &config.auth).map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Token generation failed: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "user_id": user.id,
+        "username": user.username,
+        "guest": true
+    })))
+}
+
+async fn upgrade_guest(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    body: Json<UpgradeGuestRequest>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    if !user.is_guest {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Account is already a full account",
+        ));
+    }
+
+    let username = validate_username(&body.username)?;
+    let hash_pass = hash_password(body.password.trim(), &config)?;
+
+    let upgraded = auth::upgrade_guest_user(&pool.0, user.user_id, username, &hash_pass)
+        .await
+        .map_err(|e| {
+            let error_msg = e.to_string();
+            if error_msg.contains("duplicate key") || error_msg.contains("unique constraint") {
+                actix_web::error::ErrorBadRequest("Username already exists")
+            } else {
+                actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+            }
+        })?
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Account is already a full account"))?;
+
+    let token = generate_jwt(&upgraded, &config.auth).map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Token generation failed: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "user_id": upgraded.id,
+        "username": upgraded.username
+    })))
+}
+
+async fn change_password(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    manager: web::Data<Arc<WsManager>>,
+    body: Json<ChangePasswordRequest>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    let current = auth::get_user_by_id(&pool.0, user.user_id)
+        .await
+        .map_err(|_| actix_web::error::ErrorUnauthorized("User not found"))?;
+
+    let Some(password_hash) = current.password.as_deref() else {
+        let message = if current.is_guest {
+            "Guest accounts have no password, upgrade the account instead"
+        } else {
+            "This account signs in through an identity provider and has no password"
+        };
+        return Err(actix_web::error::ErrorBadRequest(message));
+    };
+
+    let is_valid = verify(&body.current_password, password_hash)
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Password verification failed"))?;
+
+    if !is_valid {
+        return Err(actix_web::error::ErrorUnauthorized(
+            "Current password is incorrect",
+        ));
+    }
+
+    let hash_pass = hash_password(body.new_password.trim(), &config)?;
+
+    // Bumping the token version revokes every token issued so far, so the
+    // caller gets a fresh one to keep this session alive.
+    let updated = auth::update_password(&pool.0, current.id, &hash_pass)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    close_sockets(&manager, updated.id).await?;
+
+    let token = generate_jwt(&updated, &config.auth).map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Token generation failed: {}", e))
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "user_id": updated.id,
+        "username": updated.username
+    })))
+}
+
+async fn forgot_password(
+    pool: web::Data<DbPool>,
+    notifier: web::Data<dyn Notifier>,
+    body: Json<ForgotPasswordRequest>,
+) -> Result<HttpResponse> {
+    // Always answer the same way so usernames can't be probed.
+    let response = HttpResponse::Accepted().json(serde_json::json!({
+        "message": "If the account exists, a reset token has been sent"
+    }));
+
+    let user = match auth::get_user_by_username(&pool.0, body.username.trim()).await {
+        Ok(user) if !user.is_guest => user,
+        _ => return Ok(response),
+    };
+
+    let token = generate_random_token();
+    let expires_at = Utc::now() + Duration::minutes(RESET_TOKEN_TTL_MINUTES);
+
+    auth::create_password_reset_token(&pool.0, user.id, &hash_reset_token(&token), expires_at)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    notifier
+        .send_password_reset(&user, &token, expires_at)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Notification failed: {}", e))
+        })?;
+
+    Ok(response)
+}
+
+async fn reset_password(
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    manager: web::Data<Arc<WsManager>>,
+    body: Json<ResetPasswordRequest>,
+) -> Result<HttpResponse> {
+    // Rejected passwords must not burn the token.
+    let hash_pass = hash_password(body.new_password.trim(), &config)?;
+
+    let user = auth::reset_password(&pool.0, &hash_reset_token(body.token.trim()), &hash_pass)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("Database error: {}", e)))?
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Invalid or expired reset token"))?;
+
+    close_sockets(&manager, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": user.id,
+        "username": user.username
+    })))
+}
+
+/// Sockets outlive the token they were opened with, so revoking tokens
+/// alone would leave a stolen session playing.
+async fn close_sockets(manager: &WsManager, user_id: Uuid) -> Result<()> {
+    manager
+        .disconnect_user(user_id, "Password changed, sign in again")
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!(
+                "Password changed but sockets were not closed: {}",
+                e
+            ))
+        })
+}
+
+async fn oidc_login(
+    req: HttpRequest,
+    providers: web::Data<OidcProviders>,
+    config: web::Data<Config>,
+    provider: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (authorize_url, cookie) = oidc_authorize_url(&req, &providers, &provider, None, &config)?;
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, authorize_url))
+        .cookie(cookie)
+        .finish())
+}
+
+async fn oidc_link(
+    req: HttpRequest,
+    providers: web::Data<OidcProviders>,
+    config: web::Data<Config>,
+    provider: web::Path<String>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    if user.is_guest {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Upgrade the guest account before linking identities",
+        ));
+    }
+
+    let (authorize_url, cookie) =
+        oidc_authorize_url(&req, &providers, &provider, Some(user.user_id), &config)?;
+
+    Ok(HttpResponse::Ok().cookie(cookie).json(serde_json::json!({
+        "authorize_url": authorize_url
+    })))
+}
+
+async fn oidc_callback(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Config>,
+    providers: web::Data<OidcProviders>,
+    metrics: web::Data<ApiMetrics>,
+    provider_name: web::Path<String>,
+    query: web::Query<OidcCallbackQuery>,
+) -> Result<HttpResponse> {
+    let browser_secret = req.cookie(OIDC_COOKIE).map(|c| c.value().to_string());
+    let response = oidc_sign_in(
+        &pool,
+        &config,
+        &providers,
+        &provider_name,
+        &query,
+        browser_secret.as_deref(),
+    )
+    .await;
+    metrics.record_auth("oidc", response.is_ok());
+
+    let mut response = response?;
+    let mut removal = Cookie::build(OIDC_COOKIE, "").path("/auth/oidc").finish();
+    removal.make_removal();
+    response.add_cookie(&removal)?;
+
+    Ok(response)
+}
+
+async fn oidc_sign_in(
+    pool: &DbPool,
+    config: &Config,
+    providers: &OidcProviders,
+    provider_name: &str,
+    query: &OidcCallbackQuery,
+    browser_secret: Option<&str>,
+) -> Result<HttpResponse> {
+    if let Some(error) = &query.error {
+        return Err(actix_web::error::ErrorUnauthorized(format!(
+            "Identity provider returned an error: {}",
+            error
+        )));
+    }
+
+    let state = verify_oidc_state(&query.state, &config.auth)
+        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid or expired state"))?;
+
+    if state.provider != provider_name {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Invalid or expired state",
+        ));
+    }
+    // A state used from another browser than the one that started the flow
+    // is someone else's login being planted on this browser.
+    if !browser_secret.is_some_and(|secret| state.is_bound_to(secret)) {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Login was started in another browser",
+        ));
+    }
+
+    let provider = providers
+        .get(provider_name)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Unknown identity provider"))?;
+
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Missing authorization code"))?;
+
+    let external = provider
+        .exchange_code(code, &state.nonce)
+        .await
+        .map_err(|e| actix_web::error::ErrorUnauthorized(format!("OIDC login failed: {}", e)))?;
+
+    let existing = identity::get_user_by_identity(&pool.0, provider_name, &external.subject)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    let user = match (existing, state.link_user) {
+        (Some(user), None) => user,
+        (Some(user), Some(link_user)) if user.id == link_user => user,
+        (Some(_), Some(_)) => {
+            return Err(actix_web::error::ErrorConflict(
+                "Identity is already linked to another account",
+            ));
+        }
+        (None, Some(link_user)) => {
+            identity::link_identity(
+                &pool.0,
+                link_user,
+                provider_name,
+                &external.subject,
+                external.email.as_deref(),
+            )
+            .await
+            .map_err(|e| {
+                actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+            })?;
+
+            auth::get_user_by_id(&pool.0, link_user)
+                .await
+                .map_err(|_| actix_web::error::ErrorUnauthorized("User not found"))?
+        }
+        (None, None) => create_external_user(pool, provider_name, &external).await?,
+    };
+
+    ensure_not_banned(pool, &user).await?;
+
+    let token = generate_jwt(&user, &config.auth).map_err(|e| {
         actix_web::error::ErrorInternalServerError(format!("Token generation failed: {}", e))
     })?;
 
@@ -76,3 +558,155 @@ async fn login(pool: web::Data<DbPool>, body: Json<LoginRequest>) -> Result<Http
         "username": user.username
     })))
 }
+
+async fn list_identities(
+    pool: web::Data<DbPool>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    let identities = identity::list_identities(&pool.0, user.user_id)
+        .await
+        .map_err(|e| {
+            actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+        })?;
+
+    Ok(HttpResponse::Ok().json(identities))
+}
+
+/// Where to send the browser to log in, and the cookie binding the flow to
+/// that browser.
+fn oidc_authorize_url(
+    req: &HttpRequest,
+    providers: &OidcProviders,
+    provider_name: &str,
+    link_user: Option<Uuid>,
+    config: &Config,
+) -> Result<(String, Cookie<'static>)> {
+    let provider = providers
+        .get(provider_name)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Unknown identity provider"))?;
+
+    let nonce = generate_random_token();
+    let browser_secret = generate_random_token();
+    let state = generate_oidc_state(
+        provider_name,
+        &nonce,
+        link_user,
+        &browser_secret,
+        &config.auth,
+    )
+    .map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("State generation failed: {}", e))
+    })?;
+
+    // Lax, so the cookie comes along on the provider's redirect back.
+    let cookie = Cookie::build(OIDC_COOKIE, browser_secret)
+        .path("/auth/oidc")
+        .http_only(true)
+        .secure(req.connection_info().scheme() == "https")
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(OIDC_STATE_TTL_MINUTES))
+        .finish();
+
+    Ok((provider.authorize_url(&state, &nonce), cookie))
+}
+
+/// First login through a provider: create a password-less account named after
+/// the external profile, falling back to a suffixed name on collisions.
+async fn create_external_user(
+    pool: &DbPool,
+    provider_name: &str,
+    external: &ExternalIdentity,
+) -> Result<User> {
+    let base = external
+        .preferred_username
+        .as_deref()
+        .or_else(|| external.email.as_deref().and_then(|e| e.split('@').next()))
+        .map(|name| {
+            name.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+                .take(MAX_USERNAME_CHARS)
+                .collect::<String>()
+        })
+        .filter(|name| validate_username(name).is_ok())
+        .unwrap_or_else(|| "user".to_string());
+
+    // The suffix is a dash and six hex digits.
+    let prefix: String = base.chars().take(MAX_USERNAME_CHARS - 7).collect();
+    let candidates = [
+        base.clone(),
+        format!("{}-{}", prefix, &Uuid::new_v4().simple().to_string()[..6]),
+    ];
+
+    for username in &candidates {
+        let username = validate_username(username)?;
+
+        match identity::create_user_with_identity(
+            &pool.0,
+            username,
+            provider_name,
+            &external.subject,
+            external.email.as_deref(),
+        )
+        .await
+        {
+            Ok(user) => return Ok(user),
+            Err(e) if e.to_string().contains("users_username_key") => continue,
+            Err(e) => {
+                return Err(actix_web::error::ErrorInternalServerError(format!(
+                    "Database error: {}",
+                    e
+                )));
+            }
+        }
+    }
+
+    Err(actix_web::error::ErrorConflict(
+        "Could not pick a free username",
+    ))
+}
+
+async fn ensure_not_banned(pool: &DbPool, user: &User) -> Result<()> {
+    let ban = admin::get_active_ban(&pool.0, user.id).await.map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Database error: {}", e))
+    })?;
+
+    match ban {
+        Some(ban) => Err(ban_error(&ban)),
+        None => Ok(()),
+    }
+}
+
+fn validate_username(username: &str) -> Result<&str> {
+    let username = username.trim();
+
+    if username.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Username must not be empty",
+        ));
+    }
+    if username.starts_with(GUEST_PREFIX) {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Usernames starting with 'guest-' are reserved",
+        ));
+    }
+    if username.chars().count() > MAX_USERNAME_CHARS {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Usernames are at most {} characters",
+            MAX_USERNAME_CHARS
+        )));
+    }
+
+    Ok(username)
+}
+
+fn hash_password(password: &str, config: &Config) -> Result<String> {
+    if password.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Password must not be empty",
+        ));
+    }
+
+    hash(password, config.auth.bcrypt_cost).map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!("Password hashing failed: {}", e))
+    })
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use actix_web::{HttpResponse, web};
+use ws::manager::WsManager;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/ready", web::get().to(ready));
+}
+
+/// Readiness for load balancers: 503 while draining or while the broadcaster
+/// is reconnecting, since players here would miss updates made on
+/// other instances.
+async fn ready(manager: web::Data<Arc<WsManager>>) -> HttpResponse {
+    let subscriber = if manager.broadcaster.is_connected() {
+        "connected"
+    } else {
+        "disconnected"
+    };
+    let body = serde_json::json!({
+        "broadcaster": subscriber,
+        "accepting_connections": manager.is_accepting(),
+    });
+
+    if manager.is_ready() {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
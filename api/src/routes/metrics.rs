@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use db::pool::DbPool;
+use sha2::{Digest, Sha256};
+use ws::manager::WsManager;
+
+use crate::{config::Config, metrics::ApiMetrics};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+}
+
+/// Every metric of this instance, in the Prometheus text format. Scrapers
+/// authenticate with the static `metrics_token` rather than a session, as
+/// session and user counts are nobody else's business.
+async fn metrics(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    manager: web::Data<Arc<WsManager>>,
+    api_metrics: web::Data<ApiMetrics>,
+    pool: web::Data<DbPool>,
+) -> HttpResponse {
+    let Some(token) = &config.metrics_token else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !has_token(&req, token) {
+        return HttpResponse::Unauthorized().body("Invalid or missing metrics token");
+    }
+
+    api_metrics.observe_pool(&pool);
+
+    match manager.metrics.render(&manager.registry) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to encode metrics");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Compares digests, so the time taken says nothing about the token.
+fn has_token(req: &HttpRequest, token: &str) -> bool {
+    let Some(presented) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    Sha256::digest(presented.as_bytes()) == Sha256::digest(token.as_bytes())
+}
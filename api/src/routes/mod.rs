@@ -1,3 +1,6 @@
+pub mod admin;
 pub mod auth;
 pub mod game;
+pub mod health;
+pub mod metrics;
 pub mod websocket;
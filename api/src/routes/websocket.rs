@@ -2,9 +2,12 @@ use std::sync::Arc;
 
 use actix_web::{HttpRequest, HttpResponse, Result, web};
 use actix_web_lab::middleware::from_fn;
-use ws::{handler, manager::WsManager};
+use ws::{
+    handler::{self, SessionUser},
+    manager::WsManager,
+};
 
-use crate::middleware::{extract_user_from_request, jwt_auth_fn};
+use crate::middleware::{AuthenticatedUser, jwt_auth_fn};
 
 pub fn config(cfg: &mut web::ServiceConfig, ws_manager: web::Data<Arc<WsManager>>) {
     cfg.service(
@@ -20,12 +23,15 @@ async fn websocket_handler(
     stream: web::Payload,
     game_id: web::Path<String>,
     manager: web::Data<Arc<WsManager>>,
+    user: web::ReqData<AuthenticatedUser>,
 ) -> Result<HttpResponse> {
-    let user_id = extract_user_from_request(&req).map_err(|e| {
-        actix_web::error::ErrorUnauthorized(format!("Authentication failed: {}", e))
-    })?;
+    let user = user.into_inner();
+    let user = SessionUser {
+        user_id: user.user_id,
+        username: user.username,
+        is_guest: user.is_guest,
+    };
 
-    handler::upgrade(req, stream, game_id, manager, user_id)
-        .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(format!("WebSocket error: {}", e)))
+    // Rejections keep their status: 503 while draining, 400 for a bad encoding.
+    handler::upgrade(req, stream, game_id, manager, user).await
 }
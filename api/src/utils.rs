@@ -1,39 +1,125 @@
 use chrono::{Duration, Utc};
+use db::models::user::{Role, User};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use crate::config::AuthConfig;
+
+/// How long a login started at an identity provider may take.
+pub const OIDC_STATE_TTL_MINUTES: i64 = 10;
+
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
     pub exp: usize,
+    #[serde(default)]
+    pub ver: i32,
+    #[serde(default)]
+    pub guest: bool,
+    #[serde(default)]
+    pub role: Role,
 }
 
-pub fn generate_jwt(user_id: Uuid) -> Result<String, jsonwebtoken::errors::Error> {
-    let jwt_secret = std::env::var("JWT_SECRET").unwrap_or("secret".to_string());
-    let expiration = Utc::now() + Duration::hours(24);
+pub fn generate_jwt(user: &User, auth: &AuthConfig) -> Result<String, jsonwebtoken::errors::Error> {
+    let expiration = Utc::now() + Duration::hours(auth.token_lifetime_hours);
 
     let claims = Claims {
-        sub: user_id,
+        sub: user.id,
         exp: expiration.timestamp() as usize,
+        ver: user.token_version,
+        guest: user.is_guest,
+        role: user.role,
     };
 
     let token = encode(
         &Header::default(),
         &claims,
-        &EncodingKey::from_secret(jwt_secret.as_bytes()),
+        &EncodingKey::from_secret(auth.jwt_secret.as_bytes()),
     )?;
 
     Ok(token)
 }
 
-pub fn verify_jwt(token: &str) -> Result<Uuid, jsonwebtoken::errors::Error> {
-    let jwt_secret = std::env::var("JWT_SECRET").unwrap_or("secret".to_string());
+pub fn verify_jwt(token: &str, auth: &AuthConfig) -> Result<Claims, jsonwebtoken::errors::Error> {
     let decoded = decode::<Claims>(
         token,
-        &DecodingKey::from_secret(jwt_secret.as_bytes()),
+        &DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
+        &Validation::default(),
+    )?;
+
+    Ok(decoded.claims)
+}
+
+/// Returns a random hex token, used for reset tokens and OIDC nonces.
+pub fn generate_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Reset tokens are only ever stored as their SHA-256 digest.
+pub fn hash_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Round-tripped through the identity provider as the OAuth2 `state`
+/// parameter, so the login flow needs no server-side storage.
+#[derive(Serialize, Deserialize)]
+pub struct OidcState {
+    pub provider: String,
+    pub nonce: String,
+    /// Set when an already logged in user is linking another identity.
+    pub link_user: Option<Uuid>,
+    /// SHA-256 of a secret kept in a cookie of the browser that started the
+    /// flow, so a state can't be replayed from another browser.
+    pub browser: String,
+    pub exp: usize,
+}
+
+impl OidcState {
+    /// Whether `secret`, from the callback's cookie, is the one this state
+    /// was issued for.
+    pub fn is_bound_to(&self, secret: &str) -> bool {
+        hex::encode(Sha256::digest(secret.as_bytes())) == self.browser
+    }
+}
+
+pub fn generate_oidc_state(
+    provider: &str,
+    nonce: &str,
+    link_user: Option<Uuid>,
+    browser_secret: &str,
+    auth: &AuthConfig,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let expiration = Utc::now() + Duration::minutes(OIDC_STATE_TTL_MINUTES);
+
+    let state = OidcState {
+        provider: provider.to_string(),
+        nonce: nonce.to_string(),
+        link_user,
+        browser: hex::encode(Sha256::digest(browser_secret.as_bytes())),
+        exp: expiration.timestamp() as usize,
+    };
+
+    encode(
+        &Header::default(),
+        &state,
+        &EncodingKey::from_secret(auth.jwt_secret.as_bytes()),
+    )
+}
+
+pub fn verify_oidc_state(
+    token: &str,
+    auth: &AuthConfig,
+) -> Result<OidcState, jsonwebtoken::errors::Error> {
+    let decoded = decode::<OidcState>(
+        token,
+        &DecodingKey::from_secret(auth.jwt_secret.as_bytes()),
         &Validation::default(),
     )?;
 
-    Ok(decoded.claims.sub)
+    Ok(decoded.claims)
 }
@@ -0,0 +1,312 @@
+//! Needs Postgres: `DATABASE_URL=postgres://... cargo test -p tic-tac -- --ignored`.
+//! Every test runs against a fresh database with the migrations applied.
+
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{
+    App,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test, web,
+};
+use chrono::{Duration, Utc};
+use common::RecordingNotifier;
+use db::{pool::DbPool, queries::auth};
+use prometheus::Registry;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tic_tac::{metrics::ApiMetrics, notifier::Notifier, routes, utils::hash_reset_token};
+use uuid::Uuid;
+use ws::{
+    broadcast::InProcessBroadcaster,
+    config::WsConfig,
+    game::{chat::MemoryChatStore, store::MemoryGameStore},
+    manager::{self, WsManager},
+    session_queue::{Outbound, OverflowPolicy, SessionQueue},
+};
+
+async fn app(
+    pool: PgPool,
+    notifier: Arc<RecordingNotifier>,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    app_with_sockets(pool, notifier, ws_manager().await).await
+}
+
+/// Like `app`, with the sockets of `manager`.
+async fn app_with_sockets(
+    pool: PgPool,
+    notifier: Arc<RecordingNotifier>,
+    manager: Arc<WsManager>,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let notifier: Arc<dyn Notifier> = notifier;
+
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(common::config()))
+            .app_data(web::Data::new(DbPool(pool)))
+            .app_data(web::Data::from(notifier))
+            .app_data(web::Data::new(ApiMetrics::new(&Registry::new()).unwrap()))
+            .app_data(web::Data::new(manager))
+            .configure(routes::auth::config),
+    )
+    .await
+}
+
+async fn ws_manager() -> Arc<WsManager> {
+    manager::start_manager(
+        WsConfig::default(),
+        Arc::new(MemoryGameStore::new()),
+        Arc::new(MemoryChatStore::new()),
+        InProcessBroadcaster::factory(),
+    )
+    .await
+    .unwrap()
+}
+
+/// A socket of `user_id` in a game.
+fn socket(manager: &WsManager, user_id: Uuid) -> Arc<SessionQueue> {
+    let queue = Arc::new(SessionQueue::new(8, OverflowPolicy::DropOldest));
+    manager.registry.add("g1", "s1", user_id, queue.clone());
+    queue
+}
+
+async fn post(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    uri: &str,
+    token: Option<&str>,
+    body: Value,
+) -> (StatusCode, Value) {
+    let mut req = test::TestRequest::post().uri(uri).set_json(body);
+    if let Some(token) = token {
+        req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+    }
+
+    let res = match test::try_call_service(app, req.to_request()).await {
+        Ok(res) => res.map_into_boxed_body().into_parts().1,
+        Err(e) => e.error_response(),
+    };
+    let status = res.status();
+    let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+async fn register_and_login(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    username: &str,
+    password: &str,
+) -> String {
+    let credentials = json!({ "username": username, "password": password });
+    let (status, _) = post(app, "/auth/register", None, credentials.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = post(app, "/auth/login", None, credentials).await;
+    assert_eq!(status, StatusCode::OK);
+    body["token"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn rejected_password_keeps_the_reset_token(pool: PgPool) {
+    let notifier = Arc::new(RecordingNotifier::default());
+    let app = app(pool, notifier.clone()).await;
+    register_and_login(&app, "alice", "old-password").await;
+
+    post(
+        &app,
+        "/auth/password/forgot",
+        None,
+        json!({ "username": "alice" }),
+    )
+    .await;
+    let token = notifier.last_token();
+
+    let (status, _) = post(
+        &app,
+        "/auth/password/reset",
+        None,
+        json!({ "token": token, "new_password": "  " }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = post(
+        &app,
+        "/auth/password/reset",
+        None,
+        json!({ "token": token, "new_password": "new-password" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn reset_token_is_single_use(pool: PgPool) {
+    let notifier = Arc::new(RecordingNotifier::default());
+    let app = app(pool, notifier.clone()).await;
+    register_and_login(&app, "alice", "old-password").await;
+
+    post(
+        &app,
+        "/auth/password/forgot",
+        None,
+        json!({ "username": "alice" }),
+    )
+    .await;
+    let token = notifier.last_token();
+    let reset = json!({ "token": token, "new_password": "new-password" });
+
+    let (status, _) = post(&app, "/auth/password/reset", None, reset.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = post(&app, "/auth/password/reset", None, reset).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = post(
+        &app,
+        "/auth/login",
+        None,
+        json!({ "username": "alice", "password": "new-password" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn expired_reset_token_is_rejected(pool: PgPool) {
+    let app = app(pool.clone(), Arc::default()).await;
+    register_and_login(&app, "alice", "old-password").await;
+
+    let user = auth::get_user_by_username(&pool, "alice").await.unwrap();
+    auth::create_password_reset_token(
+        &pool,
+        user.id,
+        &hash_reset_token("stale"),
+        Utc::now() - Duration::minutes(1),
+    )
+    .await
+    .unwrap();
+
+    let (status, _) = post(
+        &app,
+        "/auth/password/reset",
+        None,
+        json!({ "token": "stale", "new_password": "new-password" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let unchanged = auth::get_user_by_id(&pool, user.id).await.unwrap();
+    assert_eq!(unchanged.token_version, user.token_version);
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn reset_revokes_existing_sessions(pool: PgPool) {
+    let notifier = Arc::new(RecordingNotifier::default());
+    let manager = ws_manager().await;
+    let app = app_with_sockets(pool.clone(), notifier.clone(), manager.clone()).await;
+    let session = register_and_login(&app, "alice", "old-password").await;
+    let before = auth::get_user_by_username(&pool, "alice").await.unwrap();
+    let socket = socket(&manager, before.id);
+
+    post(
+        &app,
+        "/auth/password/forgot",
+        None,
+        json!({ "username": "alice" }),
+    )
+    .await;
+    let reset = json!({ "token": notifier.last_token(), "new_password": "new-password" });
+    let (status, _) = post(&app, "/auth/password/reset", None, reset).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let after = auth::get_user_by_id(&pool, before.id).await.unwrap();
+    assert_eq!(after.token_version, before.token_version + 1);
+    assert!(matches!(socket.pop().await, Some(Outbound::Close(_))));
+
+    let change = json!({ "current_password": "new-password", "new_password": "newer" });
+    let (status, _) = post(&app, "/auth/password/change", Some(&session), change).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn guest_upgrade_validates_the_username(pool: PgPool) {
+    let app = app(pool, Arc::default()).await;
+    let (status, guest) = post(&app, "/auth/guest", None, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = guest["token"].as_str().unwrap();
+
+    for username in ["guest-taken", "   ", &"x".repeat(33)] {
+        let upgrade = json!({ "username": username, "password": "password" });
+        let (status, _) = post(&app, "/auth/upgrade", Some(token), upgrade).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "username {:?}", username);
+    }
+
+    let upgrade = json!({ "username": "  alice ", "password": "password" });
+    let (status, body) = post(&app, "/auth/upgrade", Some(token), upgrade).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "alice");
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn guests_past_their_token_lifetime_are_deleted(pool: PgPool) {
+    let app = app(pool.clone(), Arc::default()).await;
+    let mut guests = Vec::new();
+    for _ in 0..3 {
+        let (status, guest) = post(&app, "/auth/guest", None, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        guests.push(guest);
+    }
+    let upgrade = json!({ "username": "alice", "password": "password" });
+    let token = guests[1]["token"].as_str().unwrap();
+    let (status, _) = post(&app, "/auth/upgrade", Some(token), upgrade).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let [stale, upgraded, fresh] = [0, 1, 2].map(|i| {
+        guests[i]["user_id"]
+            .as_str()
+            .unwrap()
+            .parse::<Uuid>()
+            .unwrap()
+    });
+    sqlx::query("UPDATE users SET created_at = NOW() - INTERVAL '25 hours' WHERE id = ANY($1)")
+        .bind(vec![stale, upgraded])
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (status, _) = post(&app, "/auth/guest", None, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(auth::get_user_by_id(&pool, stale).await.is_err());
+    assert!(auth::get_user_by_id(&pool, upgraded).await.is_ok());
+    assert!(auth::get_user_by_id(&pool, fresh).await.is_ok());
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn password_change_closes_open_sockets(pool: PgPool) {
+    let manager = ws_manager().await;
+    let app = app_with_sockets(pool.clone(), Arc::default(), manager.clone()).await;
+    let session = register_and_login(&app, "alice", "old-password").await;
+    let alice = auth::get_user_by_username(&pool, "alice").await.unwrap();
+    let socket = socket(&manager, alice.id);
+
+    let change = json!({ "current_password": "old-password", "new_password": "new-password" });
+    let (status, _) = post(&app, "/auth/password/change", Some(&session), change).await;
+    assert_eq!(status, StatusCode::OK);
+
+    match socket.pop().await {
+        Some(Outbound::Close(reason)) => assert_eq!(
+            reason.description.as_deref(),
+            Some("Password changed, sign in again")
+        ),
+        _ => panic!("expected the socket to be closed"),
+    }
+}
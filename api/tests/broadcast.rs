@@ -0,0 +1,124 @@
+//! Needs Postgres: `DATABASE_URL=postgres://... cargo test -p tic-tac -- --ignored`.
+
+use std::{sync::Arc, time::Duration};
+
+use db::pool::DbPool;
+use sqlx::PgPool;
+use tic_tac::broadcast::PgBroadcaster;
+use tokio::{
+    sync::{mpsc, watch},
+    time::timeout,
+};
+use uuid::Uuid;
+use ws::{
+    broadcast::{Broadcaster, ControlMessage},
+    connection_registry::ConnectionRegistry,
+    game::{game_manager::GameManager, store::MemoryGameStore},
+    keys::Keys,
+    metrics::WsMetrics,
+    session_queue::{Outbound, OverflowPolicy, SessionQueue},
+};
+
+/// One instance: its broadcaster, running until `shutdown` is sent, and
+/// the registry and games behind it.
+struct Instance {
+    broadcaster: Arc<PgBroadcaster>,
+    registry: Arc<ConnectionRegistry>,
+    games: Arc<GameManager>,
+    shutdown: watch::Sender<bool>,
+}
+
+fn instance(pool: &PgPool) -> Instance {
+    let (game_events, events) = mpsc::unbounded_channel();
+    let registry = Arc::new(ConnectionRegistry::with_game_events(game_events));
+    let games = Arc::new(GameManager::new(Arc::new(MemoryGameStore::new())));
+    let broadcaster = Arc::new(PgBroadcaster::new(
+        DbPool(pool.clone()),
+        registry.clone(),
+        games.clone(),
+        Arc::new(WsMetrics::new("ws-test").unwrap()),
+        Keys::new("test"),
+    ));
+    let (shutdown, stop) = watch::channel(false);
+
+    tokio::spawn({
+        let broadcaster = broadcaster.clone();
+        async move { broadcaster.run(stop, events).await }
+    });
+
+    Instance {
+        broadcaster,
+        registry,
+        games,
+        shutdown,
+    }
+}
+
+async fn next(queue: &SessionQueue) -> Outbound {
+    timeout(Duration::from_secs(5), queue.pop())
+        .await
+        .expect("nothing was delivered")
+        .unwrap()
+}
+
+/// Seats a session of `user` in `game_id` on `instance` and waits until the
+/// instance follows the game, which it shows by re-syncing the game.
+async fn join(instance: &Instance, game_id: &str, user: Uuid) -> Arc<SessionQueue> {
+    while !instance.broadcaster.is_connected() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    instance
+        .games
+        .create_game(user, "alice".into(), "s1".into(), game_id.into(), false)
+        .await
+        .unwrap();
+    let queue = Arc::new(SessionQueue::new(8, OverflowPolicy::DropOldest));
+    instance.registry.add(game_id, "s1", user, queue.clone());
+
+    assert!(matches!(next(&queue).await, Outbound::State(_)));
+    queue
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn game_messages_reach_sessions_on_other_instances(pool: PgPool) {
+    let (a, b) = (instance(&pool), instance(&pool));
+    let session = join(&b, "g1", Uuid::new_v4()).await;
+
+    a.broadcaster
+        .broadcast("g1", &Outbound::Text("move".into()), "a-session")
+        .await
+        .unwrap();
+
+    match next(&session).await {
+        Outbound::Text(text) => assert_eq!(&*text, "move"),
+        _ => panic!("expected the broadcast"),
+    }
+    assert!(a.broadcaster.is_connected() && b.broadcaster.is_connected());
+
+    a.shutdown.send(true).unwrap();
+    b.shutdown.send(true).unwrap();
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn control_messages_reach_other_instances(pool: PgPool) {
+    let (a, b) = (instance(&pool), instance(&pool));
+    let user = Uuid::new_v4();
+    let session = join(&b, "g1", user).await;
+
+    let control = ControlMessage::DisconnectUser {
+        user_id: user,
+        reason: "Banned".to_string(),
+    };
+    a.broadcaster.control(&control).await.unwrap();
+
+    match next(&session).await {
+        Outbound::Close(reason) => assert_eq!(reason.description.as_deref(), Some("Banned")),
+        _ => panic!("expected a close frame"),
+    }
+    assert!(b.registry.is_empty());
+
+    a.shutdown.send(true).unwrap();
+    b.shutdown.send(true).unwrap();
+}
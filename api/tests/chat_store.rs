@@ -0,0 +1,66 @@
+//! Needs Postgres: `DATABASE_URL=postgres://... cargo test -p tic-tac -- --ignored`.
+
+use std::time::Duration;
+
+use db::pool::DbPool;
+use sqlx::PgPool;
+use tic_tac::{chat_store::PgChatStore, game_store::PgGameStore};
+use uuid::Uuid;
+use ws::game::{chat::ChatStore, game_state::GameState, store::GameStore};
+
+async fn stores(pool: PgPool) -> (PgChatStore, PgGameStore) {
+    let games = PgGameStore::new(DbPool(pool.clone()), Duration::from_secs(3600));
+    let game = GameState::new(
+        "g1".to_string(),
+        Uuid::new_v4(),
+        "alice".to_string(),
+        "s1".to_string(),
+        false,
+    );
+    games.save(&game, &[]).await.unwrap();
+
+    (PgChatStore::new(DbPool(pool)), games)
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn history_keeps_the_newest_records(pool: PgPool) {
+    let (chat, _) = stores(pool).await;
+
+    for record in ["one", "two", "three"] {
+        chat.append("g1", record, 2).await.unwrap();
+    }
+
+    assert_eq!(chat.history("g1").await.unwrap(), ["two", "three"]);
+    assert!(chat.history("g2").await.unwrap().is_empty());
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn mutes_belong_to_the_muter(pool: PgPool) {
+    let (chat, _) = stores(pool).await;
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    chat.set_muted("g1", alice, bob, true).await.unwrap();
+    chat.set_muted("g1", alice, bob, true).await.unwrap();
+    assert_eq!(chat.muted("g1", alice).await.unwrap().len(), 1);
+    assert!(chat.muted("g1", alice).await.unwrap().contains(&bob));
+    assert!(chat.muted("g1", bob).await.unwrap().is_empty());
+
+    chat.set_muted("g1", alice, bob, false).await.unwrap();
+    assert!(chat.muted("g1", alice).await.unwrap().is_empty());
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn chat_goes_away_with_its_game(pool: PgPool) {
+    let (chat, games) = stores(pool).await;
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    chat.append("g1", "hello", 10).await.unwrap();
+    chat.set_muted("g1", alice, bob, true).await.unwrap();
+
+    games.delete("g1").await.unwrap();
+
+    assert!(chat.history("g1").await.unwrap().is_empty());
+    assert!(chat.muted("g1", alice).await.unwrap().is_empty());
+}
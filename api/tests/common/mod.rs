@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use db::models::user::User;
+use tic_tac::{config::Config, notifier::Notifier};
+
+/// Settings for tests: the cheapest bcrypt cost, everything else default.
+pub fn config() -> Config {
+    let mut config = Config::default();
+    config.auth.bcrypt_cost = 4;
+    config
+}
+
+/// Keeps the reset tokens it is asked to send.
+#[derive(Default)]
+pub struct RecordingNotifier {
+    pub tokens: Mutex<Vec<String>>,
+}
+
+impl RecordingNotifier {
+    pub fn last_token(&self) -> String {
+        self.tokens.lock().unwrap().last().cloned().unwrap()
+    }
+}
+
+#[async_trait]
+impl Notifier for RecordingNotifier {
+    async fn send_password_reset(
+        &self,
+        _user: &User,
+        token: &str,
+        _expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        self.tokens.lock().unwrap().push(token.to_string());
+        Ok(())
+    }
+}
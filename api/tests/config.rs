@@ -0,0 +1,65 @@
+use tic_tac::config::Config;
+
+fn config(toml: &str) -> Config {
+    let mut config = Config::from_toml(toml).unwrap();
+    config.database.url = "postgres://localhost/tic_tac".to_string();
+    config
+}
+
+#[test]
+fn default_jwt_secret_is_refused_on_a_public_host() {
+    let mut public = config("server.host = \"0.0.0.0\"");
+
+    let error = public.validate().unwrap_err();
+    assert!(error.to_string().starts_with("JWT_SECRET must be set"));
+
+    let mut with_secret = config("server.host = \"0.0.0.0\"\nauth.jwt_secret = \"s3cr3t\"");
+    with_secret.validate().unwrap();
+}
+
+#[test]
+fn default_jwt_secret_is_allowed_on_loopback() {
+    for host in ["127.0.0.1", "localhost", "::1"] {
+        let mut local = config(&format!("server.host = \"{}\"", host));
+        local.validate().unwrap();
+    }
+}
+
+#[test]
+fn ws_settings_are_validated_with_the_rest() {
+    let mut config = config("ws.heartbeat_interval_secs = 0");
+
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn oidc_providers_are_read_from_the_file() {
+    let mut config = config(
+        "[oidc.mock]\n\
+         issuer = \"http://localhost:8090/default\"\n\
+         client_id = \"tic-tac\"\n\
+         client_secret = \"secret\"\n\
+         redirect_uri = \"http://127.0.0.1:8080/auth/oidc/mock/callback\"",
+    );
+    config.validate().unwrap();
+
+    let mock = &config.oidc["mock"];
+    assert_eq!(mock.client_id, "tic-tac");
+    assert_eq!(mock.scopes, "openid email profile");
+}
+
+#[test]
+fn oidc_providers_need_an_issuer() {
+    let mut config = config("[oidc.mock]\nclient_id = \"tic-tac\"");
+
+    let error = config.validate().unwrap_err();
+    assert_eq!(error.to_string(), "OIDC_MOCK_ISSUER must be set");
+}
+
+#[test]
+fn metrics_token_must_not_be_empty() {
+    let mut empty = config("metrics_token = \"\"");
+
+    let error = empty.validate().unwrap_err();
+    assert_eq!(error.to_string(), "METRICS_TOKEN must not be empty");
+}
@@ -0,0 +1,139 @@
+//! Needs Postgres: `DATABASE_URL=postgres://... cargo test -p tic-tac -- --ignored`.
+
+use std::{sync::Arc, time::Duration};
+
+use db::pool::DbPool;
+use sqlx::PgPool;
+use tic_tac::{chat_store::PgChatStore, game_store::PgGameStore};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+use ws::game::{chat::ChatStore, events::GameEvent, game_state::GameState, store::GameStore};
+
+const TTL: Duration = Duration::from_secs(3600);
+
+fn game(id: &str) -> GameState {
+    GameState::new(
+        id.to_string(),
+        Uuid::new_v4(),
+        "alice".to_string(),
+        "s1".to_string(),
+        false,
+    )
+}
+
+fn created() -> GameEvent {
+    GameEvent::Created {
+        player: "alice".to_string(),
+        rated: false,
+    }
+}
+
+async fn backdate(pool: &PgPool, game_id: &str, by: Duration) {
+    sqlx::query(
+        "UPDATE game_states SET updated_at = NOW() - make_interval(secs => $2) WHERE id = $1",
+    )
+    .bind(game_id)
+    .bind(by.as_secs_f64())
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn saved_games_come_back_with_their_events(pool: PgPool) {
+    let store = PgGameStore::new(DbPool(pool), TTL);
+
+    store.save(&game("g1"), &[created()]).await.unwrap();
+
+    assert_eq!(store.get("g1").await.unwrap().unwrap().id, "g1");
+    assert_eq!(store.list().await.unwrap().len(), 1);
+    let events = store.events("g1", None).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert!(
+        store
+            .events("g1", Some(&events[0].id))
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    store.delete("g1").await.unwrap();
+    assert!(store.get("g1").await.unwrap().is_none());
+    assert!(store.events("g1", None).await.unwrap().is_empty());
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn games_past_the_ttl_are_gone_and_cleaned_up_on_create(pool: PgPool) {
+    let store = PgGameStore::new(DbPool(pool.clone()), TTL);
+    store.save(&game("old"), &[created()]).await.unwrap();
+    backdate(&pool, "old", TTL + Duration::from_secs(1)).await;
+
+    assert!(store.get("old").await.unwrap().is_none());
+    let mut noop = |_: &mut GameState| Ok(Vec::new());
+    assert!(store.update("old", &mut noop).await.is_err());
+    assert!(store.list().await.unwrap().is_empty());
+
+    store.save(&game("live"), &[created()]).await.unwrap();
+
+    let (games, events): (i64, i64) = sqlx::query_as(
+        "SELECT (SELECT COUNT(*) FROM game_states), (SELECT COUNT(*) FROM game_events)",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!((games, events), (1, 1));
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn a_game_created_on_a_used_id_drops_the_old_events_and_chat(pool: PgPool) {
+    let store = PgGameStore::new(DbPool(pool.clone()), TTL);
+    let chat = PgChatStore::new(DbPool(pool));
+    store.save(&game("g1"), &[created()]).await.unwrap();
+    let mut join = |_: &mut GameState| {
+        Ok(vec![GameEvent::Joined {
+            player: "bob".to_string(),
+        }])
+    };
+    store.update("g1", &mut join).await.unwrap();
+    chat.append("g1", "gl hf", 10).await.unwrap();
+    chat.set_muted("g1", Uuid::new_v4(), Uuid::new_v4(), true)
+        .await
+        .unwrap();
+
+    store.save(&game("g1"), &[created()]).await.unwrap();
+
+    let events = store.events("g1", None).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event, created());
+    assert!(chat.history("g1").await.unwrap().is_empty());
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn concurrent_updates_of_a_game_are_serialized(pool: PgPool) {
+    let store = Arc::new(PgGameStore::new(DbPool(pool), TTL));
+    store.save(&game("g1"), &[created()]).await.unwrap();
+
+    let mut updates = JoinSet::new();
+    for n in 0..8 {
+        let store = store.clone();
+        updates.spawn(async move {
+            let mut change = |game: &mut GameState| {
+                game.seq += 1;
+                Ok(vec![GameEvent::Joined {
+                    player: format!("player-{}", n),
+                }])
+            };
+            store.update("g1", &mut change).await
+        });
+    }
+    while let Some(result) = updates.join_next().await {
+        result.unwrap().unwrap();
+    }
+
+    assert_eq!(store.get("g1").await.unwrap().unwrap().seq, 8);
+    assert_eq!(store.events("g1", None).await.unwrap().len(), 9);
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{
+    App,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test, web,
+};
+use db::pool::DbPool;
+use prometheus::{Registry, TextEncoder};
+use serde_json::json;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use tic_tac::{metrics::ApiMetrics, routes};
+use ws::{
+    broadcast::InProcessBroadcaster,
+    config::WsConfig,
+    game::{chat::MemoryChatStore, store::MemoryGameStore},
+    manager::{self, WsManager},
+};
+
+async fn app(
+    pool: PgPool,
+    manager: Arc<WsManager>,
+    metrics_token: Option<&str>,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let metrics = ApiMetrics::new(manager.metrics.registry()).unwrap();
+    let mut config = common::config();
+    config.metrics_token = metrics_token.map(str::to_string);
+
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(config))
+            .app_data(web::Data::new(DbPool(pool)))
+            .app_data(web::Data::new(metrics))
+            .app_data(web::Data::new(manager))
+            .configure(routes::auth::config)
+            .configure(routes::metrics::config),
+    )
+    .await
+}
+
+async fn ws_manager() -> Arc<WsManager> {
+    manager::start_manager(
+        WsConfig::default(),
+        Arc::new(MemoryGameStore::new()),
+        Arc::new(MemoryChatStore::new()),
+        InProcessBroadcaster::factory(),
+    )
+    .await
+    .unwrap()
+}
+
+/// A pool that never connects, for requests rejected before any query.
+fn unused_pool() -> PgPool {
+    PgPoolOptions::new()
+        .connect_lazy("postgres://unused@127.0.0.1:1/unused")
+        .unwrap()
+}
+
+async fn call(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    req: test::TestRequest,
+) -> (StatusCode, String) {
+    let res = match test::try_call_service(app, req.to_request()).await {
+        Ok(res) => res.map_into_boxed_body().into_parts().1,
+        Err(e) => e.error_response(),
+    };
+    let status = res.status();
+    let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+fn scrape(registry: &Registry) -> String {
+    TextEncoder::new()
+        .encode_to_string(&registry.gather())
+        .unwrap()
+}
+
+/// Value of the sample whose name and leading labels are `series`.
+fn sample<'a>(text: &'a str, series: &str) -> Option<&'a str> {
+    text.lines()
+        .find(|line| line.starts_with(series))
+        .and_then(|line| line.rsplit(' ').next())
+}
+
+fn get_metrics(token: Option<&str>) -> test::TestRequest {
+    let req = test::TestRequest::get().uri("/metrics");
+    match token {
+        Some(token) => req.insert_header(("Authorization", format!("Bearer {}", token))),
+        None => req,
+    }
+}
+
+#[actix_web::test]
+async fn metrics_need_the_scrape_token() {
+    let app = app(unused_pool(), ws_manager().await, Some("scrape")).await;
+
+    let (status, _) = call(&app, get_metrics(None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&app, get_metrics(Some("guess"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = call(&app, get_metrics(Some("scrape"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(sample(&body, "ws_connections{").is_some());
+    assert!(sample(&body, "db_pool_max_connections{").is_some());
+}
+
+#[actix_web::test]
+async fn metrics_are_not_served_without_a_scrape_token() {
+    let app = app(unused_pool(), ws_manager().await, None).await;
+
+    let (status, _) = call(&app, get_metrics(Some("scrape"))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn rejected_session_tokens_are_counted() {
+    let manager = ws_manager().await;
+    let app = app(unused_pool(), manager.clone(), None).await;
+
+    let identities = || test::TestRequest::get().uri("/auth/identities");
+    let (status, _) = call(&app, identities()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let forged = identities().insert_header(("Authorization", "Bearer forged"));
+    let (status, _) = call(&app, forged).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let text = scrape(manager.metrics.registry());
+    let failures = "token_verification_failures_total";
+    assert_eq!(
+        sample(&text, &format!(r#"{failures}{{reason="missing""#)),
+        Some("1")
+    );
+    assert_eq!(
+        sample(&text, &format!(r#"{failures}{{reason="invalid""#)),
+        Some("1")
+    );
+}
+
+#[actix_web::test]
+async fn failed_registrations_are_counted() {
+    let manager = ws_manager().await;
+    let app = app(unused_pool(), manager.clone(), None).await;
+
+    let body = json!({ "username": "", "password": "password123" });
+    let req = test::TestRequest::post()
+        .uri("/auth/register")
+        .set_json(body);
+    let (status, _) = call(&app, req).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let text = scrape(manager.metrics.registry());
+    let series = r#"auth_attempts_total{method="register",outcome="failure""#;
+    assert_eq!(sample(&text, series), Some("1"));
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn successful_registrations_are_counted(pool: PgPool) {
+    let manager = ws_manager().await;
+    let app = app(pool, manager.clone(), None).await;
+
+    for username in ["alice", "bob"] {
+        let credentials = json!({ "username": username, "password": "password123" });
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(credentials);
+        let (status, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let text = scrape(manager.metrics.registry());
+    let series = r#"auth_attempts_total{method="register",outcome="success""#;
+    assert_eq!(sample(&text, series), Some("2"));
+}
@@ -0,0 +1,77 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use db::models::user::{Role, User};
+use tic_tac::notifier::{FileNotifier, LogNotifier, Notifier};
+use uuid::Uuid;
+
+const TOKEN: &str = "reset-token-0123456789";
+
+fn user() -> User {
+    User {
+        id: Uuid::new_v4(),
+        username: "alice".to_string(),
+        password: None,
+        token_version: 0,
+        is_guest: false,
+        role: Role::Player,
+        created_at: Utc::now(),
+    }
+}
+
+/// Log output collected in memory.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn log_notifier_keeps_the_token_out_of_the_logs() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let user = user();
+
+    LogNotifier
+        .send_password_reset(&user, TOKEN, Utc::now())
+        .await
+        .unwrap();
+
+    let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains(&user.id.to_string()));
+    assert!(!logs.contains(TOKEN));
+    assert!(!logs.contains("alice"));
+}
+
+#[tokio::test]
+async fn file_notifier_delivers_the_token() {
+    let path = std::env::temp_dir().join(format!("notifier-{}.jsonl", Uuid::new_v4()));
+    let notifier = FileNotifier::new(&path);
+
+    notifier
+        .send_password_reset(&user(), TOKEN, Utc::now())
+        .await
+        .unwrap();
+
+    let line: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(line["kind"], "password_reset");
+    assert_eq!(line["token"], TOKEN);
+}
@@ -0,0 +1,372 @@
+//! Logins against a mock OpenID Connect provider served on a local port.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{
+    App, HttpResponse, HttpServer,
+    cookie::Cookie,
+    dev::{Service, ServiceResponse},
+    http::{StatusCode, header},
+    test, web,
+};
+use chrono::Utc;
+use db::pool::DbPool;
+use jsonwebtoken::{
+    Algorithm, EncodingKey, Header, encode,
+    jwk::{Jwk, JwkSet},
+};
+use prometheus::Registry;
+use reqwest::Url;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use tic_tac::{
+    config::Config,
+    metrics::ApiMetrics,
+    oidc::{IdentityProvider, OidcProvider, OidcProviders, OidcSettings},
+    routes,
+    utils::OidcState,
+};
+use ws::{
+    broadcast::InProcessBroadcaster,
+    config::WsConfig,
+    game::{chat::MemoryChatStore, store::MemoryGameStore},
+    manager,
+};
+
+const CLIENT_ID: &str = "tic-tac";
+const KID: &str = "mock-key";
+const PROVIDER_KEY: &[u8] = include_bytes!("fixtures/oidc_provider_key.pem");
+const ATTACKER_KEY: &[u8] = include_bytes!("fixtures/oidc_attacker_key.pem");
+
+/// An identity provider that signs id_tokens for whatever nonce it gets as
+/// the authorization code, so tests need no login page.
+struct MockProvider {
+    issuer: String,
+    /// Key the token endpoint signs with, the published one unless a test
+    /// swaps it.
+    signing_key: Mutex<&'static [u8]>,
+    /// Key id put in id_token headers.
+    kid: Mutex<&'static str>,
+    /// `preferred_username` claim of issued id_tokens.
+    username: Mutex<&'static str>,
+}
+
+async fn discovery(mock: web::Data<MockProvider>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "issuer": mock.issuer,
+        "authorization_endpoint": format!("{}/authorize", mock.issuer),
+        "token_endpoint": format!("{}/token", mock.issuer),
+        "jwks_uri": format!("{}/jwks", mock.issuer),
+    }))
+}
+
+async fn jwks() -> HttpResponse {
+    let key = EncodingKey::from_rsa_pem(PROVIDER_KEY).unwrap();
+    let mut jwk = Jwk::from_encoding_key(&key, Algorithm::RS256).unwrap();
+    jwk.common.key_id = Some(KID.to_string());
+
+    HttpResponse::Ok().json(JwkSet { keys: vec![jwk] })
+}
+
+async fn token(
+    mock: web::Data<MockProvider>,
+    form: web::Form<HashMap<String, String>>,
+) -> HttpResponse {
+    let claims = json!({
+        "iss": mock.issuer,
+        "aud": CLIENT_ID,
+        "sub": "mock-subject",
+        "exp": Utc::now().timestamp() + 300,
+        "nonce": form["code"],
+        "preferred_username": *mock.username.lock().unwrap(),
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(mock.kid.lock().unwrap().to_string());
+    let key = EncodingKey::from_rsa_pem(*mock.signing_key.lock().unwrap()).unwrap();
+
+    HttpResponse::Ok().json(json!({ "id_token": encode(&header, &claims, &key).unwrap() }))
+}
+
+async fn start_mock() -> web::Data<MockProvider> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mock = web::Data::new(MockProvider {
+        issuer: format!("http://{}", listener.local_addr().unwrap()),
+        signing_key: Mutex::new(PROVIDER_KEY),
+        kid: Mutex::new(KID),
+        username: Mutex::new("mocker"),
+    });
+
+    let data = mock.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .route(
+                "/.well-known/openid-configuration",
+                web::get().to(discovery),
+            )
+            .route("/jwks", web::get().to(jwks))
+            .route("/token", web::post().to(token))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    tokio::spawn(server);
+
+    mock
+}
+
+async fn discover(mock: &MockProvider) -> OidcProvider {
+    OidcProvider::discover(OidcSettings {
+        issuer: mock.issuer.clone(),
+        client_id: CLIENT_ID.to_string(),
+        client_secret: "secret".to_string(),
+        redirect_uri: "http://127.0.0.1:8080/auth/oidc/mock/callback".to_string(),
+        scopes: "openid".to_string(),
+    })
+    .await
+    .unwrap()
+}
+
+async fn app(
+    pool: PgPool,
+    provider: OidcProvider,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let providers = OidcProviders(HashMap::from([(
+        "mock".to_string(),
+        Arc::new(provider) as Arc<dyn IdentityProvider>,
+    )]));
+    let manager = manager::start_manager(
+        WsConfig::default(),
+        Arc::new(MemoryGameStore::new()),
+        Arc::new(MemoryChatStore::new()),
+        InProcessBroadcaster::factory(),
+    )
+    .await
+    .unwrap();
+
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(common::config()))
+            .app_data(web::Data::new(DbPool(pool)))
+            .app_data(web::Data::new(providers))
+            .app_data(web::Data::new(manager))
+            .app_data(web::Data::new(ApiMetrics::new(&Registry::new()).unwrap()))
+            .configure(routes::auth::config),
+    )
+    .await
+}
+
+/// A pool that never connects, for flows rejected before any query.
+fn unused_pool() -> PgPool {
+    PgPoolOptions::new()
+        .connect_lazy("postgres://unused@127.0.0.1:1/unused")
+        .unwrap()
+}
+
+/// Starts a login; returns the `state` and `nonce` sent to the provider,
+/// and the cookie the browser got.
+async fn start_login(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+) -> (String, String, Cookie<'static>) {
+    let req = test::TestRequest::get()
+        .uri("/auth/oidc/mock/login")
+        .to_request();
+    let res = test::call_service(app, req).await;
+    assert_eq!(res.status(), StatusCode::FOUND);
+
+    let cookie = res
+        .response()
+        .cookies()
+        .find(|c| c.name() == "oidc_browser")
+        .unwrap()
+        .into_owned();
+    assert!(cookie.http_only().unwrap_or(false));
+
+    let location = res
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let query: HashMap<_, _> = Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect();
+
+    (query["state"].clone(), query["nonce"].clone(), cookie)
+}
+
+async fn callback(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    state: &str,
+    code: &str,
+    cookie: Option<Cookie<'static>>,
+) -> (StatusCode, Value) {
+    let mut url = Url::parse("http://localhost/auth/oidc/mock/callback").unwrap();
+    url.query_pairs_mut()
+        .append_pair("state", state)
+        .append_pair("code", code);
+    let uri = format!("{}?{}", url.path(), url.query().unwrap());
+    let mut req = test::TestRequest::get().uri(&uri);
+    if let Some(cookie) = cookie {
+        req = req.cookie(cookie);
+    }
+
+    let res = match test::try_call_service(app, req.to_request()).await {
+        Ok(res) => res.map_into_boxed_body().into_parts().1,
+        Err(e) => e.error_response(),
+    };
+    let status = res.status();
+    let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+
+    let body = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+
+    (status, body)
+}
+
+#[actix_web::test]
+async fn discovery_and_code_exchange_verify_the_id_token() {
+    let mock = start_mock().await;
+    let provider = discover(&mock).await;
+
+    let identity = provider.exchange_code("nonce-1", "nonce-1").await.unwrap();
+    assert_eq!(identity.subject, "mock-subject");
+    assert_eq!(identity.preferred_username.as_deref(), Some("mocker"));
+
+    assert!(provider.exchange_code("nonce-1", "nonce-2").await.is_err());
+}
+
+#[actix_web::test]
+async fn id_token_signed_with_another_key_is_rejected() {
+    let mock = start_mock().await;
+    let provider = discover(&mock).await;
+    *mock.signing_key.lock().unwrap() = ATTACKER_KEY;
+
+    let error = provider.exchange_code("nonce", "nonce").await.unwrap_err();
+    assert!(format!("{:#}", error).contains("id_token validation failed"));
+}
+
+#[actix_web::test]
+async fn id_token_with_an_unknown_key_id_is_rejected() {
+    let mock = start_mock().await;
+    let provider = discover(&mock).await;
+    *mock.kid.lock().unwrap() = "rotated-away";
+
+    let error = provider.exchange_code("nonce", "nonce").await.unwrap_err();
+    assert!(error.to_string().contains("No signing key rotated-away"));
+}
+
+#[actix_web::test]
+async fn expired_state_is_rejected() {
+    let mock = start_mock().await;
+    let app = app(unused_pool(), discover(&mock).await).await;
+    let (_, nonce, cookie) = start_login(&app).await;
+
+    let config: Config = common::config();
+    let expired = OidcState {
+        provider: "mock".to_string(),
+        nonce: nonce.clone(),
+        link_user: None,
+        browser: hex::encode(Sha256::digest(cookie.value().as_bytes())),
+        exp: (Utc::now().timestamp() - 3600) as usize,
+    };
+    let expired = encode(
+        &Header::default(),
+        &expired,
+        &EncodingKey::from_secret(config.auth.jwt_secret.as_bytes()),
+    )
+    .unwrap();
+
+    let (status, body) = callback(&app, &expired, &nonce, Some(cookie)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "Invalid or expired state");
+}
+
+#[actix_web::test]
+async fn state_is_rejected_without_the_browser_cookie() {
+    let mock = start_mock().await;
+    let app = app(unused_pool(), discover(&mock).await).await;
+    let (state, nonce, _) = start_login(&app).await;
+    let (_, _, other_browser) = start_login(&app).await;
+
+    let (status, _) = callback(&app, &state, &nonce, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = callback(&app, &state, &nonce, Some(other_browser)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "Login was started in another browser");
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn login_creates_an_account_for_a_new_identity(pool: PgPool) {
+    let mock = start_mock().await;
+    let app = app(pool, discover(&mock).await).await;
+    let (state, nonce, cookie) = start_login(&app).await;
+
+    let (status, body) = callback(&app, &state, &nonce, Some(cookie)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "mocker");
+    assert!(body["token"].is_string());
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn long_profile_names_are_cut_to_the_username_limit(pool: PgPool) {
+    let mock = start_mock().await;
+    *mock.username.lock().unwrap() = "a-very-long-display-name-from-the-provider";
+    let app = app(pool, discover(&mock).await).await;
+    let (state, nonce, cookie) = start_login(&app).await;
+
+    let (status, body) = callback(&app, &state, &nonce, Some(cookie)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "a-very-long-display-name-from-th");
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn profile_names_in_the_guest_namespace_are_not_used(pool: PgPool) {
+    let mock = start_mock().await;
+    *mock.username.lock().unwrap() = "guest-1234";
+    let app = app(pool, discover(&mock).await).await;
+    let (state, nonce, cookie) = start_login(&app).await;
+
+    let (status, body) = callback(&app, &state, &nonce, Some(cookie)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "user");
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn identity_provider_account_has_no_password_to_change(pool: PgPool) {
+    let mock = start_mock().await;
+    let app = app(pool, discover(&mock).await).await;
+    let (state, nonce, cookie) = start_login(&app).await;
+    let (_, body) = callback(&app, &state, &nonce, Some(cookie)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/auth/password/change")
+        .insert_header((
+            header::AUTHORIZATION,
+            format!("Bearer {}", body["token"].as_str().unwrap()),
+        ))
+        .set_json(json!({ "current_password": "", "new_password": "password123" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let body = test::read_body(res).await;
+    assert_eq!(
+        body,
+        "This account signs in through an identity provider and has no password"
+    );
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{
+    App,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test, web,
+};
+use db::pool::DbPool;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use tic_tac::{metrics::ApiMetrics, routes};
+use ws::{
+    broadcast::InProcessBroadcaster,
+    config::WsConfig,
+    game::{chat::MemoryChatStore, store::MemoryGameStore},
+    manager::{self, WsManager},
+};
+
+async fn app(
+    pool: PgPool,
+    manager: Arc<WsManager>,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let metrics = ApiMetrics::new(manager.metrics.registry()).unwrap();
+    let manager = web::Data::new(manager);
+
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(common::config()))
+            .app_data(web::Data::new(DbPool(pool)))
+            .app_data(web::Data::new(metrics))
+            .configure(routes::auth::config)
+            .configure(|cfg| routes::websocket::config(cfg, manager.clone())),
+    )
+    .await
+}
+
+async fn ws_manager() -> Arc<WsManager> {
+    manager::start_manager(
+        WsConfig::default(),
+        Arc::new(MemoryGameStore::new()),
+        Arc::new(MemoryChatStore::new()),
+        InProcessBroadcaster::factory(),
+    )
+    .await
+    .unwrap()
+}
+
+async fn call(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    req: test::TestRequest,
+) -> (StatusCode, String) {
+    let res = match test::try_call_service(app, req.to_request()).await {
+        Ok(res) => res.map_into_boxed_body().into_parts().1,
+        Err(e) => e.error_response(),
+    };
+    let status = res.status();
+    let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+async fn post_json(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    uri: &str,
+    body: &Value,
+) -> Value {
+    let req = test::TestRequest::post().uri(uri).set_json(body);
+    let (status, body) = call(app, req).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    serde_json::from_str(&body).unwrap()
+}
+
+/// Signs up `username` and returns a session token.
+async fn token(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    username: &str,
+) -> String {
+    let credentials = json!({ "username": username, "password": "password123" });
+    post_json(app, "/auth/register", &credentials).await;
+    let body = post_json(app, "/auth/login", &credentials).await;
+
+    body["token"].as_str().unwrap().to_string()
+}
+
+fn upgrade(uri: &str, token: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(uri)
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("Upgrade", "websocket"))
+        .insert_header(("Connection", "Upgrade"))
+        .insert_header(("Sec-WebSocket-Version", "13"))
+        .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn sockets_are_turned_away_with_503_while_draining(pool: PgPool) {
+    let manager = ws_manager().await;
+    let app = app(pool, manager.clone()).await;
+    let token = token(&app, "alice").await;
+
+    manager.shutdown().await;
+
+    let (status, body) = call(&app, upgrade("/ws/game-1", &token)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, "Server is shutting down");
+}
+
+#[sqlx::test(migrations = "../db/migrations")]
+#[ignore = "needs Postgres at DATABASE_URL"]
+async fn unknown_encodings_are_a_bad_request(pool: PgPool) {
+    let app = app(pool, ws_manager().await).await;
+    let token = token(&app, "alice").await;
+
+    let (status, _) = call(&app, upgrade("/ws/game-1?encoding=xml", &token)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,3 @@
+ALTER TABLE user_bans ADD COLUMN reason TEXT NOT NULL DEFAULT '';
+ALTER TABLE user_bans ADD COLUMN expires_at TIMESTAMPTZ;
+ALTER TABLE user_bans ADD COLUMN lifted_at TIMESTAMPTZ;
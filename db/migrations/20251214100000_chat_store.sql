@@ -0,0 +1,15 @@
+CREATE TABLE chat_messages (
+    id BIGSERIAL PRIMARY KEY,
+    game_id TEXT NOT NULL REFERENCES game_states(id) ON DELETE CASCADE,
+    record TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_chat_messages_game_id ON chat_messages(game_id, id);
+
+CREATE TABLE chat_mutes (
+    game_id TEXT NOT NULL REFERENCES game_states(id) ON DELETE CASCADE,
+    muter UUID NOT NULL,
+    muted UUID NOT NULL,
+    PRIMARY KEY (game_id, muter, muted)
+);
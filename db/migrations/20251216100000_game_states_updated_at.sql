@@ -0,0 +1 @@
+CREATE INDEX idx_game_states_updated_at ON game_states(updated_at);
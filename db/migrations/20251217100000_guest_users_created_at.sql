@@ -0,0 +1 @@
+CREATE INDEX idx_users_guest_created_at ON users(created_at) WHERE is_guest;
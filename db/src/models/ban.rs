@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
+pub struct UserBan {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub banned_by: Option<Uuid>,
+    pub reason: String,
+    /// `None` means the ban is permanent.
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub lifted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
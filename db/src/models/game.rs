@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+/// A game event as stored, with its JSON left to the caller.
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
+pub struct GameEventRow {
+    pub id: i64,
+    pub event: String,
+}
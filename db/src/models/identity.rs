@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
@@ -1 +1,4 @@
+pub mod ban;
+pub mod game;
+pub mod identity;
 pub mod user;
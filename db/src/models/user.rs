@@ -2,11 +2,26 @@ use serde::{Deserialize, Serialize};
 
 use uuid::Uuid;
 
+#[derive(
+    sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Player,
+    Moderator,
+    Admin,
+}
+
 #[derive(sqlx::FromRow, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
-    #[sqlx(rename="password_hash")]
-    pub password: String,
+    #[sqlx(rename = "password_hash")]
+    pub password: Option<String>,
+    pub token_version: i32,
+    pub is_guest: bool,
+    pub role: Role,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
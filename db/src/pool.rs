@@ -3,10 +3,36 @@ use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
 #[derive(Clone)]
 pub struct DbPool(pub Pool<Postgres>);
 
+/// How much of the pool is in use.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    /// Connections open, idle or not.
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+impl PoolStats {
+    pub fn in_use(&self) -> usize {
+        (self.size as usize).saturating_sub(self.idle)
+    }
+}
+
 impl DbPool {
-    pub async fn new(url: &str) -> anyhow::Result<Self> {
-        let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
+    pub async fn new(url: &str, max_connections: u32) -> anyhow::Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await?;
 
         Ok(Self(pool))
     }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.0.size(),
+            idle: self.0.num_idle(),
+            max: self.0.options().get_max_connections(),
+        }
+    }
 }
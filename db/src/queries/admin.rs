@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::models::{
+    ban::UserBan,
+    user::{Role, User},
+};
+
+/// Changing a role bumps the token version so tokens carrying the old role
+/// stop working.
+pub async fn set_user_role(pool: &Pool<Postgres>, user_id: Uuid, role: Role) -> sqlx::Result<User> {
+    sqlx::query_as::<_, User>(
+        "UPDATE users SET role = $2, token_version = token_version + 1 WHERE id = $1 RETURNING *",
+    )
+    .bind(user_id)
+    .bind(role)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn ban_user(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    banned_by: Uuid,
+    reason: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> sqlx::Result<UserBan> {
+    sqlx::query_as::<_, UserBan>(
+        "INSERT INTO user_bans (user_id, banned_by, reason, expires_at) \
+         VALUES ($1, $2, $3, $4) RETURNING *",
+    )
+    .bind(user_id)
+    .bind(banned_by)
+    .bind(reason)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await
+}
+
+/// Lifts every active ban of the user, keeping the records for history.
+pub async fn unban_user(pool: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "UPDATE user_bans SET lifted_at = NOW() \
+         WHERE user_id = $1 AND lifted_at IS NULL \
+         AND (expires_at IS NULL OR expires_at > NOW())",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Returns the ban that currently applies to the user, preferring the one
+/// that lasts longest.
+pub async fn get_active_ban(pool: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<Option<UserBan>> {
+    sqlx::query_as::<_, UserBan>(
+        "SELECT * FROM user_bans \
+         WHERE user_id = $1 AND lifted_at IS NULL \
+         AND (expires_at IS NULL OR expires_at > NOW()) \
+         ORDER BY expires_at DESC NULLS FIRST LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn list_bans(pool: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<Vec<UserBan>> {
+    sqlx::query_as::<_, UserBan>(
+        "SELECT * FROM user_bans WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
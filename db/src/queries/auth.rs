@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 use uuid::Uuid;
 
@@ -17,6 +18,49 @@ pub async fn create_user(
     .await
 }
 
+pub async fn create_guest_user(pool: &Pool<Postgres>, username: &str) -> sqlx::Result<User> {
+    sqlx::query_as::<_, User>(
+        "INSERT INTO users (username, is_guest) VALUES ($1, TRUE) RETURNING *",
+    )
+    .bind(username)
+    .fetch_one(pool)
+    .await
+}
+
+/// Deletes the guests created more than `max_age_secs` ago and never
+/// upgraded. Guests can't sign in again, so once their token expires
+/// nothing can reach the account.
+pub async fn delete_stale_guests(pool: &Pool<Postgres>, max_age_secs: f64) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM users WHERE is_guest AND created_at <= NOW() - make_interval(secs => $1)",
+    )
+    .bind(max_age_secs)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Turns a guest into a regular account in place, so everything keyed by the
+/// user id carries over.
+pub async fn upgrade_guest_user(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    username: &str,
+    password: &str,
+) -> sqlx::Result<Option<User>> {
+    sqlx::query_as::<_, User>(
+        "UPDATE users SET username = $2, password_hash = $3, is_guest = FALSE, \
+         token_version = token_version + 1 \
+         WHERE id = $1 AND is_guest RETURNING *",
+    )
+    .bind(user_id)
+    .bind(username)
+    .bind(password)
+    .fetch_optional(pool)
+    .await
+}
+
 pub async fn get_user_by_username(pool: &Pool<Postgres>, username: &str) -> sqlx::Result<User> {
     sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
         .bind(username)
@@ -30,3 +74,78 @@ pub async fn get_user_by_id(pool: &Pool<Postgres>, user_id: Uuid) -> sqlx::Resul
         .fetch_one(pool)
         .await
 }
+
+pub async fn update_password(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    password: &str,
+) -> sqlx::Result<User> {
+    sqlx::query_as::<_, User>(
+        "UPDATE users SET password_hash = $2, token_version = token_version + 1 WHERE id = $1 RETURNING *",
+    )
+    .bind(user_id)
+    .bind(password)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn create_password_reset_token(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+    )
+    .bind(user_id)
+    .bind(token_hash)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Consumes a reset token and sets its owner's password in a single
+/// transaction, revoking the owner's sessions and other reset tokens.
+/// Returns `None`, changing nothing, if the token is unknown, expired or
+/// already used.
+pub async fn reset_password(
+    pool: &Pool<Postgres>,
+    token_hash: &str,
+    password: &str,
+) -> sqlx::Result<Option<User>> {
+    let mut tx = pool.begin().await?;
+
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        "UPDATE password_reset_tokens SET used_at = NOW() \
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW() \
+         RETURNING user_id",
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+
+    let user = sqlx::query_as::<_, User>(
+        "UPDATE users SET password_hash = $2, token_version = token_version + 1 WHERE id = $1 RETURNING *",
+    )
+    .bind(user_id)
+    .bind(password)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE password_reset_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(user))
+}
@@ -0,0 +1,107 @@
+use sqlx::{PgConnection, Pool, Postgres};
+use uuid::Uuid;
+
+/// Chat records are JSON owned by the WebSocket layer, stored as text. They
+/// go away with their game.
+pub async fn append_chat_message(
+    conn: &mut PgConnection,
+    game_id: &str,
+    record: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO chat_messages (game_id, record) VALUES ($1, $2)")
+        .bind(game_id)
+        .bind(record)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes all but the newest `keep` chat messages of a game.
+pub async fn trim_chat_messages(
+    conn: &mut PgConnection,
+    game_id: &str,
+    keep: i64,
+) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM chat_messages WHERE game_id = $1 AND id NOT IN \
+         (SELECT id FROM chat_messages WHERE game_id = $1 ORDER BY id DESC LIMIT $2)",
+    )
+    .bind(game_id)
+    .bind(keep)
+    .execute(conn)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Chat messages of a game, oldest first.
+pub async fn list_chat_messages(pool: &Pool<Postgres>, game_id: &str) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar("SELECT record FROM chat_messages WHERE game_id = $1 ORDER BY id")
+        .bind(game_id)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn mute(
+    pool: &Pool<Postgres>,
+    game_id: &str,
+    muter: Uuid,
+    muted: Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO chat_mutes (game_id, muter, muted) VALUES ($1, $2, $3) \
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(game_id)
+    .bind(muter)
+    .bind(muted)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn unmute(
+    pool: &Pool<Postgres>,
+    game_id: &str,
+    muter: Uuid,
+    muted: Uuid,
+) -> sqlx::Result<u64> {
+    let result =
+        sqlx::query("DELETE FROM chat_mutes WHERE game_id = $1 AND muter = $2 AND muted = $3")
+            .bind(game_id)
+            .bind(muter)
+            .bind(muted)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Deletes the chat messages and mutes of a game.
+pub async fn clear_chat(conn: &mut PgConnection, game_id: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM chat_messages WHERE game_id = $1")
+        .bind(game_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM chat_mutes WHERE game_id = $1")
+        .bind(game_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Users `muter` muted in a game.
+pub async fn list_muted(
+    pool: &Pool<Postgres>,
+    game_id: &str,
+    muter: Uuid,
+) -> sqlx::Result<Vec<Uuid>> {
+    sqlx::query_scalar("SELECT muted FROM chat_mutes WHERE game_id = $1 AND muter = $2")
+        .bind(game_id)
+        .bind(muter)
+        .fetch_all(pool)
+        .await
+}
@@ -0,0 +1,144 @@
+use sqlx::{PgConnection, Pool, Postgres};
+
+use crate::models::game::GameEventRow;
+
+/// Game states and events are JSON documents owned by the WebSocket layer,
+/// they are passed through as text. Games untouched for more than
+/// `max_age_secs` count as gone.
+pub async fn get_game_state(
+    pool: &Pool<Postgres>,
+    game_id: &str,
+    max_age_secs: f64,
+) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar(
+        "SELECT state::text FROM game_states \
+         WHERE id = $1 AND updated_at > NOW() - make_interval(secs => $2)",
+    )
+    .bind(game_id)
+    .bind(max_age_secs)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Like `get_game_state`, but locks the row until the transaction `conn`
+/// belongs to ends.
+pub async fn lock_game_state(
+    conn: &mut PgConnection,
+    game_id: &str,
+    max_age_secs: f64,
+) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar(
+        "SELECT state::text FROM game_states \
+         WHERE id = $1 AND updated_at > NOW() - make_interval(secs => $2) FOR UPDATE",
+    )
+    .bind(game_id)
+    .bind(max_age_secs)
+    .fetch_optional(conn)
+    .await
+}
+
+pub async fn save_game_state(
+    conn: &mut PgConnection,
+    game_id: &str,
+    state: &str,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO game_states (id, state) VALUES ($1, $2::jsonb) \
+         ON CONFLICT (id) DO UPDATE SET state = EXCLUDED.state, updated_at = NOW()",
+    )
+    .bind(game_id)
+    .bind(state)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a new game under `game_id`. An earlier game with that id is
+/// deleted first, together with its events and chat.
+pub async fn replace_game_state(
+    conn: &mut PgConnection,
+    game_id: &str,
+    state: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM game_states WHERE id = $1")
+        .bind(game_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("INSERT INTO game_states (id, state) VALUES ($1, $2::jsonb)")
+        .bind(game_id)
+        .bind(state)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn append_game_event(
+    conn: &mut PgConnection,
+    game_id: &str,
+    event: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO game_events (game_id, event) VALUES ($1, $2::jsonb)")
+        .bind(game_id)
+        .bind(event)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Deleting a game deletes its events too.
+pub async fn delete_game_state(pool: &Pool<Postgres>, game_id: &str) -> sqlx::Result<u64> {
+    let result = sqlx::query("DELETE FROM game_states WHERE id = $1")
+        .bind(game_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn list_game_states(
+    pool: &Pool<Postgres>,
+    max_age_secs: f64,
+) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar(
+        "SELECT state::text FROM game_states \
+         WHERE updated_at > NOW() - make_interval(secs => $1) ORDER BY updated_at DESC",
+    )
+    .bind(max_age_secs)
+    .fetch_all(pool)
+    .await
+}
+
+/// Deletes the games untouched for more than `max_age_secs`, with their
+/// events and chat.
+pub async fn delete_stale_game_states(
+    pool: &Pool<Postgres>,
+    max_age_secs: f64,
+) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM game_states WHERE updated_at <= NOW() - make_interval(secs => $1)",
+    )
+    .bind(max_age_secs)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Events of a game with an id above `after`, oldest first.
+pub async fn list_game_events(
+    pool: &Pool<Postgres>,
+    game_id: &str,
+    after: i64,
+) -> sqlx::Result<Vec<GameEventRow>> {
+    sqlx::query_as::<_, GameEventRow>(
+        "SELECT id, event::text AS event FROM game_events \
+         WHERE game_id = $1 AND id > $2 ORDER BY id",
+    )
+    .bind(game_id)
+    .bind(after)
+    .fetch_all(pool)
+    .await
+}
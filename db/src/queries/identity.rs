@@ -0,0 +1,82 @@
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::models::{identity::UserIdentity, user::User};
+
+pub async fn get_user_by_identity(
+    pool: &Pool<Postgres>,
+    provider: &str,
+    subject: &str,
+) -> sqlx::Result<Option<User>> {
+    sqlx::query_as::<_, User>(
+        "SELECT users.* FROM users \
+         JOIN user_identities ON user_identities.user_id = users.id \
+         WHERE user_identities.provider = $1 AND user_identities.subject = $2",
+    )
+    .bind(provider)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn link_identity(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> sqlx::Result<UserIdentity> {
+    sqlx::query_as::<_, UserIdentity>(
+        "INSERT INTO user_identities (user_id, provider, subject, email) \
+         VALUES ($1, $2, $3, $4) RETURNING *",
+    )
+    .bind(user_id)
+    .bind(provider)
+    .bind(subject)
+    .bind(email)
+    .fetch_one(pool)
+    .await
+}
+
+/// Creates a password-less user and links the external identity to it in a
+/// single transaction.
+pub async fn create_user_with_identity(
+    pool: &Pool<Postgres>,
+    username: &str,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> sqlx::Result<User> {
+    let mut tx = pool.begin().await?;
+
+    let user = sqlx::query_as::<_, User>("INSERT INTO users (username) VALUES ($1) RETURNING *")
+        .bind(username)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO user_identities (user_id, provider, subject, email) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(user.id)
+    .bind(provider)
+    .bind(subject)
+    .bind(email)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+pub async fn list_identities(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+) -> sqlx::Result<Vec<UserIdentity>> {
+    sqlx::query_as::<_, UserIdentity>(
+        "SELECT * FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
@@ -1 +1,6 @@
+pub mod admin;
 pub mod auth;
+pub mod chat;
+pub mod game_state;
+pub mod identity;
+pub mod notify;
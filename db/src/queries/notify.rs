@@ -0,0 +1,20 @@
+use sqlx::{Pool, Postgres};
+
+pub use sqlx::postgres::PgListener;
+
+/// Sends `payload` to every connection listening on `channel`. Postgres
+/// caps payloads at just under 8000 bytes.
+pub async fn notify(pool: &Pool<Postgres>, channel: &str, payload: &str) -> sqlx::Result<()> {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(channel)
+        .bind(payload)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// A dedicated connection for `LISTEN`, separate from the pool's.
+pub async fn listener(pool: &Pool<Postgres>) -> sqlx::Result<PgListener> {
+    PgListener::connect_with(pool).await
+}
@@ -0,0 +1,77 @@
+//! Throughput of Redis reads with a connection opened per operation, as the
+//! stores used to do, against the shared connection they use now.
+//!
+//! Needs a Redis at `REDIS_URL` (default `redis://127.0.0.1:6379`):
+//!
+//!     cargo bench -p ws --bench redis_connections
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::future::join_all;
+use redis::{AsyncTypedCommands, Client};
+use tokio::runtime::Runtime;
+use ws::{config::RedisConfig, keys::Keys, redis_conn::RedisConnection};
+
+/// Operations in flight at once, as with many sessions moving together.
+const CONCURRENCY: [usize; 2] = [1, 32];
+
+fn redis_connections(c: &mut Criterion) {
+    let redis_url =
+        std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+    let runtime = Runtime::new().unwrap();
+    let client = Client::open(redis_url.as_str()).unwrap();
+    let shared = RedisConnection::new(client.clone(), RedisConfig::default());
+    let key = Keys::new("bench:").game_state("redis-connections");
+
+    let ready = runtime.block_on(async {
+        let mut conn = shared.get().await?;
+        conn.set(&key, "{}").await?;
+        anyhow::Ok(())
+    });
+    if let Err(e) = ready {
+        eprintln!(
+            "Skipping Redis benchmarks, {} unreachable: {:#}",
+            redis_url, e
+        );
+        return;
+    }
+
+    let mut group = c.benchmark_group("redis_get");
+    for concurrency in CONCURRENCY {
+        group.throughput(Throughput::Elements(concurrency as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("connection_per_op", concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime).iter(|| {
+                    join_all((0..concurrency).map(|_| async {
+                        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+                        conn.get(&key).await.unwrap()
+                    }))
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("shared_connection", concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime).iter(|| {
+                    join_all((0..concurrency).map(|_| async {
+                        let mut conn = shared.get().await.unwrap();
+                        conn.get(&key).await.unwrap()
+                    }))
+                })
+            },
+        );
+    }
+    group.finish();
+
+    runtime.block_on(async {
+        let mut conn = shared.get().await.unwrap();
+        conn.del(&key).await.unwrap();
+    });
+}
+
+criterion_group!(benches, redis_connections);
+criterion_main!(benches);
@@ -0,0 +1,671 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_ws::{CloseCode, CloseReason};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use opentelemetry::global;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::UnboundedReceiver, watch};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::{
+    connection_registry::ConnectionRegistry,
+    game::{events::EventSource, game_manager::GameManager, messages::WsServerMessage},
+    keys::Keys,
+    metrics::WsMetrics,
+    session_queue::Outbound,
+};
+
+/// First and longest wait between reconnect attempts of a transport.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often the consumer group of an instance is read.
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long an event may go without its state being delivered by the
+/// transport before the game is re-synced.
+const EVENT_GRACE: Duration = Duration::from_secs(1);
+
+/// Fans game messages and instance-wide commands out to the sessions of
+/// every instance.
+#[async_trait]
+pub trait Broadcaster: Send + Sync {
+    /// Delivers `message` to every session of the game, on every instance,
+    /// except `exclude_session_id`.
+    async fn broadcast(
+        &self,
+        game_id: &str,
+        message: &Outbound,
+        exclude_session_id: &str,
+    ) -> Result<()>;
+
+    /// Applies `message` on every instance, this one included.
+    async fn control(&self, message: &ControlMessage) -> Result<()>;
+
+    /// Receives from the other instances until `shutdown` flips to true.
+    /// `game_events` names the games whose local sessions came or went.
+    async fn run(&self, shutdown: watch::Receiver<bool>, game_events: UnboundedReceiver<String>);
+
+    /// Whether messages from the other instances are getting through.
+    fn is_connected(&self) -> bool;
+}
+
+/// Builds the broadcaster of a `WsManager` once its registry, games and
+/// metrics exist.
+pub type BroadcasterFactory = Box<
+    dyn FnOnce(
+            Arc<ConnectionRegistry>,
+            Arc<GameManager>,
+            Arc<WsMetrics>,
+        ) -> Result<Arc<dyn Broadcaster>>
+        + Send,
+>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    DisconnectUser { user_id: Uuid, reason: String },
+    CloseGame { game_id: String, reason: String },
+}
+
+impl ControlMessage {
+    pub fn apply(&self, registry: &ConnectionRegistry) {
+        match self {
+            ControlMessage::DisconnectUser { user_id, reason } => {
+                let reason = CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some(reason.clone()),
+                };
+                registry.disconnect_user(*user_id, &reason);
+            }
+            ControlMessage::CloseGame { game_id, reason } => {
+                let reason = CloseReason {
+                    code: CloseCode::Normal,
+                    description: Some(reason.clone()),
+                };
+                registry.close_game(game_id, &reason);
+            }
+        }
+    }
+}
+
+/// A game message on its way to other instances.
+#[derive(Serialize, Deserialize)]
+pub struct MessagePayload {
+    /// Instance that published the message. It has already delivered it to
+    /// its own sessions, so it skips its own echo.
+    #[serde(default)]
+    pub origin: String,
+    pub sender_id: String,
+    message: String,
+    /// Full game states can be coalesced by slow sessions.
+    #[serde(default)]
+    state: bool,
+    /// `move_applied` delta for `message`, which then holds the full state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delta: Option<String>,
+    /// Author of a chat `message`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from: Option<Uuid>,
+    /// Trace context of the span that published the message, e.g.
+    /// `traceparent`, so deliveries on other instances join its trace.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    trace: HashMap<String, String>,
+    /// `seq` of the game state in `message`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Sequenced {
+    seq: u64,
+}
+
+impl MessagePayload {
+    pub fn new(origin: &str, sender_session_id: &str, message: &Outbound) -> Result<Self> {
+        let (message, state, delta, from) = match message {
+            Outbound::Text(text) => (text.to_string(), false, None, None),
+            Outbound::State(text) => (text.clone(), true, None, None),
+            Outbound::Delta { delta, state } => {
+                (state.clone(), true, Some(delta.to_string()), None)
+            }
+            Outbound::Chat { from, message } => (message.to_string(), false, None, Some(*from)),
+            Outbound::Close(_) => anyhow::bail!("Close frames can't be published"),
+        };
+
+        let seq = state
+            .then(|| serde_json::from_str::<Sequenced>(&message).ok())
+            .flatten()
+            .map(|state| state.seq);
+
+        let mut trace = HashMap::new();
+        let context = tracing::Span::current().context();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut trace);
+        });
+
+        Ok(Self {
+            origin: origin.to_string(),
+            sender_id: sender_session_id.to_string(),
+            message,
+            state,
+            delta,
+            from,
+            trace,
+            seq,
+        })
+    }
+
+    /// `seq` of the game state carried, if any.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn into_outbound(self) -> Outbound {
+        match (self.delta, self.from, self.state) {
+            (Some(delta), _, _) => Outbound::Delta {
+                delta: delta.into(),
+                state: self.message,
+            },
+            (None, Some(from), _) => Outbound::Chat {
+                from,
+                message: self.message.into(),
+            },
+            (None, None, true) => Outbound::State(self.message),
+            (None, None, false) => Outbound::Text(self.message.into()),
+        }
+    }
+
+    /// Delivers a payload received from the transport to the local sessions
+    /// of the game, unless this instance published it.
+    pub fn deliver(self, registry: &ConnectionRegistry, instance_id: &str, game_id: &str) {
+        if self.origin == instance_id {
+            return;
+        }
+
+        let span = tracing::info_span!("deliver", %game_id, origin = %self.origin);
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&self.trace));
+        let _ = span.set_parent(parent);
+        let _entered = span.enter();
+
+        let sender_id = self.sender_id.clone();
+        registry.broadcast_except(game_id, &self.into_outbound(), &sender_id);
+    }
+}
+
+/// Sends the stored state of one game to its local sessions, covering
+/// whatever was published before this instance followed the game. Returns
+/// the `seq` of the state sent.
+pub async fn resync_game(
+    registry: &ConnectionRegistry,
+    game_manager: &GameManager,
+    game_id: &str,
+) -> Option<u64> {
+    let game = match game_manager.get_game(game_id).await {
+        Ok(Some(game)) => game,
+        Ok(None) => return None,
+        Err(e) => {
+            tracing::warn!(%game_id, error = %e, "Failed to re-sync game");
+            return None;
+        }
+    };
+
+    match WsServerMessage::state_outbound(&game) {
+        Ok(state) => {
+            registry.broadcast_except(game_id, &state, "");
+            Some(game.seq)
+        }
+        Err(e) => {
+            tracing::warn!(%game_id, error = %e, "Failed to re-sync game");
+            None
+        }
+    }
+}
+
+/// Exponential backoff between reconnect attempts.
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next: INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn reset(&mut self) {
+        self.next = INITIAL_BACKOFF;
+    }
+
+    pub fn current(&self) -> Duration {
+        self.next
+    }
+
+    /// Sleeps for the current delay and doubles it. Returns false if
+    /// `shutdown` flipped to true meanwhile.
+    pub async fn wait(&mut self, shutdown: &mut watch::Receiver<bool>) -> bool {
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => true,
+            _ = shutdown.wait_for(|stop| *stop) => false,
+        }
+    }
+}
+
+/// A message received on one of the channels a `Subscription` listens on.
+pub struct Notification {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// The receiving connection of a transport, e.g. a Redis subscriber or a
+/// Postgres `LISTEN` connection.
+#[async_trait]
+pub trait Subscription: Send {
+    async fn listen(&mut self, channel: &str) -> Result<()>;
+
+    async fn unlisten(&mut self, channel: &str) -> Result<()>;
+
+    /// The next notification, or `None` once the connection dropped.
+    /// Anything sent until the next connection listens is lost.
+    async fn recv(&mut self) -> Result<Option<Notification>>;
+}
+
+enum FollowEnd {
+    Shutdown,
+    Lost,
+}
+
+enum Wakeup {
+    Notification(Notification),
+    Game(String),
+}
+
+/// The receiving side shared by the transports: listens on the control
+/// channel and the channels of the games with local sessions, and delivers
+/// what arrives to those sessions. Where game events are kept in streams,
+/// it also reads them to catch what the transport dropped.
+pub struct Follower {
+    registry: Arc<ConnectionRegistry>,
+    game_manager: Arc<GameManager>,
+    keys: Keys,
+    metrics: Arc<WsMetrics>,
+    instance_id: String,
+    /// Names the transport in logs.
+    transport: &'static str,
+    connected: AtomicBool,
+    /// Latest `seq` of each followed game that reached its local sessions.
+    delivered: Mutex<HashMap<String, u64>>,
+}
+
+impl Follower {
+    pub fn new(
+        registry: Arc<ConnectionRegistry>,
+        game_manager: Arc<GameManager>,
+        metrics: Arc<WsMetrics>,
+        keys: Keys,
+        transport: &'static str,
+    ) -> Self {
+        Self {
+            registry,
+            game_manager,
+            keys,
+            metrics,
+            instance_id: Uuid::new_v4().to_string(),
+            transport,
+            connected: AtomicBool::new(false),
+            delivered: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Tags what this instance publishes, so it skips its own echoes.
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Follows over connections from `connect` until `shutdown` flips to
+    /// true. Whenever a connection fails or drops, reconnects with
+    /// exponential backoff and re-syncs the local games, since anything
+    /// sent meanwhile was missed.
+    pub async fn run<S, F, Fut>(
+        &self,
+        connect: F,
+        mut shutdown: watch::Receiver<bool>,
+        mut game_events: UnboundedReceiver<String>,
+    ) where
+        S: Subscription,
+        F: Fn() -> Fut + Send + Sync,
+        Fut: Future<Output = Result<S>> + Send,
+    {
+        let mut backoff = Backoff::default();
+        let mut resync = false;
+
+        loop {
+            let followed = match connect().await {
+                Ok(mut subscription) => {
+                    self.follow(&mut subscription, &mut shutdown, &mut game_events, resync)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match followed {
+                Ok(FollowEnd::Shutdown) => break,
+                Ok(FollowEnd::Lost) => {
+                    tracing::warn!(
+                        transport = self.transport,
+                        "Subscription lost, reconnecting"
+                    );
+                    self.metrics.record_subscribe_error();
+                    backoff.reset();
+                }
+                Err(e) => {
+                    tracing::error!(
+                        transport = self.transport,
+                        error = format!("{:#}", e),
+                        retry_in = ?backoff.current(),
+                        "Subscriber error"
+                    );
+                    self.metrics.record_subscribe_error();
+                }
+            }
+
+            self.connected.store(false, Ordering::Relaxed);
+            resync = true;
+
+            if !backoff.wait(&mut shutdown).await {
+                break;
+            }
+        }
+
+        self.connected.store(false, Ordering::Relaxed);
+    }
+
+    async fn follow<S: Subscription>(
+        &self,
+        subscription: &mut S,
+        shutdown: &mut watch::Receiver<bool>,
+        game_events: &mut UnboundedReceiver<String>,
+        resync: bool,
+    ) -> Result<FollowEnd> {
+        subscription
+            .listen(&self.keys.control_channel())
+            .await
+            .context("Failed to listen on the control channel")?;
+
+        // Games that gained sessions while disconnected are picked up here;
+        // their queued events are then no-ops.
+        let mut listening = HashSet::new();
+        for game_id in self.registry.game_ids() {
+            subscription
+                .listen(&self.keys.game_channel(&game_id))
+                .await
+                .context("Failed to listen on a game channel")?;
+            listening.insert(game_id);
+        }
+
+        self.connected.store(true, Ordering::Relaxed);
+        if resync {
+            for game_id in self.registry.game_ids() {
+                self.resync(&game_id).await;
+            }
+        }
+
+        loop {
+            let wakeup = tokio::select! {
+                notification = subscription.recv() => match notification? {
+                    Some(notification) => Wakeup::Notification(notification),
+                    None => return Ok(FollowEnd::Lost),
+                },
+                Some(game_id) = game_events.recv() => Wakeup::Game(game_id),
+                _ = shutdown.wait_for(|stop| *stop) => return Ok(FollowEnd::Shutdown),
+            };
+
+            match wakeup {
+                Wakeup::Notification(notification) => self.deliver(notification),
+                Wakeup::Game(game_id) => {
+                    self.follow_game(subscription, &mut listening, game_id)
+                        .await?
+                }
+            }
+        }
+    }
+
+    /// Listens on a game that has local sessions, or stops listening on one
+    /// that has none left, going by the registry rather than the event so
+    /// that out-of-order events settle on the right state. A newly followed
+    /// game is re-synced, as moves sent between its first session joining
+    /// and the listen landing are otherwise lost.
+    async fn follow_game<S: Subscription>(
+        &self,
+        subscription: &mut S,
+        listening: &mut HashSet<String>,
+        game_id: String,
+    ) -> Result<()> {
+        let local = self.registry.games.contains_key(&game_id);
+        let channel = self.keys.game_channel(&game_id);
+
+        if local && !listening.contains(&game_id) {
+            subscription
+                .listen(&channel)
+                .await
+                .context("Failed to listen on a game channel")?;
+            self.resync(&game_id).await;
+            listening.insert(game_id);
+        } else if !local && listening.contains(&game_id) {
+            subscription
+                .unlisten(&channel)
+                .await
+                .context("Failed to stop listening on a game channel")?;
+            self.delivered.lock().unwrap().remove(&game_id);
+            listening.remove(&game_id);
+        }
+
+        Ok(())
+    }
+
+    fn deliver(&self, notification: Notification) {
+        let Notification { channel, payload } = notification;
+
+        if channel == self.keys.control_channel() {
+            match serde_json::from_str::<ControlMessage>(&payload) {
+                Ok(control) => control.apply(&self.registry),
+                Err(e) => tracing::warn!(error = %e, "Failed to parse control message"),
+            }
+            return;
+        }
+
+        if let Some(game_id) = self.keys.game_id_from_channel(&channel) {
+            match serde_json::from_str::<MessagePayload>(&payload) {
+                Ok(payload) => {
+                    // Our own echoes count: the sessions got those directly.
+                    if let Some(seq) = payload.seq() {
+                        self.record_delivered(game_id, seq);
+                    }
+                    payload.deliver(&self.registry, &self.instance_id, game_id)
+                }
+                Err(e) => tracing::warn!(error = %e, "Failed to parse message payload"),
+            }
+        }
+    }
+
+    async fn resync(&self, game_id: &str) {
+        if let Some(seq) = resync_game(&self.registry, &self.game_manager, game_id).await {
+            self.record_delivered(game_id, seq);
+        }
+    }
+
+    fn record_delivered(&self, game_id: &str, seq: u64) {
+        self.delivered
+            .lock()
+            .unwrap()
+            .insert(game_id.to_string(), seq);
+    }
+
+    fn has_delivered(&self, game_id: &str, seq: u64) -> bool {
+        self.delivered
+            .lock()
+            .unwrap()
+            .get(game_id)
+            .is_some_and(|delivered| *delivered >= seq)
+    }
+
+    /// Reads the events of the local games through a consumer group named
+    /// after this instance until `shutdown` flips to true. An event whose
+    /// state the transport hasn't delivered within `EVENT_GRACE`, e.g.
+    /// because it was published while the subscription was down, gets its
+    /// game re-synced. An event is acknowledged once its state was
+    /// delivered or its game re-synced, so each one is acted on at least
+    /// once.
+    pub async fn consume<E: EventSource>(&self, source: &E, mut shutdown: watch::Receiver<bool>) {
+        let mut joined = HashSet::new();
+        let mut waiting = HashMap::new();
+        let mut backoff = Backoff::default();
+
+        loop {
+            match self.read_events(source, &mut joined, &mut waiting).await {
+                Ok(()) => {
+                    backoff.reset();
+                    tokio::select! {
+                        _ = tokio::time::sleep(EVENT_POLL_INTERVAL) => {}
+                        _ = shutdown.wait_for(|stop| *stop) => break,
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error = format!("{:#}", e),
+                        retry_in = ?backoff.current(),
+                        "Failed to read game events"
+                    );
+                    // The group may be gone with its stream, e.g. when the
+                    // game was created anew.
+                    joined.clear();
+                    if !backoff.wait(&mut shutdown).await {
+                        break;
+                    }
+                }
+            }
+        }
+
+        for game_id in joined {
+            if let Err(e) = source.leave(&game_id, &self.instance_id).await {
+                tracing::warn!(%game_id, error = %e, "Failed to leave event group");
+            }
+        }
+    }
+
+    /// One poll of the consumer group. `waiting` holds when each event not
+    /// yet delivered was first read, by game and entry id.
+    async fn read_events<E: EventSource>(
+        &self,
+        source: &E,
+        joined: &mut HashSet<String>,
+        waiting: &mut HashMap<(String, String), Instant>,
+    ) -> Result<()> {
+        let group = self.instance_id.as_str();
+        let local: HashSet<String> = self.registry.game_ids().into_iter().collect();
+
+        let left: Vec<String> = joined.difference(&local).cloned().collect();
+        for game_id in left {
+            source.leave(&game_id, group).await?;
+            waiting.retain(|(waiting_game, _), _| *waiting_game != game_id);
+            joined.remove(&game_id);
+        }
+        for game_id in local {
+            if !joined.contains(&game_id) && source.join(&game_id, group).await? {
+                joined.insert(game_id);
+            }
+        }
+
+        let game_ids: Vec<String> = joined.iter().cloned().collect();
+        let mut handled: HashMap<String, Vec<String>> = HashMap::new();
+        for event in source.read(group, &game_ids).await? {
+            let delivered = event
+                .seq
+                .is_some_and(|seq| self.has_delivered(&event.game_id, seq));
+
+            if !delivered {
+                let key = (event.game_id.clone(), event.id.clone());
+                let read_at = *waiting.entry(key).or_insert_with(Instant::now);
+                if read_at.elapsed() < EVENT_GRACE {
+                    continue;
+                }
+                tracing::debug!(game_id = %event.game_id, id = %event.id, "Event missed, re-syncing");
+                self.resync(&event.game_id).await;
+            }
+
+            waiting.remove(&(event.game_id.clone(), event.id.clone()));
+            handled.entry(event.game_id).or_default().push(event.id);
+        }
+
+        for (game_id, ids) in handled {
+            source.ack(&game_id, group, &ids).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fan-out within this process only, for single-instance deployments.
+pub struct InProcessBroadcaster {
+    registry: Arc<ConnectionRegistry>,
+}
+
+impl InProcessBroadcaster {
+    pub fn new(registry: Arc<ConnectionRegistry>) -> Self {
+        Self { registry }
+    }
+
+    pub fn factory() -> BroadcasterFactory {
+        Box::new(|registry, _, _| Ok(Arc::new(Self::new(registry))))
+    }
+}
+
+#[async_trait]
+impl Broadcaster for InProcessBroadcaster {
+    async fn broadcast(
+        &self,
+        game_id: &str,
+        message: &Outbound,
+        exclude_session_id: &str,
+    ) -> Result<()> {
+        self.registry
+            .broadcast_except(game_id, message, exclude_session_id);
+        Ok(())
+    }
+
+    async fn control(&self, message: &ControlMessage) -> Result<()> {
+        message.apply(&self.registry);
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        mut shutdown: watch::Receiver<bool>,
+        _game_events: UnboundedReceiver<String>,
+    ) {
+        let _ = shutdown.wait_for(|stop| *stop).await;
+    }
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,120 @@
+use std::str::FromStr;
+
+use actix_web::{
+    http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL},
+    HttpRequest,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Wire format of a socket, chosen when it is opened. Messages are the same
+/// `WsClientMessage`/`WsServerMessage` shapes in every format; binary formats
+/// travel in binary frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "msgpack" => Ok(Self::MessagePack),
+            "cbor" => Ok(Self::Cbor),
+            other => anyhow::bail!(
+                "Unknown encoding {:?}, expected json, msgpack or cbor",
+                other
+            ),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EncodingQuery {
+    encoding: Option<String>,
+}
+
+/// A frame received from a client, before decoding.
+#[derive(Debug, Clone, Copy)]
+pub enum ClientFrame<'a> {
+    Text(&'a str),
+    Binary(&'a [u8]),
+}
+
+impl Encoding {
+    /// Name used both as the `encoding` query parameter and as the WebSocket
+    /// subprotocol.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        *self != Encoding::Json
+    }
+
+    /// Picks the encoding of a new socket from the `encoding` query parameter
+    /// or, failing that, the first known entry of `Sec-WebSocket-Protocol`.
+    /// Returns the subprotocol to confirm in the handshake response, if the
+    /// choice came from there.
+    pub fn from_request(req: &HttpRequest) -> anyhow::Result<(Self, Option<HeaderValue>)> {
+        let query = actix_web::web::Query::<EncodingQuery>::from_query(req.query_string())
+            .map_err(|e| anyhow::anyhow!("Invalid query string: {}", e))?;
+
+        if let Some(encoding) = &query.encoding {
+            return Ok((encoding.parse()?, None));
+        }
+
+        let offered = req
+            .headers()
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim);
+
+        for name in offered {
+            if let Ok(encoding) = name.parse::<Self>() {
+                return Ok((encoding, Some(HeaderValue::from_static(encoding.name()))));
+            }
+        }
+
+        Ok((Self::Json, None))
+    }
+
+    /// Decodes a client frame into a generic value. Text frames are always
+    /// JSON.
+    pub fn decode(&self, frame: ClientFrame<'_>) -> anyhow::Result<Value> {
+        Ok(match (frame, self) {
+            (ClientFrame::Text(text), _) => serde_json::from_str(text)?,
+            (ClientFrame::Binary(bytes), Encoding::Json) => serde_json::from_slice(bytes)?,
+            (ClientFrame::Binary(bytes), Encoding::MessagePack) => rmp_serde::from_slice(bytes)?,
+            (ClientFrame::Binary(bytes), Encoding::Cbor) => ciborium::from_reader(bytes)?,
+        })
+    }
+
+    /// Re-encodes an outgoing JSON message for a binary socket.
+    pub fn encode_binary(&self, json: &str) -> anyhow::Result<Vec<u8>> {
+        if *self == Encoding::Json {
+            return Ok(json.as_bytes().to_vec());
+        }
+
+        let value: Value = serde_json::from_str(json)?;
+
+        Ok(match self {
+            Encoding::MessagePack => rmp_serde::to_vec_named(&value)?,
+            _ => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(&value, &mut bytes)?;
+                bytes
+            }
+        })
+    }
+}
@@ -0,0 +1,305 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::keys::Keys;
+use crate::session_queue::OverflowPolicy;
+
+/// Tunables for WebSocket sessions. In a config file, durations are whole
+/// seconds (`_secs`) or milliseconds (`_ms`) and every field is optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WsConfig {
+    /// How often the server pings each client.
+    #[serde(rename = "heartbeat_interval_secs", deserialize_with = "secs")]
+    pub heartbeat_interval: Duration,
+    /// A session that has not been heard from for this long is reaped.
+    #[serde(rename = "client_timeout_secs", deserialize_with = "secs")]
+    pub client_timeout: Duration,
+    /// Messages buffered per session before the overflow policy kicks in.
+    pub outbound_queue_capacity: usize,
+    #[serde(deserialize_with = "parsed")]
+    pub overflow_policy: OverflowPolicy,
+    pub chat: ChatConfig,
+    /// How long a shutdown waits for sessions to drain.
+    #[serde(rename = "shutdown_deadline_secs", deserialize_with = "secs")]
+    pub shutdown_deadline: Duration,
+    /// Suggested delay before clients reconnect after a shutdown notice.
+    #[serde(rename = "reconnect_hint_secs", deserialize_with = "secs")]
+    pub reconnect_hint: Duration,
+    /// Names of the Redis keys and channels, under an optional prefix.
+    #[serde(rename = "key_prefix", deserialize_with = "key_prefix")]
+    pub keys: Keys,
+    pub redis: RedisConfig,
+    /// How long Redis keeps a game, its events and its chat after the last
+    /// change.
+    #[serde(rename = "game_ttl_secs", deserialize_with = "secs")]
+    pub game_ttl: Duration,
+    /// Value of the `instance` label on every metric. Defaults to the
+    /// host name, so series carry on across restarts.
+    pub instance_id: String,
+}
+
+/// Limits for in-game chat.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// Longest chat line, in characters.
+    pub max_length: usize,
+    /// Chat lines and emotes a session may send per `rate_window`.
+    pub rate_limit: u32,
+    #[serde(rename = "rate_window_secs", deserialize_with = "secs")]
+    pub rate_window: Duration,
+    /// Messages kept per game for players who reconnect.
+    #[serde(rename = "history")]
+    pub history_len: usize,
+    /// Words masked out of chat lines, matched case-insensitively.
+    pub blocklist: Vec<String>,
+}
+
+/// Timeouts of the shared Redis connections.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// How long opening a connection may take.
+    #[serde(rename = "connect_timeout_ms", deserialize_with = "millis")]
+    pub connect_timeout: Duration,
+    /// How long a command may wait for its reply.
+    #[serde(rename = "response_timeout_ms", deserialize_with = "millis")]
+    pub response_timeout: Duration,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(5),
+            client_timeout: Duration::from_secs(15),
+            outbound_queue_capacity: 64,
+            overflow_policy: OverflowPolicy::CoalesceState,
+            chat: ChatConfig::default(),
+            shutdown_deadline: Duration::from_secs(10),
+            reconnect_hint: Duration::from_secs(2),
+            keys: Keys::default(),
+            redis: RedisConfig::default(),
+            game_ttl: Duration::from_secs(3600),
+            instance_id: host_name(),
+        }
+    }
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 200,
+            rate_limit: 5,
+            rate_window: Duration::from_secs(10),
+            history_len: 50,
+            blocklist: Vec::new(),
+        }
+    }
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(2),
+            response_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl WsConfig {
+    /// Overrides fields with `WS_HEARTBEAT_INTERVAL_SECS`,
+    /// `WS_CLIENT_TIMEOUT_SECS`, `WS_OUTBOUND_QUEUE_CAPACITY`,
+    /// `WS_OVERFLOW_POLICY`, the `WS_CHAT_*` limits,
+    /// `WS_SHUTDOWN_DEADLINE_SECS`, `WS_RECONNECT_HINT_SECS`,
+    /// `WS_GAME_TTL_SECS`, `INSTANCE_ID`, `REDIS_KEY_PREFIX` and the
+    /// `REDIS_*` connection settings, keeping the current values for unset
+    /// ones.
+    pub fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Some(secs) = env_secs("WS_HEARTBEAT_INTERVAL_SECS")? {
+            self.heartbeat_interval = secs;
+        }
+        if let Some(secs) = env_secs("WS_CLIENT_TIMEOUT_SECS")? {
+            self.client_timeout = secs;
+        }
+
+        if let Some(capacity) = env_count("WS_OUTBOUND_QUEUE_CAPACITY")? {
+            self.outbound_queue_capacity = capacity;
+        }
+        if let Ok(value) = std::env::var("WS_OVERFLOW_POLICY") {
+            self.overflow_policy = value.parse()?;
+        }
+
+        if let Some(max_length) = env_count("WS_CHAT_MAX_LENGTH")? {
+            self.chat.max_length = max_length;
+        }
+        if let Some(rate_limit) = env_count("WS_CHAT_RATE_LIMIT")? {
+            self.chat.rate_limit = rate_limit;
+        }
+        if let Some(secs) = env_secs("WS_CHAT_RATE_WINDOW_SECS")? {
+            self.chat.rate_window = secs;
+        }
+        if let Some(history_len) = env_count("WS_CHAT_HISTORY")? {
+            self.chat.history_len = history_len;
+        }
+        if let Ok(value) = std::env::var("WS_CHAT_BLOCKLIST") {
+            self.chat.blocklist = value.split(',').map(str::to_string).collect();
+        }
+
+        if let Some(secs) = env_secs("WS_SHUTDOWN_DEADLINE_SECS")? {
+            self.shutdown_deadline = secs;
+        }
+        if let Some(secs) = env_secs("WS_RECONNECT_HINT_SECS")? {
+            self.reconnect_hint = secs;
+        }
+        if let Some(secs) = env_secs("WS_GAME_TTL_SECS")? {
+            self.game_ttl = secs;
+        }
+        if let Ok(instance_id) = std::env::var("INSTANCE_ID") {
+            self.instance_id = instance_id;
+        }
+
+        if let Ok(prefix) = std::env::var("REDIS_KEY_PREFIX") {
+            self.keys = prefixed_keys(&prefix);
+        }
+        if let Some(millis) = env_millis("REDIS_CONNECT_TIMEOUT_MS")? {
+            self.redis.connect_timeout = millis;
+        }
+        if let Some(millis) = env_millis("REDIS_RESPONSE_TIMEOUT_MS")? {
+            self.redis.response_timeout = millis;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the settings against each other and for values that must be
+    /// positive, whether they came from a file or the environment, and
+    /// tidies the blocklist.
+    pub fn validate(&mut self) -> anyhow::Result<()> {
+        if self.heartbeat_interval.is_zero() {
+            anyhow::bail!("WS_HEARTBEAT_INTERVAL_SECS must be a positive number of seconds");
+        }
+        if self.client_timeout <= self.heartbeat_interval {
+            anyhow::bail!("WS_CLIENT_TIMEOUT_SECS must be greater than WS_HEARTBEAT_INTERVAL_SECS");
+        }
+        if self.outbound_queue_capacity == 0 {
+            anyhow::bail!("WS_OUTBOUND_QUEUE_CAPACITY must be a positive number");
+        }
+        if self.chat.max_length == 0 {
+            anyhow::bail!("WS_CHAT_MAX_LENGTH must be a positive number");
+        }
+        if self.chat.rate_limit == 0 {
+            anyhow::bail!("WS_CHAT_RATE_LIMIT must be a positive number");
+        }
+        if self.chat.rate_window.is_zero() {
+            anyhow::bail!("WS_CHAT_RATE_WINDOW_SECS must be a positive number of seconds");
+        }
+        if self.chat.history_len == 0 {
+            anyhow::bail!("WS_CHAT_HISTORY must be a positive number");
+        }
+        if self.game_ttl.is_zero() {
+            anyhow::bail!("WS_GAME_TTL_SECS must be a positive number of seconds");
+        }
+        if self.redis.connect_timeout.is_zero() {
+            anyhow::bail!("REDIS_CONNECT_TIMEOUT_MS must be a positive number of milliseconds");
+        }
+        if self.redis.response_timeout.is_zero() {
+            anyhow::bail!("REDIS_RESPONSE_TIMEOUT_MS must be a positive number of milliseconds");
+        }
+        if self.instance_id.trim().is_empty() {
+            anyhow::bail!("INSTANCE_ID must not be empty");
+        }
+
+        self.chat.blocklist = self
+            .chat
+            .blocklist
+            .iter()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        Ok(())
+    }
+}
+
+/// `HOSTNAME` if exported, else the system's host name. Shells often don't
+/// export `HOSTNAME`, e.g. under systemd.
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Keys under `prefix`, with a `:` separator appended unless it is empty.
+fn prefixed_keys(prefix: &str) -> Keys {
+    let prefix = prefix.trim();
+
+    if prefix.is_empty() {
+        Keys::default()
+    } else {
+        Keys::new(format!("{}:", prefix.trim_end_matches(':')))
+    }
+}
+
+/// A count from the environment. `validate` checks the ones that must be
+/// positive.
+fn env_count<T: FromStr>(key: &str) -> anyhow::Result<Option<T>> {
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("{} must be a whole number", key)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_secs(key: &str) -> anyhow::Result<Option<Duration>> {
+    match std::env::var(key) {
+        Ok(value) => {
+            let secs: u64 = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{} must be a whole number of seconds", key))?;
+            Ok(Some(Duration::from_secs(secs)))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_millis(key: &str) -> anyhow::Result<Option<Duration>> {
+    match std::env::var(key) {
+        Ok(value) => {
+            let millis: u64 = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{} must be a whole number of milliseconds", key))?;
+            Ok(Some(Duration::from_millis(millis)))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+fn key_prefix<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Keys, D::Error> {
+    String::deserialize(deserializer).map(|prefix| prefixed_keys(&prefix))
+}
+
+fn parsed<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
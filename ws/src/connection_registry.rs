@@ -1,44 +1,212 @@
+use std::sync::Arc;
+
+use actix_ws::CloseReason;
 use dashmap::DashMap;
+use serde::Serialize;
 use tokio::sync::mpsc::UnboundedSender;
-pub type SessionTx = UnboundedSender<String>;
+use uuid::Uuid;
+
+use crate::session_queue::{Outbound, SessionQueue};
+
+pub type SessionTx = Arc<SessionQueue>;
 
 #[derive(Clone)]
+pub struct SessionHandle {
+    pub user_id: Uuid,
+    pub queue: SessionTx,
+}
+
+/// Per-session diagnostics.
+#[derive(Debug, Serialize)]
+pub struct SessionStats {
+    pub game_id: String,
+    pub session_id: String,
+    pub user_id: Uuid,
+    pub queue_depth: usize,
+    pub dropped_messages: u64,
+}
+
+#[derive(Clone, Default)]
 pub struct ConnectionRegistry {
-    pub games: DashMap<String, DashMap<String, SessionTx>>,
+    pub games: DashMap<String, DashMap<String, SessionHandle>>,
+    /// Told about every game that gains its first local session or loses its
+    /// last one, so the Redis subscription can follow.
+    game_events: Option<UnboundedSender<String>>,
 }
 
 impl ConnectionRegistry {
     pub fn new() -> Self {
         Self {
             games: DashMap::new(),
+            game_events: None,
+        }
+    }
+
+    pub fn with_game_events(game_events: UnboundedSender<String>) -> Self {
+        Self {
+            games: DashMap::new(),
+            game_events: Some(game_events),
         }
     }
 
-    pub fn add(&self, game_id: &str, session_id: &str, tx: SessionTx) {
-        let game = self.games.entry(game_id.to_string()).or_default();
-        game.insert(session_id.to_string(), tx);
+    pub fn add(&self, game_id: &str, session_id: &str, user_id: Uuid, queue: SessionTx) {
+        let mut first = false;
+        let game = self.games.entry(game_id.to_string()).or_insert_with(|| {
+            first = true;
+            DashMap::new()
+        });
+        game.insert(session_id.to_string(), SessionHandle { user_id, queue });
+        drop(game);
+
+        if first {
+            self.game_changed(game_id);
+        }
     }
 
     pub fn remove(&self, game_id: &str, session_id: &str) {
         if let Some(game) = self.games.get_mut(game_id) {
-            game.remove(session_id);
+            if let Some((_, session)) = game.remove(session_id) {
+                session.queue.close();
+            }
 
-            if game.is_empty() {
-                drop(game);
-                self.games.remove(game_id);
+            drop(game);
+            if self
+                .games
+                .remove_if(game_id, |_, game| game.is_empty())
+                .is_some()
+            {
+                self.game_changed(game_id);
             }
         }
     }
 
-    pub fn broadcast_except(&self, game_id: &str, msg: &str, exclude_session_id: &str) {
+    fn game_changed(&self, game_id: &str) {
+        if let Some(game_events) = &self.game_events {
+            let _ = game_events.send(game_id.to_string());
+        }
+    }
+
+    /// Whether `user_id` still has a local session in the game.
+    pub fn has_user_session(&self, game_id: &str, user_id: Uuid) -> bool {
+        self.games
+            .get(game_id)
+            .is_some_and(|game| game.iter().any(|s| s.user_id == user_id))
+    }
+
+    /// Sends a message to a single local session, if it is still connected.
+    pub fn send(&self, game_id: &str, session_id: &str, msg: Outbound) {
+        if let Some(game) = self.games.get(game_id) {
+            if let Some(session) = game.get(session_id) {
+                session.queue.push(msg);
+            }
+        }
+    }
+
+    pub fn broadcast_except(&self, game_id: &str, msg: &Outbound, exclude_session_id: &str) {
         if let Some(game) = self.games.get(game_id) {
             for r in game.iter() {
-                let (session_id, tx) = r.pair();
+                let (session_id, session) = r.pair();
 
                 if session_id != exclude_session_id {
-                    let _ = tx.send(msg.to_string());
+                    session.queue.push(msg.clone());
+                    tracing::trace!(%session_id, "Queued for session");
                 }
             }
         }
     }
+
+    /// Closes every local socket held by `user_id` and forgets them. Returns
+    /// how many sessions were closed.
+    pub fn disconnect_user(&self, user_id: Uuid, reason: &CloseReason) -> usize {
+        let mut closed = Vec::new();
+
+        for game in self.games.iter() {
+            for r in game.iter() {
+                let (session_id, session) = r.pair();
+
+                if session.user_id == user_id {
+                    session.queue.push(Outbound::Close(reason.clone()));
+                    closed.push((game.key().clone(), session_id.clone()));
+                }
+            }
+        }
+
+        for (game_id, session_id) in &closed {
+            self.remove(game_id, session_id);
+        }
+
+        closed.len()
+    }
+
+    /// Closes every local socket of `game_id` and forgets them. Returns how
+    /// many sessions were closed.
+    pub fn close_game(&self, game_id: &str, reason: &CloseReason) -> usize {
+        let closed: Vec<String> = match self.games.get(game_id) {
+            Some(game) => game
+                .iter()
+                .map(|session| {
+                    session.queue.push(Outbound::Close(reason.clone()));
+                    session.key().clone()
+                })
+                .collect(),
+            None => return 0,
+        };
+
+        for session_id in &closed {
+            self.remove(game_id, session_id);
+        }
+
+        closed.len()
+    }
+
+    /// Queues `notice` and then a close frame on every local session. The
+    /// sessions stay registered until their sockets are actually gone.
+    /// Returns how many sessions are being closed.
+    pub fn close_all(&self, notice: &Outbound, reason: &CloseReason) -> usize {
+        let mut closing = 0;
+
+        for game in self.games.iter() {
+            for session in game.iter() {
+                session.queue.push(notice.clone());
+                session.queue.push(Outbound::Close(reason.clone()));
+                closing += 1;
+            }
+        }
+
+        closing
+    }
+
+    /// Games with at least one local session.
+    pub fn game_ids(&self) -> Vec<String> {
+        self.games.iter().map(|game| game.key().clone()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// Open local sessions, across games.
+    pub fn session_count(&self) -> usize {
+        self.games.iter().map(|game| game.len()).sum()
+    }
+
+    pub fn session_stats(&self) -> Vec<SessionStats> {
+        let mut stats = Vec::new();
+
+        for game in self.games.iter() {
+            for r in game.iter() {
+                let (session_id, session) = r.pair();
+
+                stats.push(SessionStats {
+                    game_id: game.key().clone(),
+                    session_id: session_id.clone(),
+                    user_id: session.user_id,
+                    queue_depth: session.queue.len(),
+                    dropped_messages: session.queue.dropped(),
+                });
+            }
+        }
+
+        stats
+    }
 }
@@ -0,0 +1,399 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use redis::AsyncTypedCommands;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::{ChatConfig, WsConfig};
+use crate::game::messages::WsServerMessage;
+use crate::keys::Keys;
+use crate::redis_conn::RedisConnection;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Emote {
+    Wave,
+    ThumbsUp,
+    Laugh,
+    Think,
+    Oops,
+    Gg,
+}
+
+/// A chat line or emote in a game's history, with the user who sent it so
+/// mutes apply to history too. The id never goes out to clients.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatRecord {
+    pub sender: Uuid,
+    pub message: WsServerMessage,
+}
+
+/// Where per-game chat state is kept: a capped history for reconnecting
+/// players, and who muted whom.
+#[async_trait]
+pub trait ChatStore: Send + Sync {
+    /// Appends a serialized `ChatRecord`, keeping the newest `keep`.
+    async fn append(&self, game_id: &str, record: &str, keep: usize) -> Result<()>;
+
+    /// Stored records of a game, oldest first.
+    async fn history(&self, game_id: &str) -> Result<Vec<String>>;
+
+    async fn set_muted(&self, game_id: &str, muter: Uuid, muted: Uuid, on: bool) -> Result<()>;
+
+    /// Users `muter` doesn't want to hear from in this game.
+    async fn muted(&self, game_id: &str, muter: Uuid) -> Result<HashSet<Uuid>>;
+
+    /// Forgets the history and mutes of a game.
+    async fn clear(&self, game_id: &str) -> Result<()>;
+}
+
+/// Chat state in Redis, shared by every instance. History and mutes expire
+/// with the game they belong to.
+pub struct RedisChatStore {
+    redis: RedisConnection,
+    keys: Keys,
+    ttl: Duration,
+}
+
+impl RedisChatStore {
+    pub fn new(redis: RedisConnection, keys: Keys, ttl: Duration) -> Self {
+        Self { redis, keys, ttl }
+    }
+
+    pub fn open(redis_url: &str, config: &WsConfig) -> Result<Self> {
+        let redis = RedisConnection::open(redis_url, config.redis.clone())
+            .context("Failed to create redis client for chat")?;
+
+        Ok(Self::new(redis, config.keys.clone(), config.game_ttl))
+    }
+}
+
+#[async_trait]
+impl ChatStore for RedisChatStore {
+    async fn append(&self, game_id: &str, record: &str, keep: usize) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+        let key = self.keys.chat_history(game_id);
+
+        conn.rpush(&key, record).await?;
+        conn.ltrim(&key, -(keep as isize), -1).await?;
+        conn.expire(&key, self.ttl.as_secs() as i64).await?;
+
+        Ok(())
+    }
+
+    async fn history(&self, game_id: &str) -> Result<Vec<String>> {
+        let mut conn = self.redis.get().await?;
+
+        Ok(conn.lrange(self.keys.chat_history(game_id), 0, -1).await?)
+    }
+
+    async fn set_muted(&self, game_id: &str, muter: Uuid, muted: Uuid, on: bool) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+        let key = self.keys.chat_muted(game_id, &muter.to_string());
+
+        if on {
+            conn.sadd(&key, muted.to_string()).await?;
+            conn.expire(&key, self.ttl.as_secs() as i64).await?;
+        } else {
+            conn.srem(&key, muted.to_string()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn muted(&self, game_id: &str, muter: Uuid) -> Result<HashSet<Uuid>> {
+        let mut conn = self.redis.get().await?;
+        let members = conn
+            .smembers(self.keys.chat_muted(game_id, &muter.to_string()))
+            .await?;
+
+        Ok(members.iter().filter_map(|id| id.parse().ok()).collect())
+    }
+
+    async fn clear(&self, game_id: &str) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+
+        let mut keys: Vec<String> = {
+            let mut scan_conn = conn.clone();
+            let pattern = self.keys.chat_muted(game_id, "*");
+            let iter =
+                redis::AsyncCommands::scan_match::<_, String>(&mut scan_conn, pattern).await?;
+            iter.collect().await
+        };
+        keys.push(self.keys.chat_history(game_id));
+        conn.del(&keys).await?;
+
+        Ok(())
+    }
+}
+
+/// Chat state in process memory. Like `MemoryGameStore`, it isn't shared
+/// between instances and nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryChatStore {
+    history: Mutex<HashMap<String, VecDeque<String>>>,
+    mutes: Mutex<HashMap<(String, Uuid), HashSet<Uuid>>>,
+}
+
+impl MemoryChatStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ChatStore for MemoryChatStore {
+    async fn append(&self, game_id: &str, record: &str, keep: usize) -> Result<()> {
+        let mut history = self.history.lock().unwrap();
+        let lines = history.entry(game_id.to_string()).or_default();
+
+        lines.push_back(record.to_string());
+        while lines.len() > keep {
+            lines.pop_front();
+        }
+
+        Ok(())
+    }
+
+    async fn history(&self, game_id: &str) -> Result<Vec<String>> {
+        let history = self.history.lock().unwrap();
+
+        Ok(history
+            .get(game_id)
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn set_muted(&self, game_id: &str, muter: Uuid, muted: Uuid, on: bool) -> Result<()> {
+        let mut mutes = self.mutes.lock().unwrap();
+        let set = mutes.entry((game_id.to_string(), muter)).or_default();
+
+        if on {
+            set.insert(muted);
+        } else {
+            set.remove(&muted);
+        }
+
+        Ok(())
+    }
+
+    async fn muted(&self, game_id: &str, muter: Uuid) -> Result<HashSet<Uuid>> {
+        let mutes = self.mutes.lock().unwrap();
+
+        Ok(mutes
+            .get(&(game_id.to_string(), muter))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn clear(&self, game_id: &str) -> Result<()> {
+        self.history.lock().unwrap().remove(game_id);
+        self.mutes
+            .lock()
+            .unwrap()
+            .retain(|(game, _), _| game != game_id);
+
+        Ok(())
+    }
+}
+
+/// Validates chat lines and keeps per-game chat state in a `ChatStore`.
+/// Rate limits and mutes are per user, shared by all their sockets on this
+/// instance.
+pub struct ChatManager {
+    store: Arc<dyn ChatStore>,
+    config: ChatConfig,
+    limiters: Mutex<HashMap<Uuid, RateLimiter>>,
+    /// Mutes of users with a socket here, by game and muter, so writers can
+    /// skip chat without asking the store.
+    muted: RwLock<HashMap<(String, Uuid), HashSet<Uuid>>>,
+}
+
+impl ChatManager {
+    pub fn new(store: Arc<dyn ChatStore>, config: ChatConfig) -> Self {
+        Self {
+            store,
+            config,
+            limiters: Mutex::new(HashMap::new()),
+            muted: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Trims and length-checks a chat line and masks blocklisted words.
+    pub fn sanitize(&self, text: &str) -> Result<String, String> {
+        let text = text.trim();
+
+        if text.is_empty() {
+            return Err("Chat message is empty".to_string());
+        }
+        if text.chars().count() > self.config.max_length {
+            return Err(format!(
+                "Chat message is longer than {} characters",
+                self.config.max_length
+            ));
+        }
+
+        Ok(self.filter(text))
+    }
+
+    /// Masks every blocklisted word that stands on its own, whatever
+    /// surrounds it, but not one inside a longer word.
+    fn filter(&self, text: &str) -> String {
+        // ASCII lowercasing keeps byte offsets, so matches index `text` too.
+        let lower = text.to_ascii_lowercase();
+        let mut masked = vec![false; text.len()];
+
+        for banned in &self.config.blocklist {
+            for (start, _) in lower.match_indices(banned.as_str()) {
+                let end = start + banned.len();
+                let before = text[..start].chars().next_back();
+                let after = text[end..].chars().next();
+
+                if !matches!(before, Some(c) if c.is_alphanumeric())
+                    && !matches!(after, Some(c) if c.is_alphanumeric())
+                {
+                    masked[start..end].fill(true);
+                }
+            }
+        }
+
+        text.char_indices()
+            .map(|(i, c)| if masked[i] { '*' } else { c })
+            .collect()
+    }
+
+    /// Counts a chat message or emote by `user_id`, returning whether it is
+    /// within the rate limit.
+    pub fn allow(&self, user_id: Uuid) -> bool {
+        let mut limiters = self.limiters.lock().unwrap();
+        limiters.retain(|_, limiter| !limiter.is_idle());
+
+        limiters
+            .entry(user_id)
+            .or_insert_with(|| RateLimiter::new(self.config.rate_limit, self.config.rate_window))
+            .check()
+    }
+
+    /// Appends a chat message to the history, keeping the newest ones.
+    pub async fn append_history(&self, game_id: &str, record: &ChatRecord) -> Result<()> {
+        let record = serde_json::to_string(record)?;
+
+        self.store
+            .append(game_id, &record, self.config.history_len)
+            .await
+    }
+
+    /// Recent chat messages of a game, oldest first. Entries that don't
+    /// parse, such as ones stored by older servers, are skipped.
+    pub async fn history(&self, game_id: &str) -> Result<Vec<ChatRecord>> {
+        let records = self.store.history(game_id).await?;
+
+        Ok(records
+            .iter()
+            .filter_map(|record| serde_json::from_str(record).ok())
+            .collect())
+    }
+
+    pub async fn set_muted(&self, game_id: &str, muter: Uuid, muted: Uuid, on: bool) -> Result<()> {
+        self.store.set_muted(game_id, muter, muted, on).await?;
+
+        let mut cached = self.muted.write().unwrap();
+        let set = cached.entry((game_id.to_string(), muter)).or_default();
+        if on {
+            set.insert(muted);
+        } else {
+            set.remove(&muted);
+        }
+
+        Ok(())
+    }
+
+    /// Users `muter` doesn't want to hear from in this game, as stored.
+    /// `is_muted` answers from what this returned last.
+    pub async fn muted(&self, game_id: &str, muter: Uuid) -> Result<HashSet<Uuid>> {
+        let muted = self.store.muted(game_id, muter).await?;
+        self.muted
+            .write()
+            .unwrap()
+            .insert((game_id.to_string(), muter), muted.clone());
+
+        Ok(muted)
+    }
+
+    /// Whether `muter` muted `sender` in this game, without a store round
+    /// trip.
+    pub fn is_muted(&self, game_id: &str, muter: Uuid, sender: Uuid) -> bool {
+        self.muted
+            .read()
+            .unwrap()
+            .get(&(game_id.to_string(), muter))
+            .is_some_and(|muted| muted.contains(&sender))
+    }
+
+    /// Forgets the mutes `is_muted` knows of `muter`, once they have no
+    /// socket left in the game here.
+    pub fn forget_muted(&self, game_id: &str, muter: Uuid) {
+        self.muted
+            .write()
+            .unwrap()
+            .remove(&(game_id.to_string(), muter));
+    }
+
+    /// Drops what was said in an earlier game with the same id.
+    pub async fn clear(&self, game_id: &str) -> Result<()> {
+        self.store.clear(game_id).await?;
+        self.muted
+            .write()
+            .unwrap()
+            .retain(|(game, _), _| game != game_id);
+
+        Ok(())
+    }
+}
+
+/// Allows `limit` messages per fixed `window`.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    window_start: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Counts a message, returning whether it is within the limit.
+    pub fn check(&mut self) -> bool {
+        if self.window_start.elapsed() >= self.window {
+            self.window_start = Instant::now();
+            self.count = 0;
+        }
+
+        if self.count >= self.limit {
+            return false;
+        }
+
+        self.count += 1;
+        true
+    }
+
+    /// Whether the current window is over. An idle limiter counts like a
+    /// new one.
+    pub fn is_idle(&self) -> bool {
+        self.window_start.elapsed() >= self.window
+    }
+}
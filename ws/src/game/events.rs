@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::{
+    streams::{StreamId, StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply},
+    AsyncCommands, Pipeline,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::game_state::{GameStatus, Player},
+    keys::Keys,
+    redis_conn::RedisConnection,
+};
+
+/// Entries kept per game. A game has at most a dozen, this only guards
+/// against runaway writers.
+pub(crate) const EVENTS_MAXLEN: usize = 1000;
+
+/// Entries read per game and poll of a consumer group.
+const READ_COUNT: usize = 100;
+
+/// Something that happened to a game, as appended to its event stream.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    Created {
+        player: String,
+        rated: bool,
+    },
+    Joined {
+        player: String,
+    },
+    Moved {
+        position: usize,
+        symbol: Player,
+    },
+    Finished {
+        status: GameStatus,
+        winner: Option<Player>,
+    },
+}
+
+/// An event with the id of its stream entry, which orders the events of a
+/// game and lets readers resume after it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoggedEvent {
+    pub id: String,
+    pub event: GameEvent,
+}
+
+impl LoggedEvent {
+    fn from_entry(entry: &StreamId) -> Result<Self> {
+        let json: String = entry.get("event").context("Stream entry has no event")?;
+
+        Ok(Self {
+            id: entry.id.clone(),
+            event: serde_json::from_str(&json)?,
+        })
+    }
+}
+
+/// An entry handed to a consumer group. `seq` is the one the game reached
+/// with the event, missing on entries written by older servers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEvent {
+    pub game_id: String,
+    pub id: String,
+    pub seq: Option<u64>,
+}
+
+/// Consumer groups over the event streams of games. Each instance reads
+/// through a group of its own, so it sees every event of the games it
+/// follows, and an entry stays pending until acknowledged.
+#[async_trait]
+pub trait EventSource: Send + Sync {
+    /// Creates `group` on the stream of a game, reading from its first
+    /// entry. Returns false if the game has no stream yet.
+    async fn join(&self, game_id: &str, group: &str) -> Result<bool>;
+
+    /// Deletes `group` from the stream of a game, with its pending entries.
+    async fn leave(&self, game_id: &str, group: &str) -> Result<()>;
+
+    /// Entries of the games for `group`: first the ones it was handed
+    /// before but never acknowledged, then new ones.
+    async fn read(&self, group: &str, game_ids: &[String]) -> Result<Vec<GroupEvent>>;
+
+    async fn ack(&self, game_id: &str, group: &str, ids: &[String]) -> Result<()>;
+}
+
+/// Per-game Redis Streams of `GameEvent`s. Unlike pub/sub, entries stay
+/// around: late joiners replay them, and instances read them through
+/// consumer groups with at-least-once delivery.
+#[derive(Clone)]
+pub struct EventLog {
+    redis: RedisConnection,
+    keys: Keys,
+}
+
+impl EventLog {
+    pub fn new(redis: RedisConnection, keys: Keys) -> Self {
+        Self { redis, keys }
+    }
+
+    /// Adds the command appending `event` to `pipe`, so events are written
+    /// in the same transaction as the state they describe. The caller
+    /// expires the stream with its game.
+    pub fn append_to(
+        &self,
+        pipe: &mut Pipeline,
+        game_id: &str,
+        seq: u64,
+        event: &GameEvent,
+    ) -> Result<()> {
+        let json = serde_json::to_string(event)?;
+
+        pipe.xadd_maxlen(
+            self.keys.game_events(game_id),
+            StreamMaxlen::Approx(EVENTS_MAXLEN),
+            "*",
+            &[("event", json), ("seq", seq.to_string())],
+        )
+        .ignore();
+
+        Ok(())
+    }
+
+    /// Events of a game, oldest first, starting after the entry `after` or
+    /// from the beginning.
+    pub async fn replay(&self, game_id: &str, after: Option<&str>) -> Result<Vec<LoggedEvent>> {
+        let mut conn = self.redis.get().await?;
+        let start = match after {
+            Some(id) => format!("({}", id),
+            None => "-".to_string(),
+        };
+
+        let reply: StreamRangeReply = conn
+            .xrange(self.keys.game_events(game_id), start, "+")
+            .await?;
+
+        reply.ids.iter().map(LoggedEvent::from_entry).collect()
+    }
+}
+
+#[async_trait]
+impl EventSource for EventLog {
+    async fn join(&self, game_id: &str, group: &str) -> Result<bool> {
+        let mut conn = self.redis.get().await?;
+        let key = self.keys.game_events(game_id);
+
+        // Without MKSTREAM, so an expired stream isn't brought back without
+        // its TTL.
+        let created: redis::RedisResult<()> = conn.xgroup_create(&key, group, "0").await;
+
+        match created {
+            Ok(()) => Ok(true),
+            Err(e) if e.code() == Some("BUSYGROUP") => Ok(true),
+            Err(e) => match conn.exists(&key).await? {
+                true => Err(e.into()),
+                false => Ok(false),
+            },
+        }
+    }
+
+    async fn leave(&self, game_id: &str, group: &str) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+
+        let _: bool = conn
+            .xgroup_destroy(self.keys.game_events(game_id), group)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn read(&self, group: &str, game_ids: &[String]) -> Result<Vec<GroupEvent>> {
+        if game_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.redis.get().await?;
+        let games: HashMap<String, &String> = game_ids
+            .iter()
+            .map(|game_id| (self.keys.game_events(game_id), game_id))
+            .collect();
+        let streams: Vec<&String> = games.keys().collect();
+        let options = StreamReadOptions::default()
+            .group(group, group)
+            .count(READ_COUNT);
+
+        let mut events = Vec::new();
+        for from in ["0", ">"] {
+            let ids = vec![from; streams.len()];
+            let reply: Option<StreamReadReply> =
+                conn.xread_options(&streams, &ids, &options).await?;
+
+            for stream in reply.map(|reply| reply.keys).unwrap_or_default() {
+                let game_id = games.get(&stream.key).context("Unexpected stream")?;
+                for entry in &stream.ids {
+                    events.push(GroupEvent {
+                        game_id: (*game_id).clone(),
+                        id: entry.id.clone(),
+                        seq: entry.get("seq"),
+                    });
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    async fn ack(&self, game_id: &str, group: &str, ids: &[String]) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+
+        let _: i64 = conn
+            .xack(self.keys.game_events(game_id), group, ids)
+            .await?;
+
+        Ok(())
+    }
+}
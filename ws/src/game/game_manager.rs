@@ -1,147 +1,181 @@
 use anyhow::{Context, Result};
-use redis::{AsyncTypedCommands, Client};
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::game::{
+    events::{GameEvent, LoggedEvent},
     game_logic::GameEngine,
     game_state::{GameState, GameStatus, Player},
+    store::GameStore,
 };
 
 #[derive(Clone)]
 pub struct GameManager {
-    redis_client: Arc<Client>,
+    store: Arc<dyn GameStore>,
 }
 
 impl GameManager {
-    pub fn new(redis_client: Arc<Client>) -> Self {
-        Self { redis_client }
+    pub fn new(store: Arc<dyn GameStore>) -> Self {
+        Self { store }
     }
 
+    #[tracing::instrument(skip(self, player1_name, player1_session))]
     pub async fn create_game(
         &self,
         player1_id: Uuid,
+        player1_name: String,
         player1_session: String,
         game_id: String,
+        rated: bool,
     ) -> Result<GameState> {
-        let game = GameState::new(game_id.clone(), player1_id, player1_session);
+        let game = GameState::new(game_id, player1_id, player1_name, player1_session, rated);
 
-        let mut conn = self.redis_client.get_multiplexed_async_connection().await?;
-
-        let game_json = serde_json::to_string(&game)?;
-        let key = format!("game:{}", game_id);
-
-        conn.set_ex(key, game_json, 3600)
-            .await
-            .context("Failed to set key in redis")?;
+        let created = GameEvent::Created {
+            player: game.player1_name.clone(),
+            rated,
+        };
+        self.store.save(&game, &[created]).await?;
 
         Ok(game)
     }
 
     pub async fn get_game(&self, game_id: &str) -> Result<Option<GameState>> {
-        let mut conn = self.redis_client.get_multiplexed_async_connection().await?;
-
-        let key = format!("game:{}", game_id);
-        let game_json: Option<String> = conn.get(key).await?;
-
-        match game_json {
-            Some(json) => {
-                let json = serde_json::from_str(&json)?;
-                Ok(json)
-            }
-            None => Ok(None),
-        }
+        self.store.get(game_id).await
     }
 
+    #[tracing::instrument(skip(self, player2_name, player2_session))]
     pub async fn join_game(
         &self,
         game_id: &str,
         player2_id: Uuid,
+        player2_name: String,
         player2_session: String,
+        is_guest: bool,
     ) -> Result<GameState> {
-        let mut game = self.get_game(game_id).await?.context("Game not found")?;
+        self.update_game(game_id, |game| {
+            if game.status != GameStatus::Waiting {
+                anyhow::bail!("Game is not waiting for players");
+            }
 
-        if game.status != GameStatus::Waiting {
-            anyhow::bail!("Game is not waiting for players");
-        }
+            if game.rated && is_guest {
+                anyhow::bail!("Guests can only join unrated games");
+            }
 
-        game.player2_id = Some(player2_id);
-        game.player2_session = Some(player2_session);
-        game.status = GameStatus::InProgress;
+            game.player2_id = Some(player2_id);
+            game.player2_name = Some(player2_name.clone());
+            game.player2_session = Some(player2_session.clone());
+            game.status = GameStatus::InProgress;
 
-        self.save_game(&game).await?;
-        Ok(game)
+            Ok(vec![GameEvent::Joined {
+                player: player2_name.clone(),
+            }])
+        })
+        .await
     }
 
+    #[tracing::instrument(skip(self))]
     pub async fn make_move(
         &self,
         game_id: &str,
         user_id: Uuid,
         position: usize,
     ) -> Result<GameState> {
-        let mut game = self.get_game(game_id).await?.context("Game not found")?;
-
-        if game.status != GameStatus::InProgress {
-            anyhow::bail!("Game is not in progress");
-        }
-
-        if !game.is_player_turn(user_id) {
-            anyhow::bail!("Not your turn");
-        }
-
-        let player = game.get_player_symbol(user_id).context("Invalid player")?;
-
-        GameEngine::make_move(&mut game.board, position, player)
-            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-
-        let winner = GameEngine::check_winner(&game.board);
-
-        match winner {
-            Some(Player::X) => {
-                game.status = GameStatus::Finished;
-                game.winner = Some(game.player1_id);
+        self.update_game(game_id, |game| {
+            if game.status != GameStatus::InProgress {
+                anyhow::bail!("Game is not in progress");
             }
-            Some(Player::O) => {
-                game.status = GameStatus::Finished;
-                game.winner = game.player2_id;
+
+            if !game.is_player_turn(user_id) {
+                anyhow::bail!("Not your turn");
             }
-            None if GameEngine::is_board_full(&game.board) => {
-                game.status = GameStatus::Finished;
-                game.winner = None;
+
+            let player = game.get_player_symbol(user_id).context("Invalid player")?;
+
+            GameEngine::make_move(&mut game.board, position, player)
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+            let winner = GameEngine::check_winner(&game.board);
+
+            match winner {
+                Some(Player::X) => {
+                    game.status = GameStatus::Finished;
+                    game.winner = Some(game.player1_id);
+                }
+                Some(Player::O) => {
+                    game.status = GameStatus::Finished;
+                    game.winner = game.player2_id;
+                }
+                None if GameEngine::is_board_full(&game.board) => {
+                    game.status = GameStatus::Finished;
+                    game.winner = None;
+                }
+
+                None => {
+                    let next = if user_id == game.player1_id {
+                        game.player2_id.expect("player2 must exist")
+                    } else {
+                        game.player1_id
+                    };
+                    game.current_turn = Some(next);
+                }
             }
 
-            None => {
-                let next = if user_id == game.player1_id {
-                    game.player2_id.expect("player2 must exist")
-                } else {
-                    game.player1_id
-                };
-                game.current_turn = Some(next);
+            let mut events = vec![GameEvent::Moved {
+                position,
+                symbol: player,
+            }];
+            if game.status == GameStatus::Finished {
+                events.push(GameEvent::Finished {
+                    status: GameStatus::Finished,
+                    winner,
+                });
             }
-        }
 
-        self.save_game(&game).await?;
-        Ok(game)
+            Ok(events)
+        })
+        .await
     }
 
-    async fn save_game(&self, game: &GameState) -> Result<()> {
-        let mut conn = self.redis_client.get_multiplexed_async_connection().await?;
-
-        let key = format!("game:{}", game.id);
-        let game_json = serde_json::to_string(game)?;
+    /// Applies `change` to a stored game, bumping its sequence number, and
+    /// appends the events it returns to the game's log in the same write.
+    async fn update_game<F>(&self, game_id: &str, mut change: F) -> Result<GameState>
+    where
+        F: FnMut(&mut GameState) -> Result<Vec<GameEvent>> + Send,
+    {
+        self.store
+            .update(game_id, &mut |game| {
+                let events = change(game)?;
+                game.seq += 1;
+                Ok(events)
+            })
+            .await
+    }
 
-        conn.set_ex(key, game_json, 3600).await?;
+    /// Lists every game currently stored.
+    pub async fn list_games(&self) -> Result<Vec<GameState>> {
+        self.store.list().await
+    }
 
-        Ok(())
+    /// Ends a game without a winner, e.g. when a moderator steps in.
+    pub async fn end_game(&self, game_id: &str) -> Result<GameState> {
+        self.update_game(game_id, |game| {
+            game.status = GameStatus::Abandoned;
+            game.current_turn = None;
+
+            Ok(vec![GameEvent::Finished {
+                status: GameStatus::Abandoned,
+                winner: None,
+            }])
+        })
+        .await
     }
 
     pub async fn delete_game(&self, game_id: &str) -> Result<()> {
-        let mut conn = self.redis_client.get_multiplexed_async_connection().await?;
-
-        let key = format!("game:{}", game_id);
-
-        conn.del(key).await?;
+        self.store.delete(game_id).await
+    }
 
-        Ok(())
+    /// Logged events of a game, oldest first, after the event `after`.
+    pub async fn events(&self, game_id: &str, after: Option<&str>) -> Result<Vec<LoggedEvent>> {
+        self.store.events(game_id, after).await
     }
 }
@@ -28,6 +28,11 @@ pub struct GameState {
     pub player1_id: Uuid,
     pub player2_id: Option<Uuid>,
 
+    #[serde(default)]
+    pub player1_name: String,
+    #[serde(default)]
+    pub player2_name: Option<String>,
+
     pub player1_session: String,
     pub player2_session: Option<String>,
 
@@ -37,21 +42,39 @@ pub struct GameState {
     pub board: Board,
     pub winner: Option<Uuid>,
     pub created_at: i64,
+
+    #[serde(default)]
+    pub rated: bool,
+
+    /// Bumped on every saved change, so clients applying `move_applied`
+    /// deltas can tell when they missed one.
+    #[serde(default)]
+    pub seq: u64,
 }
 
 impl GameState {
-    pub fn new(game_id: String, player1_id: Uuid, player1_session: String) -> Self {
+    pub fn new(
+        game_id: String,
+        player1_id: Uuid,
+        player1_name: String,
+        player1_session: String,
+        rated: bool,
+    ) -> Self {
         Self {
             id: game_id,
             player1_id,
+            player1_name,
             player1_session,
             player2_id: None,
+            player2_name: None,
             player2_session: None,
             current_turn: Some(player1_id),
             status: GameStatus::Waiting,
             board: [[None; 3]; 3],
             winner: None,
             created_at: chrono::Utc::now().timestamp(),
+            rated,
+            seq: 0,
         }
     }
 
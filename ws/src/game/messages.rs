@@ -1,26 +1,251 @@
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::game::game_state::GameState;
+use crate::{
+    game::{
+        chat::Emote,
+        events::LoggedEvent,
+        game_state::{Board, GameState, GameStatus, Player},
+    },
+    session_queue::Outbound,
+};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum WsClientMessage {
+    /// Optional handshake. Clients that skip it speak protocol version 1.
+    #[serde(rename = "hello")]
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+
     #[serde(rename = "create_game")]
-    CreateGame,
+    CreateGame {
+        #[serde(default)]
+        rated: bool,
+    },
 
     #[serde(rename = "join_game")]
     JoinGame,
 
     #[serde(rename = "make_move")]
     MakeMove { position: usize },
+
+    /// Asks for the full game state, e.g. after a gap in `move_applied`
+    /// sequence numbers.
+    #[serde(rename = "request_snapshot")]
+    RequestSnapshot,
+
+    #[serde(rename = "chat")]
+    Chat { text: String },
+
+    #[serde(rename = "emote")]
+    Emote { emote: Emote },
+
+    /// Stops chat and emotes from the other player reaching this player.
+    #[serde(rename = "mute")]
+    Mute,
+
+    #[serde(rename = "unmute")]
+    Unmute,
+
+    /// Asks for the game's event log, from the start or after the event
+    /// with id `after`.
+    #[serde(rename = "replay_events")]
+    ReplayEvents {
+        #[serde(default)]
+        after: Option<String>,
+    },
+}
+
+impl WsClientMessage {
+    /// The `type` tag, e.g. for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Hello { .. } => "hello",
+            Self::CreateGame { .. } => "create_game",
+            Self::JoinGame => "join_game",
+            Self::MakeMove { .. } => "make_move",
+            Self::RequestSnapshot => "request_snapshot",
+            Self::Chat { .. } => "chat",
+            Self::Emote { .. } => "emote",
+            Self::Mute => "mute",
+            Self::Unmute => "unmute",
+            Self::ReplayEvents { .. } => "replay_events",
+        }
+    }
+}
+
+/// How a socket takes part in the game it's connected to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ViewerRole {
+    Player,
+    Spectator,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerView {
+    pub symbol: Player,
+    pub username: String,
+}
+
+/// What clients see of a game. Projected from the stored `GameState` per
+/// viewer, so storage can change, and keep session ids, user ids and other
+/// internals to itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameView {
+    pub id: String,
+    pub status: GameStatus,
+    pub board: Board,
+    pub players: Vec<PlayerView>,
+    pub turn: Option<Player>,
+    pub winner: Option<Player>,
+    pub rated: bool,
+    pub seq: u64,
+    pub created_at: i64,
+    pub role: ViewerRole,
+    /// The viewer's own symbol, for players.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub you: Option<Player>,
+}
+
+impl GameView {
+    pub fn project(game: &GameState, viewer: Uuid) -> Self {
+        let mut players = vec![PlayerView {
+            symbol: Player::X,
+            username: game.player1_name.clone(),
+        }];
+        if let Some(username) = &game.player2_name {
+            players.push(PlayerView {
+                symbol: Player::O,
+                username: username.clone(),
+            });
+        }
+
+        let you = game.get_player_symbol(viewer);
+
+        Self {
+            id: game.id.clone(),
+            status: game.status.clone(),
+            board: game.board,
+            players,
+            turn: game.current_turn.and_then(|id| game.get_player_symbol(id)),
+            winner: game.winner.and_then(|id| game.get_player_symbol(id)),
+            rated: game.rated,
+            seq: game.seq,
+            created_at: game.created_at,
+            role: if you.is_some() {
+                ViewerRole::Player
+            } else {
+                ViewerRole::Spectator
+            },
+            you,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum WsServerMessage {
     #[serde(rename = "game_state")]
-    GameState { game: GameState },
+    GameState { game: GameView },
 
+    /// A request failed. `request_id` echoes the failed request's id.
     #[serde(rename = "error")]
-    Error { message: String },
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// The request with this id was applied.
+    #[serde(rename = "ack")]
+    Ack { request_id: String },
+
+    /// Reply to `hello` with what the session will speak from now on.
+    #[serde(rename = "welcome")]
+    Welcome {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+
+    /// A move and what it did to the game. `seq` is the game's sequence
+    /// number after the move.
+    #[serde(rename = "move_applied")]
+    MoveApplied {
+        position: usize,
+        symbol: Player,
+        next_turn: Option<Player>,
+        status: GameStatus,
+        winner: Option<Player>,
+        seq: u64,
+    },
+
+    #[serde(rename = "chat")]
+    Chat {
+        from: String,
+        text: String,
+        sent_at: i64,
+    },
+
+    #[serde(rename = "emote")]
+    Emote {
+        from: String,
+        emote: Emote,
+        sent_at: i64,
+    },
+
+    /// Recent `chat` and `emote` messages, oldest first.
+    #[serde(rename = "chat_history")]
+    ChatHistory { messages: Vec<WsServerMessage> },
+
+    /// Reply to `replay_events`, oldest first.
+    #[serde(rename = "game_events")]
+    GameEvents { events: Vec<LoggedEvent> },
+
+    /// This server is going away. Reconnect after `reconnect_after_ms`, the
+    /// new socket lands on another instance.
+    #[serde(rename = "server_shutdown")]
+    ServerShutdown { reconnect_after_ms: u64 },
+
+    /// A socket of `username` left the game.
+    #[serde(rename = "player_disconnected")]
+    PlayerDisconnected { username: String },
+}
+
+impl WsServerMessage {
+    /// Outbound for a game state. It carries the stored state, each
+    /// session's writer projects it into a `GameView` for its viewer.
+    pub fn state_outbound(game: &GameState) -> serde_json::Result<Outbound> {
+        Ok(Outbound::State(serde_json::to_string(game)?))
+    }
+
+    /// Outbound for a move: a `move_applied` delta for sessions that asked
+    /// for deltas and the resulting full state for everyone else.
+    pub fn move_outbound(
+        game: &GameState,
+        position: usize,
+        symbol: Player,
+    ) -> serde_json::Result<Outbound> {
+        let delta = WsServerMessage::MoveApplied {
+            position,
+            symbol,
+            next_turn: game.current_turn.and_then(|id| game.get_player_symbol(id)),
+            status: game.status.clone(),
+            winner: game.winner.and_then(|id| game.get_player_symbol(id)),
+            seq: game.seq,
+        };
+
+        Ok(Outbound::Delta {
+            delta: serde_json::to_string(&delta)?.into(),
+            state: serde_json::to_string(game)?,
+        })
+    }
+
+    pub fn to_outbound(&self) -> serde_json::Result<Outbound> {
+        Ok(Outbound::Text(serde_json::to_string(self)?.into()))
+    }
 }
@@ -1,4 +1,7 @@
+pub mod chat;
+pub mod events;
 pub mod game_logic;
 pub mod game_manager;
 pub mod game_state;
 pub mod messages;
+pub mod store;
@@ -0,0 +1,295 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use redis::{AsyncTypedCommands, Script};
+
+use crate::{
+    config::WsConfig,
+    game::{
+        events::{EventLog, GameEvent, LoggedEvent, EVENTS_MAXLEN},
+        game_state::GameState,
+    },
+    keys::Keys,
+    redis_conn::RedisConnection,
+};
+
+/// Attempts at an `update` that keeps losing races before giving up.
+const UPDATE_ATTEMPTS: usize = 10;
+
+/// A change to one game: edits it in place and returns the events it caused.
+/// Returning an error leaves the stored game untouched.
+pub type GameChange<'a> = &'a mut (dyn FnMut(&mut GameState) -> Result<Vec<GameEvent>> + Send);
+
+/// Where games and their event logs are kept.
+#[async_trait]
+pub trait GameStore: Send + Sync {
+    async fn get(&self, game_id: &str) -> Result<Option<GameState>>;
+
+    /// Stores `game` as a new game with `events` as its log, in one write.
+    /// A game stored before under the same id is replaced, log included.
+    async fn save(&self, game: &GameState, events: &[GameEvent]) -> Result<()>;
+
+    /// Applies `change` to the stored game and saves the result with its
+    /// events, as if no other update of the game ran in between. `change`
+    /// may run more than once when the store retries after a conflict.
+    async fn update(&self, game_id: &str, change: GameChange<'_>) -> Result<GameState>;
+
+    async fn delete(&self, game_id: &str) -> Result<()>;
+
+    async fn list(&self) -> Result<Vec<GameState>>;
+
+    /// Logged events of a game, oldest first, after the event `after` or
+    /// from the beginning.
+    async fn events(&self, game_id: &str, after: Option<&str>) -> Result<Vec<LoggedEvent>>;
+}
+
+/// Compare-and-set on `seq`, so concurrent updates of a game can't
+/// overwrite each other. KEYS: state, events. ARGV: the `seq` the update
+/// started from, the new state, the TTL, the stream length, the new `seq`,
+/// then events.
+const UPDATE_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if not current or (cjson.decode(current)['seq'] or 0) ~= tonumber(ARGV[1]) then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+for i = 6, #ARGV do
+    redis.call('XADD', KEYS[2], 'MAXLEN', '~', ARGV[4], '*', 'event', ARGV[i], 'seq', ARGV[5])
+end
+if #ARGV > 5 then
+    redis.call('EXPIRE', KEYS[2], ARGV[3])
+end
+return 1
+"#;
+
+/// Games as expiring JSON keys, events as Redis Streams. Shared by every
+/// instance pointed at the same Redis.
+pub struct RedisGameStore {
+    redis: RedisConnection,
+    keys: Keys,
+    /// How long a game is kept after its last change.
+    ttl: Duration,
+    events: EventLog,
+    update_script: Script,
+}
+
+impl RedisGameStore {
+    pub fn new(redis: RedisConnection, keys: Keys, ttl: Duration) -> Self {
+        Self {
+            events: EventLog::new(redis.clone(), keys.clone()),
+            redis,
+            keys,
+            ttl,
+            update_script: Script::new(UPDATE_SCRIPT),
+        }
+    }
+
+    pub fn open(redis_url: &str, config: &WsConfig) -> Result<Self> {
+        let redis = RedisConnection::open(redis_url, config.redis.clone())
+            .context("Failed to create redis client for games")?;
+
+        Ok(Self::new(redis, config.keys.clone(), config.game_ttl))
+    }
+}
+
+#[async_trait]
+impl GameStore for RedisGameStore {
+    async fn get(&self, game_id: &str) -> Result<Option<GameState>> {
+        let mut conn = self.redis.get().await?;
+
+        let game_json = conn.get(self.keys.game_state(game_id)).await?;
+
+        match game_json {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, game: &GameState, events: &[GameEvent]) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+
+        let game_json = serde_json::to_string(game)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .del(self.keys.game_events(&game.id))
+            .ignore()
+            .set_ex(
+                self.keys.game_state(&game.id),
+                game_json,
+                self.ttl.as_secs(),
+            )
+            .ignore();
+        for event in events {
+            self.events
+                .append_to(&mut pipe, &game.id, game.seq, event)?;
+        }
+        if !events.is_empty() {
+            pipe.expire(self.keys.game_events(&game.id), self.ttl.as_secs() as i64)
+                .ignore();
+        }
+        pipe.exec_async(&mut conn)
+            .await
+            .context("Failed to set key in redis")?;
+
+        Ok(())
+    }
+
+    async fn update(&self, game_id: &str, change: GameChange<'_>) -> Result<GameState> {
+        let mut conn = self.redis.get().await?;
+
+        for _ in 0..UPDATE_ATTEMPTS {
+            let mut game = self.get(game_id).await?.context("Game not found")?;
+            let started_at = game.seq;
+            let events = change(&mut game)?;
+
+            let mut invocation = self.update_script.key(self.keys.game_state(game_id));
+            invocation
+                .key(self.keys.game_events(game_id))
+                .arg(started_at)
+                .arg(serde_json::to_string(&game)?)
+                .arg(self.ttl.as_secs())
+                .arg(EVENTS_MAXLEN)
+                .arg(game.seq);
+            for event in &events {
+                invocation.arg(serde_json::to_string(event)?);
+            }
+
+            let saved: i64 = invocation.invoke_async(&mut conn).await?;
+            if saved == 1 {
+                return Ok(game);
+            }
+        }
+
+        anyhow::bail!("Game is changing too fast, try again")
+    }
+
+    async fn delete(&self, game_id: &str) -> Result<()> {
+        let mut conn = self.redis.get().await?;
+
+        conn.del(&[
+            self.keys.game_state(game_id),
+            self.keys.game_events(game_id),
+        ])
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<GameState>> {
+        let mut conn = self.redis.get().await?;
+
+        let keys: Vec<String> = {
+            let mut scan_conn = conn.clone();
+            let pattern = self.keys.game_state_pattern();
+            let iter =
+                redis::AsyncCommands::scan_match::<_, String>(&mut scan_conn, pattern).await?;
+            iter.collect().await
+        };
+
+        let mut games = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(json) = conn.get(key).await? {
+                games.push(serde_json::from_str(&json)?);
+            }
+        }
+
+        Ok(games)
+    }
+
+    async fn events(&self, game_id: &str, after: Option<&str>) -> Result<Vec<LoggedEvent>> {
+        self.events.replay(game_id, after).await
+    }
+}
+
+/// Games in a map inside this process. Nothing is shared with other
+/// instances and nothing survives a restart, which suits tests and
+/// single-node development.
+#[derive(Default)]
+pub struct MemoryGameStore {
+    games: Mutex<HashMap<String, StoredGame>>,
+}
+
+struct StoredGame {
+    state: GameState,
+    /// Event ids count up from 1 per game.
+    events: Vec<LoggedEvent>,
+}
+
+impl StoredGame {
+    fn append(&mut self, events: &[GameEvent]) {
+        for event in events {
+            self.events.push(LoggedEvent {
+                id: (self.events.len() + 1).to_string(),
+                event: event.clone(),
+            });
+        }
+    }
+}
+
+impl MemoryGameStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl GameStore for MemoryGameStore {
+    async fn get(&self, game_id: &str) -> Result<Option<GameState>> {
+        let games = self.games.lock().unwrap();
+
+        Ok(games.get(game_id).map(|game| game.state.clone()))
+    }
+
+    async fn save(&self, game: &GameState, events: &[GameEvent]) -> Result<()> {
+        let mut games = self.games.lock().unwrap();
+
+        let mut stored = StoredGame {
+            state: game.clone(),
+            events: Vec::new(),
+        };
+        stored.append(events);
+        games.insert(game.id.clone(), stored);
+
+        Ok(())
+    }
+
+    async fn update(&self, game_id: &str, change: GameChange<'_>) -> Result<GameState> {
+        let mut games = self.games.lock().unwrap();
+        let stored = games.get_mut(game_id).context("Game not found")?;
+
+        let mut game = stored.state.clone();
+        let events = change(&mut game)?;
+        stored.state = game.clone();
+        stored.append(&events);
+
+        Ok(game)
+    }
+
+    async fn delete(&self, game_id: &str) -> Result<()> {
+        self.games.lock().unwrap().remove(game_id);
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<GameState>> {
+        let games = self.games.lock().unwrap();
+
+        Ok(games.values().map(|game| game.state.clone()).collect())
+    }
+
+    async fn events(&self, game_id: &str, after: Option<&str>) -> Result<Vec<LoggedEvent>> {
+        let after: usize = match after {
+            Some(id) => id.parse().context("Invalid event id")?,
+            None => 0,
+        };
+        let games = self.games.lock().unwrap();
+
+        Ok(games
+            .get(game_id)
+            .map(|game| game.events.iter().skip(after).cloned().collect())
+            .unwrap_or_default())
+    }
+}
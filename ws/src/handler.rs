@@ -1,183 +1,706 @@
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    game::messages::{WsClientMessage, WsServerMessage},
+    codec::{ClientFrame, Encoding},
+    game::{
+        chat::{ChatManager, ChatRecord},
+        events::LoggedEvent,
+        game_state::{GameState, Player},
+        messages::{WsClientMessage, WsServerMessage},
+    },
     manager::WsManager,
+    protocol::{Negotiated, SessionProtocol},
+    session_queue::{Outbound, SessionQueue, SharedText},
 };
 use actix_web::{rt, web, Error, HttpRequest, HttpResponse};
-use actix_ws::Message;
-use futures_util::StreamExt;
-use tokio::sync::mpsc;
+use actix_ws::{CloseCode, CloseReason, Closed, Message, ProtocolError, Session};
+use futures_util::{Stream, StreamExt};
+use tokio::time;
+use tracing::Instrument;
 use uuid::Uuid;
 
+/// How long a closing session waits for its writer to flush the queue.
+const WRITER_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The authenticated user behind a socket.
+#[derive(Clone, Debug)]
+pub struct SessionUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub is_guest: bool,
+}
+
+/// Outgoing half of a socket. Implemented by `actix_ws::Session`, and by
+/// in-memory sinks when driving sessions in tests.
+pub trait SessionSink: Clone + Send + 'static {
+    fn text(&mut self, msg: String) -> impl Future<Output = Result<(), Closed>> + Send;
+    fn binary(&mut self, msg: Vec<u8>) -> impl Future<Output = Result<(), Closed>> + Send;
+    fn ping(&mut self, msg: &[u8]) -> impl Future<Output = Result<(), Closed>> + Send;
+    fn pong(&mut self, msg: &[u8]) -> impl Future<Output = Result<(), Closed>> + Send;
+    fn close(self, reason: Option<CloseReason>) -> impl Future<Output = Result<(), Closed>> + Send;
+}
+
+impl SessionSink for Session {
+    async fn text(&mut self, msg: String) -> Result<(), Closed> {
+        Session::text(self, msg).await
+    }
+
+    async fn binary(&mut self, msg: Vec<u8>) -> Result<(), Closed> {
+        Session::binary(self, msg).await
+    }
+
+    async fn ping(&mut self, msg: &[u8]) -> Result<(), Closed> {
+        Session::ping(self, msg).await
+    }
+
+    async fn pong(&mut self, msg: &[u8]) -> Result<(), Closed> {
+        Session::pong(self, msg).await
+    }
+
+    async fn close(self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        Session::close(self, reason).await
+    }
+}
+
 pub async fn upgrade(
     req: HttpRequest,
     body: web::Payload,
     path_game_id: web::Path<String>,
     manager: web::Data<Arc<WsManager>>,
-    user_id: Uuid,
+    user: SessionUser,
 ) -> anyhow::Result<HttpResponse, Error> {
     let game_id = path_game_id.into_inner();
 
-    let (res, mut session, mut incoming) = actix_ws::handle(&req, body)?;
+    if !manager.is_accepting() {
+        return Err(actix_web::error::ErrorServiceUnavailable(
+            "Server is shutting down",
+        ));
+    }
+
+    let (encoding, subprotocol) =
+        Encoding::from_request(&req).map_err(actix_web::error::ErrorBadRequest)?;
+
+    let (mut res, session, incoming) = actix_ws::handle(&req, body)?;
+
+    if let Some(subprotocol) = subprotocol {
+        res.headers_mut()
+            .insert(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL, subprotocol);
+    }
 
     let session_id = Uuid::new_v4().to_string();
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
 
-    manager.registry.add(&game_id, &session_id, tx.clone());
+    rt::spawn(
+        run_session(
+            manager.get_ref().clone(),
+            game_id,
+            session_id,
+            user,
+            encoding,
+            session,
+            incoming,
+        )
+        .in_current_span(),
+    );
+
+    Ok(res)
+}
+
+/// Drives one socket until it goes away: registers it, spawns its writer,
+/// reads client frames and keeps the heartbeat. On exit the writer is
+/// cancelled, the socket closed with a reason and the game told that the
+/// player left.
+pub async fn run_session<S, I>(
+    manager: Arc<WsManager>,
+    game_id: String,
+    session_id: String,
+    user: SessionUser,
+    encoding: Encoding,
+    session: S,
+    incoming: I,
+) where
+    S: SessionSink,
+    I: Stream<Item = Result<Message, ProtocolError>> + Unpin,
+{
+    let span = tracing::info_span!(
+        "ws_session",
+        user_id = %user.user_id,
+        %game_id,
+        %session_id,
+    );
+
+    drive_session(
+        manager, game_id, session_id, user, encoding, session, incoming,
+    )
+    .instrument(span)
+    .await
+}
+
+async fn drive_session<S, I>(
+    manager: Arc<WsManager>,
+    game_id: String,
+    session_id: String,
+    user: SessionUser,
+    encoding: Encoding,
+    mut session: S,
+    mut incoming: I,
+) where
+    S: SessionSink,
+    I: Stream<Item = Result<Message, ProtocolError>> + Unpin,
+{
+    tracing::info!("WebSocket session started");
+
+    let queue = Arc::new(SessionQueue::new(
+        manager.config.outbound_queue_capacity,
+        manager.config.overflow_policy,
+    ));
+
+    manager
+        .registry
+        .add(&game_id, &session_id, user.user_id, queue.clone());
+
+    let ctx = SessionContext {
+        protocol: Arc::new(SessionProtocol::new(encoding)),
+        manager,
+        game_id,
+        session_id,
+        user,
+    };
+
+    let mut writer = tokio::spawn(
+        write_outbound(
+            queue.clone(),
+            ctx.protocol.clone(),
+            ctx.manager.chat.clone(),
+            ctx.game_id.clone(),
+            ctx.user.user_id,
+            session.clone(),
+        )
+        .in_current_span(),
+    );
+
+    let mut last_heartbeat = Instant::now();
+    let mut heartbeat = time::interval(ctx.manager.config.heartbeat_interval);
 
-    rt::spawn({
-        let manager = manager.clone();
-        let game_id = game_id.clone();
-        let session_id = session_id.clone();
-        let user_id = user_id;
+    let close_reason = loop {
+        tokio::select! {
+            msg = incoming.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        tracing::warn!(error = %e, "WebSocket protocol error");
+                        break CloseReason {
+                            code: CloseCode::Protocol,
+                            description: Some(e.to_string()),
+                        };
+                    }
+                    None => break connection_lost(),
+                };
+
+                // Any frame proves the client is still there.
+                last_heartbeat = Instant::now();
 
-        async move {
-            while let Some(Ok(msg)) = incoming.next().await {
                 match msg {
                     Message::Text(text) => {
-                        if let Err(e) = handle_message(
-                            &manager,
-                            &game_id,
-                            &session_id,
-                            user_id,
-                            text.to_string(),
-                        )
-                        .await
-                        {
-                            eprintln!("Message handling error: {}", e);
+                        if let Err(e) = handle_message(&ctx, ClientFrame::Text(&text)).await {
+                            tracing::error!(error = %e, "Message handling error");
                         }
                     }
+                    Message::Binary(bytes) => {
+                        if let Err(e) = handle_message(&ctx, ClientFrame::Binary(&bytes)).await {
+                            tracing::error!(error = %e, "Message handling error");
+                        }
+                    }
+                    Message::Ping(bytes) => {
+                        let sent = session.pong(&bytes).await;
+                        if sent.is_err() {
+                            break connection_lost();
+                        }
+                    }
+                    Message::Close(reason) => {
+                        // Echo the client's close frame to finish the handshake.
+                        break reason.unwrap_or_else(|| CloseCode::Normal.into());
+                    }
                     _ => {}
                 }
             }
-            manager.registry.remove(&game_id, &session_id);
-        }
-    });
 
-    tokio::spawn(async move {
-        while let Some(outgoing) = rx.recv().await {
-            if let Err(e) = session.text(outgoing).await {
-                eprintln!("Failed to send message: {}", e);
-                break;
+            _ = heartbeat.tick() => {
+                if last_heartbeat.elapsed() > ctx.manager.config.client_timeout {
+                    ctx.manager.metrics.record_reaped();
+                    break CloseReason {
+                        code: CloseCode::Away,
+                        description: Some("Heartbeat timeout".to_string()),
+                    };
+                }
+
+                if session.ping(b"").await.is_err() {
+                    break connection_lost();
+                }
             }
         }
-    });
+    };
 
-    Ok(res)
+    tracing::info!(code = ?close_reason.code, "WebSocket session ended");
+    ctx.manager.registry.remove(&ctx.game_id, &ctx.session_id);
+
+    // Let the writer flush what is already queued, such as a final error or
+    // shutdown notice, before the socket closes.
+    queue.close();
+    if time::timeout(WRITER_DRAIN_TIMEOUT, &mut writer)
+        .await
+        .is_err()
+    {
+        tracing::debug!("Outbound queue not drained in time");
+        writer.abort();
+    }
+    let _ = session.close(Some(close_reason)).await;
+
+    if !ctx
+        .manager
+        .registry
+        .has_user_session(&ctx.game_id, ctx.user.user_id)
+    {
+        ctx.manager
+            .chat
+            .forget_muted(&ctx.game_id, ctx.user.user_id);
+    }
+
+    if let Err(e) = announce_departure(&ctx).await {
+        tracing::warn!(error = %e, "Failed to announce disconnect");
+    }
 }
 
-async fn handle_message(
-    manager: &Arc<WsManager>,
-    game_id: &str,
-    session_id: &str,
-    user_id: Uuid,
-    raw: String,
-) -> anyhow::Result<()> {
-    match serde_json::from_str::<WsClientMessage>(&raw) {
-        Ok(WsClientMessage::CreateGame) => {
-            match manager
-                .game_manager
-                .create_game(user_id, session_id.to_string(), game_id.to_string())
-                .await
-            {
-                Ok(game) => broadcast_state(manager, game_id, session_id, game).await?,
-                Err(e) => {
-                    send_error(
-                        manager,
-                        game_id,
-                        session_id,
-                        &format!("Failed to create game: {}", e),
-                    )
-                    .await?;
+/// Tells the game a player left, once their last socket to it on this
+/// instance is gone. Spectators come and go silently.
+async fn announce_departure(ctx: &SessionContext) -> anyhow::Result<()> {
+    let registry = &ctx.manager.registry;
+    if registry.has_user_session(&ctx.game_id, ctx.user.user_id) {
+        return Ok(());
+    }
+
+    let Some(game) = ctx.manager.game_manager.get_game(&ctx.game_id).await? else {
+        return Ok(());
+    };
+    if game.get_player_symbol(ctx.user.user_id).is_none() {
+        return Ok(());
+    }
+
+    let left = WsServerMessage::PlayerDisconnected {
+        username: ctx.user.username.clone(),
+    };
+    ctx.manager
+        .broadcast_server_message(&ctx.game_id, &left)
+        .await
+}
+
+/// Sends queued messages in the shape the session negotiated, skipping the
+/// ones it can't understand or `viewer` muted in `game_id`. Game states are
+/// projected for `viewer`.
+async fn write_outbound<S: SessionSink>(
+    queue: Arc<SessionQueue>,
+    protocol: Arc<SessionProtocol>,
+    chat: Arc<ChatManager>,
+    game_id: String,
+    viewer: Uuid,
+    mut session: S,
+) {
+    while let Some(outgoing) = queue.pop().await {
+        let state = match outgoing {
+            Outbound::Text(text) => Ok(Rendered::Shared(text)),
+            Outbound::State(state) => protocol.render_state(&state, viewer).map(Rendered::Own),
+            Outbound::Delta { delta, state } => {
+                if protocol.get().has("deltas") {
+                    Ok(Rendered::Shared(delta))
+                } else {
+                    protocol.render_state(&state, viewer).map(Rendered::Own)
                 }
             }
-        }
-        Ok(WsClientMessage::JoinGame) => {
-            match manager
-                .game_manager
-                .join_game(game_id, user_id, session_id.to_string())
-                .await
-            {
-                Ok(game) => {
-                    broadcast_state(manager, game_id, session_id, game).await?;
+            Outbound::Chat { from, message } => {
+                if chat.is_muted(&game_id, viewer, from) {
+                    continue;
                 }
+                Ok(Rendered::Shared(message))
+            }
+            Outbound::Close(reason) => {
+                let _ = session.close(Some(reason)).await;
+                break;
+            }
+        };
+
+        let text = match state {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to render game state");
+                continue;
+            }
+        };
+
+        let Some(adapted) = protocol.adapt_outgoing(text.as_str()) else {
+            continue;
+        };
+
+        let encoding = protocol.encoding();
+        let sent = if encoding.is_binary() {
+            // Messages every session gets unchanged are encoded once per
+            // broadcast; anything projected or adapted for this session alone
+            // is encoded here.
+            let bytes = match (&text, &adapted) {
+                (Rendered::Shared(shared), Cow::Borrowed(_)) => shared.encoded(encoding),
+                _ => encoding.encode_binary(&adapted),
+            };
+            match bytes {
+                Ok(bytes) => session.binary(bytes).await,
                 Err(e) => {
-                    send_error(
-                        manager,
-                        &game_id,
-                        session_id,
-                        &format!("Failed to join game: {}", e),
-                    )
-                    .await?;
+                    tracing::error!(error = %e, "Failed to encode message");
+                    continue;
                 }
             }
+        } else {
+            session.text(adapted.into_owned()).await
+        };
+
+        if let Err(e) = sent {
+            tracing::debug!(error = %e, "Failed to send message");
+            break;
+        }
+    }
+}
+
+/// A message ready for the adapter and encoder of one session.
+enum Rendered {
+    /// The same for every recipient of the broadcast.
+    Shared(SharedText),
+    /// Projected for this session's viewer.
+    Own(String),
+}
+
+impl Rendered {
+    fn as_str(&self) -> &str {
+        match self {
+            Rendered::Shared(text) => text.as_str(),
+            Rendered::Own(text) => text,
+        }
+    }
+}
+
+fn connection_lost() -> CloseReason {
+    CloseReason {
+        code: CloseCode::Away,
+        description: Some("Connection lost".to_string()),
+    }
+}
+
+/// Everything the handlers of one socket share.
+struct SessionContext {
+    manager: Arc<WsManager>,
+    game_id: String,
+    session_id: String,
+    user: SessionUser,
+    protocol: Arc<SessionProtocol>,
+}
+
+impl SessionContext {
+    /// Queues a message for this session only.
+    fn reply(&self, message: Outbound) {
+        self.manager
+            .registry
+            .send(&self.game_id, &self.session_id, message);
+    }
+}
+
+/// What a request that succeeded causes besides its `ack`.
+enum Handled {
+    /// Nothing beyond the `ack`.
+    Done,
+    Welcome(Negotiated),
+    /// Broadcast to the whole game.
+    State(GameState),
+    /// Broadcast as a delta to sessions that speak them.
+    Move {
+        game: GameState,
+        position: usize,
+        symbol: Player,
+    },
+    /// Sent to the requesting session only.
+    Snapshot(GameState),
+    /// A chat line or emote, kept in the history and broadcast.
+    Chat(WsServerMessage),
+    /// Sent to the requesting session only.
+    Events(Vec<LoggedEvent>),
+}
+
+/// Handles one client message. Every request gets exactly one `ack` or
+/// `error` reply echoing its `request_id`, ahead of any broadcast it causes,
+/// so clients can confirm or roll back optimistic updates.
+#[tracing::instrument(name = "ws_message", skip_all)]
+async fn handle_message(ctx: &SessionContext, frame: ClientFrame<'_>) -> anyhow::Result<()> {
+    let received = Instant::now();
+    let (request_id, parsed) = ctx.protocol.parse_client(frame);
+    let kind = parsed.as_ref().map_or("invalid", WsClientMessage::kind);
+    ctx.manager.metrics.record_message(kind);
+
+    // Unknown types and fields are answered, never fatal: the client may
+    // simply be newer than this server.
+    let result = match parsed {
+        Ok(msg) => dispatch(ctx, msg).await,
+        Err(e) => Err(e.to_string()),
+    };
+
+    let handled = match result {
+        Ok(handled) => handled,
+        Err(message) => {
+            let error = WsServerMessage::Error {
+                message,
+                request_id,
+            };
+            ctx.reply(error.to_outbound()?);
+            return Ok(());
+        }
+    };
+
+    if let Some(request_id) = request_id {
+        let ack = WsServerMessage::Ack { request_id };
+        ctx.reply(ack.to_outbound()?);
+    }
+
+    let manager = &ctx.manager;
+    let game_id = ctx.game_id.as_str();
+
+    match handled {
+        Handled::Done => {}
+        Handled::Welcome(negotiated) => {
+            let chat = negotiated.has("chat");
+            let welcome = WsServerMessage::Welcome {
+                version: negotiated.version,
+                capabilities: negotiated.capabilities.into_iter().collect(),
+            };
+            ctx.reply(welcome.to_outbound()?);
+
+            if chat {
+                send_chat_history(ctx).await?;
+            }
+        }
+        Handled::State(game) => {
+            let payload = WsServerMessage::state_outbound(&game)?;
+            manager.broadcast_to_all(game_id, payload).await?;
+        }
+        Handled::Move {
+            game,
+            position,
+            symbol,
+        } => {
+            let payload = WsServerMessage::move_outbound(&game, position, symbol)?;
+            manager.broadcast_to_all(game_id, payload).await?;
+            manager.metrics.observe_move(received.elapsed());
+        }
+        Handled::Snapshot(game) => {
+            ctx.reply(WsServerMessage::state_outbound(&game)?);
+        }
+        Handled::Chat(message) => {
+            let record = ChatRecord {
+                sender: ctx.user.user_id,
+                message,
+            };
+            if let Err(e) = manager.chat.append_history(game_id, &record).await {
+                tracing::warn!(error = %e, "Failed to store chat history");
+            }
+
+            let payload = Outbound::Chat {
+                from: record.sender,
+                message: serde_json::to_string(&record.message)?.into(),
+            };
+            manager.broadcast_to_all(game_id, payload).await?;
         }
-        Ok(WsClientMessage::MakeMove { position }) => {
-            match manager
+        Handled::Events(events) => {
+            ctx.reply(WsServerMessage::GameEvents { events }.to_outbound()?);
+        }
+    }
+
+    Ok(())
+}
+
+async fn dispatch(ctx: &SessionContext, msg: WsClientMessage) -> Result<Handled, String> {
+    let manager = &ctx.manager;
+    let game_id = ctx.game_id.as_str();
+    let session_id = ctx.session_id.as_str();
+    let user = &ctx.user;
+    let user_id = user.user_id;
+
+    match msg {
+        WsClientMessage::Hello {
+            version,
+            capabilities,
+        } => ctx
+            .protocol
+            .negotiate(version, &capabilities)
+            .map(Handled::Welcome),
+        WsClientMessage::CreateGame { rated } => {
+            if rated && user.is_guest {
+                return Err("Guests can only create unrated games".to_string());
+            }
+
+            manager
+                .chat
+                .clear(game_id)
+                .await
+                .map_err(|e| format!("Failed to create game: {}", e))?;
+            manager
+                .game_manager
+                .create_game(
+                    user_id,
+                    user.username.clone(),
+                    session_id.to_string(),
+                    game_id.to_string(),
+                    rated,
+                )
+                .await
+                .map(Handled::State)
+                .map_err(|e| format!("Failed to create game: {}", e))
+        }
+        WsClientMessage::JoinGame => manager
+            .game_manager
+            .join_game(
+                game_id,
+                user_id,
+                user.username.clone(),
+                session_id.to_string(),
+                user.is_guest,
+            )
+            .await
+            .map(Handled::State)
+            .map_err(|e| format!("Failed to join game: {}", e)),
+        WsClientMessage::MakeMove { position } => {
+            let game = manager
                 .game_manager
                 .make_move(game_id, user_id, position)
                 .await
-            {
-                Ok(game) => {
-                    broadcast_state(manager, game_id, session_id, game).await?;
-                }
-                Err(e) => {
-                    send_error(
-                        manager,
-                        &game_id,
-                        session_id,
-                        &format!("Failed to make move: {}", e),
-                    )
-                    .await?;
-                }
+                .map_err(|e| format!("Failed to make move: {}", e))?;
+            let symbol = game
+                .get_player_symbol(user_id)
+                .ok_or_else(|| "Failed to make move: Invalid player".to_string())?;
+
+            Ok(Handled::Move {
+                game,
+                position,
+                symbol,
+            })
+        }
+        WsClientMessage::RequestSnapshot => match manager.game_manager.get_game(game_id).await {
+            Ok(Some(game)) => Ok(Handled::Snapshot(game)),
+            Ok(None) => Err("Game not found".to_string()),
+            Err(e) => Err(format!("Failed to load game: {}", e)),
+        },
+        WsClientMessage::Chat { text } => {
+            if !manager.chat.allow(user.user_id) {
+                return Err("You are sending messages too fast".to_string());
+            }
+            player_game(ctx).await?;
+            let text = manager.chat.sanitize(&text)?;
+
+            Ok(Handled::Chat(WsServerMessage::Chat {
+                from: user.username.clone(),
+                text,
+                sent_at: chrono::Utc::now().timestamp(),
+            }))
+        }
+        WsClientMessage::Emote { emote } => {
+            if !manager.chat.allow(user.user_id) {
+                return Err("You are sending messages too fast".to_string());
             }
+            player_game(ctx).await?;
+
+            Ok(Handled::Chat(WsServerMessage::Emote {
+                from: user.username.clone(),
+                emote,
+                sent_at: chrono::Utc::now().timestamp(),
+            }))
         }
+        WsClientMessage::Mute => set_muted(ctx, true).await,
+        WsClientMessage::Unmute => set_muted(ctx, false).await,
+        WsClientMessage::ReplayEvents { after } => {
+            if !ctx.protocol.get().has("events") {
+                return Err("Replaying events needs the events capability".to_string());
+            }
 
-        Err(_) => {
-            send_error(
-                manager,
-                game_id,
-                session_id,
-                "Invalid message format. Expected Json game message.",
-            )
-            .await?
+            manager
+                .game_manager
+                .events(game_id, after.as_deref())
+                .await
+                .map(Handled::Events)
+                .map_err(|e| format!("Failed to load game events: {}", e))
         }
     }
+}
 
-    Ok(())
+/// The game of this socket, if its user plays in it. Chat is for players.
+async fn player_game(ctx: &SessionContext) -> Result<GameState, String> {
+    let game = ctx
+        .manager
+        .game_manager
+        .get_game(&ctx.game_id)
+        .await
+        .map_err(|e| format!("Failed to load game: {}", e))?
+        .ok_or_else(|| "Game not found".to_string())?;
+
+    if game.get_player_symbol(ctx.user.user_id).is_none() {
+        return Err("Only players can chat".to_string());
+    }
+
+    Ok(game)
 }
 
-async fn send_error(
-    manager: &Arc<WsManager>,
-    game_id: &str,
-    session_id: &str,
-    error_message: &str,
-) -> anyhow::Result<()> {
-    let error_msg = WsServerMessage::Error {
-        message: error_message.to_string(),
-    };
-    let json = serde_json::to_string(&error_msg)?;
+async fn set_muted(ctx: &SessionContext, muted: bool) -> Result<Handled, String> {
+    let game = player_game(ctx).await?;
 
-    if let Some(game_sessions) = manager.registry.games.get(game_id) {
-        if let Some(sender_tx) = game_sessions.value().get(session_id) {
-            let _ = sender_tx.send(json);
-        }
+    let opponent = if game.player1_id == ctx.user.user_id {
+        game.player2_id
+    } else {
+        Some(game.player1_id)
     }
-    Ok(())
+    .ok_or_else(|| "There is no other player yet".to_string())?;
+
+    ctx.manager
+        .chat
+        .set_muted(&ctx.game_id, ctx.user.user_id, opponent, muted)
+        .await
+        .map_err(|e| format!("Failed to update mute: {}", e))?;
+
+    Ok(Handled::Done)
 }
 
-async fn broadcast_state(
-    manager: &Arc<WsManager>,
-    game_id: &str,
-    sender_session_id: &str,
-    game: crate::game::game_state::GameState,
-) -> anyhow::Result<()> {
-    let payload = WsServerMessage::GameState { game };
-    let json = serde_json::to_string(&payload)?;
+/// Loads the player's mutes and sends the recent chat of the game.
+async fn send_chat_history(ctx: &SessionContext) -> anyhow::Result<()> {
+    let chat = &ctx.manager.chat;
 
-    manager
-        .broadcast_to_all(game_id, &json, sender_session_id)
-        .await?;
+    if let Err(e) = chat.muted(&ctx.game_id, ctx.user.user_id).await {
+        tracing::warn!(error = %e, "Failed to load chat mutes");
+    }
+
+    let history = match chat.history(&ctx.game_id).await {
+        Ok(history) => history,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to load chat history");
+            return Ok(());
+        }
+    };
+
+    let messages = history
+        .into_iter()
+        .filter(|record| !chat.is_muted(&ctx.game_id, ctx.user.user_id, record.sender))
+        .map(|record| record.message)
+        .filter(|message| {
+            matches!(
+                message,
+                WsServerMessage::Chat { .. } | WsServerMessage::Emote { .. }
+            )
+        })
+        .collect();
+
+    ctx.reply(WsServerMessage::ChatHistory { messages }.to_outbound()?);
     Ok(())
 }
@@ -0,0 +1,61 @@
+/// Owns the name of every Redis key and channel the server uses.
+///
+/// Names are `{prefix}{namespace}:...`, with separate namespaces for stored
+/// state, pub/sub channels and event streams, so a channel never shares a name
+/// with a key and keyspace notifications stay unambiguous. The prefix lets
+/// several environments or tenants share one Redis.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keys {
+    prefix: String,
+}
+
+impl Keys {
+    /// `prefix` is used as is, e.g. `"staging:"`.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn game_state(&self, game_id: &str) -> String {
+        format!("{}state:game:{}", self.prefix, game_id)
+    }
+
+    /// `SCAN` pattern matching every `game_state` key.
+    pub fn game_state_pattern(&self) -> String {
+        format!("{}state:game:*", self.prefix)
+    }
+
+    pub fn chat_history(&self, game_id: &str) -> String {
+        format!("{}state:chat:{}", self.prefix, game_id)
+    }
+
+    pub fn chat_muted(&self, game_id: &str, muter: &str) -> String {
+        format!("{}state:chat:{}:muted:{}", self.prefix, game_id, muter)
+    }
+
+    /// Stream of a game's `GameEvent`s.
+    pub fn game_events(&self, game_id: &str) -> String {
+        format!("{}queue:events:{}", self.prefix, game_id)
+    }
+
+    pub fn game_channel(&self, game_id: &str) -> String {
+        format!("{}chan:game:{}", self.prefix, game_id)
+    }
+
+    /// The game a `game_channel` belongs to.
+    pub fn game_id_from_channel<'a>(&self, channel: &'a str) -> Option<&'a str> {
+        channel
+            .strip_prefix(self.prefix.as_str())?
+            .strip_prefix("chan:game:")
+    }
+
+    /// Instance-wide commands, published next to the per-game channels.
+    pub fn control_channel(&self) -> String {
+        format!("{}chan:control:sessions", self.prefix)
+    }
+}
@@ -1,6 +1,14 @@
 pub mod handler;
 pub mod manager;
 
+pub mod broadcast;
+pub mod codec;
+pub mod config;
 pub mod connection_registry;
 pub mod game;
+pub mod keys;
+pub mod metrics;
+pub mod protocol;
 pub mod pubsub;
+pub mod redis_conn;
+pub mod session_queue;
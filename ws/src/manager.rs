@@ -1,74 +1,197 @@
+use actix_ws::{CloseCode, CloseReason};
 use anyhow::{Context, Result};
-use redis::Client;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use uuid::Uuid;
 
+use crate::broadcast::{Broadcaster, BroadcasterFactory, ControlMessage};
+use crate::config::WsConfig;
 use crate::connection_registry::ConnectionRegistry;
+use crate::game::chat::{ChatManager, ChatStore};
 use crate::game::game_manager::GameManager;
-use crate::pubsub::PubSub;
+use crate::game::game_state::GameState;
+use crate::game::messages::WsServerMessage;
+use crate::game::store::GameStore;
+use crate::metrics::WsMetrics;
+use crate::session_queue::Outbound;
 
 pub struct WsManager {
     pub registry: Arc<ConnectionRegistry>,
-    pub pubsub: Arc<PubSub>,
+    pub broadcaster: Arc<dyn Broadcaster>,
     pub game_manager: Arc<GameManager>,
+    pub chat: Arc<ChatManager>,
+    pub config: WsConfig,
+    pub metrics: Arc<WsMetrics>,
+    accepting: AtomicBool,
+    shutdown: watch::Sender<bool>,
+    subscriber: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl WsManager {
-    pub async fn new(redis_url: &str) -> Result<Self> {
-        let registry = Arc::new(ConnectionRegistry::new());
+    /// Games are kept in `store`, chat in `chat_store`, and both are fanned
+    /// out by the broadcaster `broadcaster` builds.
+    pub async fn new(
+        config: WsConfig,
+        store: Arc<dyn GameStore>,
+        chat_store: Arc<dyn ChatStore>,
+        broadcaster: BroadcasterFactory,
+    ) -> Result<Self> {
+        let (game_events, game_events_rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(ConnectionRegistry::with_game_events(game_events));
 
-        let redis_client = Arc::new(
-            Client::open(redis_url).context("Failed to create redis client for gamemanager")?,
-        );
+        let metrics =
+            Arc::new(WsMetrics::new(&config.instance_id).context("Failed to register metrics")?);
+        let game_manager = Arc::new(GameManager::new(store));
+        let chat = Arc::new(ChatManager::new(chat_store, config.chat.clone()));
+        let broadcaster = broadcaster(
+            Arc::clone(&registry),
+            Arc::clone(&game_manager),
+            Arc::clone(&metrics),
+        )?;
 
-        let game_manager = Arc::new(GameManager::new(Arc::clone(&redis_client)));
-        let pubsub = PubSub::new(redis_url, Arc::clone(&registry)).await?;
+        let receiver = Arc::clone(&broadcaster);
+        let (shutdown, shutdown_rx) = watch::channel(false);
 
-        let pubsub_for_subscriber = pubsub.clone();
-
-        tokio::spawn(async move {
-            if let Err(e) = pubsub_for_subscriber.start_subscriber().await {
-                eprintln!("Redis subscriber error: {}", e);
-            }
+        let subscriber = tokio::spawn(async move {
+            receiver.run(shutdown_rx, game_events_rx).await;
         });
 
         Ok(Self {
             registry,
-            pubsub: Arc::new(pubsub),
+            broadcaster,
             game_manager,
+            chat,
+            config,
+            metrics,
+            accepting: AtomicBool::new(true),
+            shutdown,
+            subscriber: Mutex::new(Some(subscriber)),
         })
     }
 
+    /// Whether new sockets are welcome. False once a shutdown has begun.
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Relaxed)
+    }
+
+    /// Ready for traffic: accepting sockets and hearing from the other
+    /// instances.
+    pub fn is_ready(&self) -> bool {
+        self.is_accepting() && self.broadcaster.is_connected()
+    }
+
+    /// Drains this instance: refuses new sockets, tells every session to
+    /// reconnect, closes each one once its queue is flushed and stops the
+    /// Redis subscriber. Gives up waiting after `shutdown_deadline`.
+    pub async fn shutdown(&self) {
+        self.accepting.store(false, Ordering::Relaxed);
+        let deadline = Instant::now() + self.config.shutdown_deadline;
+
+        let notice = WsServerMessage::ServerShutdown {
+            reconnect_after_ms: self.config.reconnect_hint.as_millis() as u64,
+        };
+        match notice.to_outbound() {
+            Ok(notice) => {
+                let reason = CloseReason {
+                    code: CloseCode::Restart,
+                    description: Some("Server shutting down".to_string()),
+                };
+                let closing = self.registry.close_all(&notice, &reason);
+                tracing::info!(sessions = closing, "Draining WebSocket sessions");
+            }
+            Err(e) => tracing::error!(error = %e, "Failed to build shutdown notice"),
+        }
+
+        while !self.registry.is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        if !self.registry.is_empty() {
+            tracing::warn!("Shutdown deadline passed with WebSocket sessions still open");
+        }
+
+        let _ = self.shutdown.send(true);
+        let subscriber = self.subscriber.lock().unwrap().take();
+        if let Some(subscriber) = subscriber {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if tokio::time::timeout(remaining, subscriber).await.is_err() {
+                tracing::warn!("Broadcaster did not stop before the shutdown deadline");
+            }
+        }
+    }
+
+    /// Delivers to every session of the game but the sender, on every
+    /// instance.
+    #[tracing::instrument(skip(self, message))]
     pub async fn broadcast_except_sender(
         &self,
         game_id: &str,
-        message: &str,
+        message: &Outbound,
         sender_session_id: &str,
     ) -> anyhow::Result<()> {
-        self.pubsub
-            .publish(game_id, message, sender_session_id)
-            .await?;
-        Ok(())
+        self.broadcaster
+            .broadcast(game_id, message, sender_session_id)
+            .await
     }
-    pub async fn broadcast_to_all(
+
+    /// Delivers to every session of the game, the sender's included, on
+    /// every instance.
+    #[tracing::instrument(skip(self, message))]
+    pub async fn broadcast_to_all(&self, game_id: &str, message: Outbound) -> Result<()> {
+        self.broadcaster.broadcast(game_id, &message, "").await
+    }
+
+    /// Sends a message originating from the server itself (not from a
+    /// session) to every socket of a game, on every instance.
+    pub async fn broadcast_server_message(
         &self,
         game_id: &str,
-        message: &str,
-        sender_session_id: &str,
+        message: &WsServerMessage,
     ) -> Result<()> {
-        if let Some(game_sessions) = self.registry.games.get(game_id) {
-            if let Some(sender_tx) = game_sessions.value().get(sender_session_id) {
-                let _ = sender_tx.send(message.to_string());
-            }
-        }
+        self.broadcast_to_all(game_id, message.to_outbound()?).await
+    }
 
-        self.pubsub
-            .publish(game_id, message, sender_session_id)
-            .await?;
+    /// Sends a game state changed by the server itself, e.g. by a moderator,
+    /// to every socket of the game, on every instance.
+    pub async fn broadcast_game_state(&self, game: &GameState) -> Result<()> {
+        self.broadcast_to_all(&game.id, WsServerMessage::state_outbound(game)?)
+            .await
+    }
+
+    /// Closes every socket `user_id` holds, on every instance.
+    pub async fn disconnect_user(&self, user_id: Uuid, reason: &str) -> Result<()> {
+        self.broadcaster
+            .control(&ControlMessage::DisconnectUser {
+                user_id,
+                reason: reason.to_string(),
+            })
+            .await
+    }
 
-        Ok(())
+    /// Closes every socket of a game, on every instance.
+    pub async fn close_game(&self, game_id: &str, reason: &str) -> Result<()> {
+        self.broadcaster
+            .control(&ControlMessage::CloseGame {
+                game_id: game_id.to_string(),
+                reason: reason.to_string(),
+            })
+            .await
     }
 }
 
-pub async fn start_manager(redis_url: &str) -> anyhow::Result<Arc<WsManager>> {
-    Ok(Arc::new(WsManager::new(redis_url).await?))
+pub async fn start_manager(
+    config: WsConfig,
+    store: Arc<dyn GameStore>,
+    chat_store: Arc<dyn ChatStore>,
+    broadcaster: BroadcasterFactory,
+) -> anyhow::Result<Arc<WsManager>> {
+    Ok(Arc::new(
+        WsManager::new(config, store, chat_store, broadcaster).await?,
+    ))
 }
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::connection_registry::ConnectionRegistry;
+
+/// Metrics kept by the WebSocket layer, in a Prometheus registry every
+/// series of which carries an `instance` label. Other layers register their
+/// own metrics in `registry()` so a single scrape covers the server.
+pub struct WsMetrics {
+    registry: Registry,
+    connections: IntGauge,
+    games: IntGauge,
+    messages: IntCounterVec,
+    move_duration: Histogram,
+    broadcast_errors: IntCounterVec,
+    reaped_connections: IntCounter,
+}
+
+impl WsMetrics {
+    pub fn new(instance_id: &str) -> prometheus::Result<Self> {
+        let labels = HashMap::from([("instance".to_string(), instance_id.to_string())]);
+        let registry = Registry::new_custom(None, Some(labels))?;
+
+        let connections = IntGauge::new("ws_connections", "Open WebSocket sessions")?;
+        let games = IntGauge::new("ws_games", "Games with at least one open session")?;
+        let messages = IntCounterVec::new(
+            Opts::new("ws_messages_total", "Client messages received, by type"),
+            &["type"],
+        )?;
+        let move_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "ws_move_duration_seconds",
+                "Time from receiving a move to broadcasting it",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 12)?),
+        )?;
+        let broadcast_errors = IntCounterVec::new(
+            Opts::new(
+                "ws_broadcast_errors_total",
+                "Failed publishes and lost subscriptions of the broadcaster",
+            ),
+            &["operation"],
+        )?;
+        let reaped_connections = IntCounter::new(
+            "ws_reaped_connections_total",
+            "Sessions closed because they missed their heartbeats",
+        )?;
+
+        registry.register(Box::new(connections.clone()))?;
+        registry.register(Box::new(games.clone()))?;
+        registry.register(Box::new(messages.clone()))?;
+        registry.register(Box::new(move_duration.clone()))?;
+        registry.register(Box::new(broadcast_errors.clone()))?;
+        registry.register(Box::new(reaped_connections.clone()))?;
+
+        Ok(Self {
+            registry,
+            connections,
+            games,
+            messages,
+            move_duration,
+            broadcast_errors,
+            reaped_connections,
+        })
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn record_message(&self, kind: &str) {
+        self.messages.with_label_values(&[kind]).inc();
+    }
+
+    pub fn observe_move(&self, elapsed: Duration) {
+        self.move_duration.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_publish_error(&self) {
+        self.broadcast_errors.with_label_values(&["publish"]).inc();
+    }
+
+    pub fn record_subscribe_error(&self) {
+        self.broadcast_errors
+            .with_label_values(&["subscribe"])
+            .inc();
+    }
+
+    pub fn record_reaped(&self) {
+        self.reaped_connections.inc();
+    }
+
+    /// Sessions closed because they missed their heartbeats.
+    pub fn reaped_connections(&self) -> u64 {
+        self.reaped_connections.get()
+    }
+
+    /// Every registered metric in the Prometheus text format, with the
+    /// connection gauges taken from `registry` now.
+    pub fn render(&self, registry: &ConnectionRegistry) -> prometheus::Result<String> {
+        self.connections.set(registry.session_count() as i64);
+        self.games.set(registry.games.len() as i64);
+
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
@@ -0,0 +1,306 @@
+use std::{borrow::Cow, collections::BTreeSet, sync::RwLock};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    codec::{ClientFrame, Encoding},
+    game::{
+        game_state::{Board, GameState, GameStatus, Player},
+        messages::{GameView, WsClientMessage, WsServerMessage},
+    },
+};
+
+/// Oldest protocol version still served. Clients that never say `hello` are
+/// assumed to speak it.
+pub const MIN_VERSION: u32 = 1;
+
+/// Version 2 added the `hello`/`welcome` handshake, capabilities, `rated`
+/// games, game sequence numbers and `request_id` correlation with `ack`
+/// replies.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Opt-in message families the server can speak.
+pub const SERVER_CAPABILITIES: &[&str] = &["presence", "deltas", "chat", "events"];
+
+/// Server message types that are only sent to sessions holding a capability.
+const GATED_MESSAGES: &[(&str, &str)] = &[
+    ("player_disconnected", "presence"),
+    ("move_applied", "deltas"),
+    ("chat", "chat"),
+    ("emote", "chat"),
+    ("chat_history", "chat"),
+    ("game_events", "events"),
+];
+
+/// Client message types this server knows, in any version.
+const CLIENT_MESSAGE_TYPES: &[&str] = &[
+    "hello",
+    "create_game",
+    "join_game",
+    "make_move",
+    "request_snapshot",
+    "chat",
+    "emote",
+    "mute",
+    "unmute",
+    "replay_events",
+];
+
+/// What a session and the server agreed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub capabilities: BTreeSet<String>,
+}
+
+impl Default for Negotiated {
+    fn default() -> Self {
+        Self {
+            version: MIN_VERSION,
+            capabilities: BTreeSet::new(),
+        }
+    }
+}
+
+impl Negotiated {
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
+
+/// Picks the highest version both sides speak and the capabilities both
+/// sides know. Unknown capabilities are ignored rather than rejected.
+pub fn negotiate(version: u32, capabilities: &[String]) -> Result<Negotiated, String> {
+    if version < MIN_VERSION {
+        return Err(format!(
+            "Unsupported protocol version {}, the server speaks {} to {}",
+            version, MIN_VERSION, CURRENT_VERSION
+        ));
+    }
+
+    Ok(Negotiated {
+        version: version.min(CURRENT_VERSION),
+        capabilities: capabilities
+            .iter()
+            .filter(|c| SERVER_CAPABILITIES.contains(&c.as_str()))
+            .cloned()
+            .collect(),
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Not a JSON object with a string `type`.
+    Malformed,
+    /// A well-formed message of a type this server doesn't know, e.g. from a
+    /// newer client.
+    UnknownType(String),
+    /// A known type with missing or invalid fields.
+    InvalidFields(String),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Malformed => {
+                write!(f, "Invalid message format. Expected a typed game message.")
+            }
+            ParseError::UnknownType(ty) => write!(f, "Unsupported message type {:?}", ty),
+            ParseError::InvalidFields(e) => write!(f, "Invalid message: {}", e),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Tagged<'a> {
+    #[serde(rename = "type", borrow)]
+    ty: Cow<'a, str>,
+}
+
+/// Negotiated protocol of one session, shared by its reader and writer.
+/// Sessions that haven't said `hello` get the defaults.
+#[derive(Debug, Default)]
+pub struct SessionProtocol {
+    encoding: Encoding,
+    negotiated: RwLock<Option<Negotiated>>,
+}
+
+impl SessionProtocol {
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            negotiated: RwLock::new(None),
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn get(&self) -> Negotiated {
+        self.negotiated.read().unwrap().clone().unwrap_or_default()
+    }
+
+    /// Stores the result of the handshake. Fails if the session already
+    /// negotiated, versions can't change mid-session.
+    pub fn negotiate(&self, version: u32, capabilities: &[String]) -> Result<Negotiated, String> {
+        let mut current = self.negotiated.write().unwrap();
+        if current.is_some() {
+            return Err("Protocol already negotiated".to_string());
+        }
+
+        let negotiated = negotiate(version, capabilities)?;
+        *current = Some(negotiated.clone());
+        Ok(negotiated)
+    }
+
+    /// Parses a client message, telling unknown types apart from garbage so
+    /// newer clients get a useful answer. Unknown fields are ignored. The
+    /// optional `request_id` is returned even when parsing fails, so the error
+    /// can be correlated.
+    pub fn parse_client(
+        &self,
+        frame: ClientFrame<'_>,
+    ) -> (Option<String>, Result<WsClientMessage, ParseError>) {
+        let Ok(value) = self.encoding.decode(frame) else {
+            return (None, Err(ParseError::Malformed));
+        };
+
+        let request_id = value
+            .get("request_id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string);
+
+        let parsed = match value.get("type").and_then(|t| t.as_str()) {
+            None => Err(ParseError::Malformed),
+            Some(ty) if !CLIENT_MESSAGE_TYPES.contains(&ty) => {
+                Err(ParseError::UnknownType(ty.to_string()))
+            }
+            Some(_) => {
+                serde_json::from_value(value).map_err(|e| ParseError::InvalidFields(e.to_string()))
+            }
+        };
+
+        (request_id, parsed)
+    }
+
+    /// Renders a stored game state as the `game_state` message `viewer`
+    /// should see.
+    pub fn render_state(&self, state: &str, viewer: Uuid) -> anyhow::Result<String> {
+        let game: GameState = serde_json::from_str(state)?;
+
+        if self.get().version < CURRENT_VERSION {
+            return Ok(serde_json::to_string(&LegacyGameStateMessage {
+                ty: "game_state",
+                game: LegacyGameState::project(&game, viewer),
+            })?);
+        }
+
+        let message = WsServerMessage::GameState {
+            game: GameView::project(&game, viewer),
+        };
+        Ok(serde_json::to_string(&message)?)
+    }
+
+    /// Rewrites an outgoing message for this session's protocol. Returns
+    /// `None` when the session must not see the message at all.
+    pub fn adapt_outgoing<'a>(&self, json: &'a str) -> Option<Cow<'a, str>> {
+        let negotiated = self.get();
+
+        let Ok(tagged) = serde_json::from_str::<Tagged>(json) else {
+            return Some(Cow::Borrowed(json));
+        };
+
+        let gate = GATED_MESSAGES
+            .iter()
+            .find(|(ty, _)| *ty == tagged.ty.as_ref());
+        if let Some((_, capability)) = gate {
+            if !negotiated.has(capability) {
+                return None;
+            }
+        }
+
+        if negotiated.version >= CURRENT_VERSION {
+            return Some(Cow::Borrowed(json));
+        }
+
+        adapt_v1(json).map(Cow::Owned)
+    }
+}
+
+/// Version 1 clients only know `game_state`, `error` and the `welcome` reply
+/// to a `hello`. They never send request ids, so they get no `ack`. They
+/// still get `server_shutdown`: a client that ignores it just reconnects
+/// when the close frame arrives, and one that reads it waits as told.
+fn adapt_v1(json: &str) -> Option<String> {
+    let mut value: serde_json::Value = serde_json::from_str(json).ok()?;
+
+    match value.get("type").and_then(|t| t.as_str()) {
+        Some("game_state") | Some("welcome") | Some("server_shutdown") => {}
+        Some("error") => {
+            if let Some(error) = value.as_object_mut() {
+                error.remove("request_id");
+            }
+        }
+        _ => return None,
+    }
+
+    serde_json::to_string(&value).ok()
+}
+
+#[derive(Serialize)]
+struct LegacyGameStateMessage {
+    #[serde(rename = "type")]
+    ty: &'static str,
+    game: LegacyGameState,
+}
+
+/// The game object of version 1, which was the storage format itself.
+/// It is projected from the viewer's `GameView`: session ids go out empty,
+/// and only the viewer's own user id is real. Other seats get a pseudonym
+/// that is stable for the game, so v1 clients can still compare
+/// `current_turn` and `winner` against the players.
+#[derive(Serialize)]
+struct LegacyGameState {
+    id: String,
+    player1_id: Uuid,
+    player2_id: Option<Uuid>,
+    player1_session: &'static str,
+    player2_session: Option<&'static str>,
+    current_turn: Option<Uuid>,
+    status: GameStatus,
+    board: Board,
+    winner: Option<Uuid>,
+    created_at: i64,
+}
+
+impl LegacyGameState {
+    fn project(game: &GameState, viewer: Uuid) -> Self {
+        let view = GameView::project(game, viewer);
+        let seat = |symbol: Player| {
+            if view.you == Some(symbol) {
+                viewer
+            } else {
+                Uuid::new_v5(
+                    &Uuid::NAMESPACE_OID,
+                    format!("{}:{:?}", view.id, symbol).as_bytes(),
+                )
+            }
+        };
+        let seated = view.players.len() > 1;
+
+        Self {
+            player1_id: seat(Player::X),
+            player2_id: seated.then(|| seat(Player::O)),
+            player1_session: "",
+            player2_session: seated.then_some(""),
+            current_turn: view.turn.map(seat),
+            winner: view.winner.map(seat),
+            id: view.id,
+            status: view.status,
+            board: view.board,
+            created_at: view.created_at,
+        }
+    }
+}
@@ -1,55 +1,114 @@
+use crate::broadcast::{
+    Broadcaster, BroadcasterFactory, ControlMessage, Follower, MessagePayload, Notification,
+    Subscription,
+};
+use crate::config::RedisConfig;
 use crate::connection_registry::ConnectionRegistry;
+use crate::game::{events::EventLog, game_manager::GameManager};
+use crate::keys::Keys;
+use crate::metrics::WsMetrics;
+use crate::redis_conn::RedisConnection;
+use crate::session_queue::Outbound;
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use futures_util::StreamExt;
-use redis::{AsyncCommands, Client};
-use serde::{Deserialize, Serialize};
+use redis::{
+    aio::{PubSubSink, PubSubStream},
+    AsyncCommands, Client,
+};
 use std::sync::Arc;
+use tokio::sync::{mpsc::UnboundedReceiver, watch};
 
-#[derive(Serialize, Deserialize)]
-pub struct MessagePayload {
-    sender_id: String,
-    message: String,
-}
-
-#[derive(Clone)]
+/// Fan-out over Redis pub/sub, one channel per game. With games in Redis, it
+/// also reads their event streams to make up for messages pub/sub dropped.
 pub struct PubSub {
-    pub_client: Client,
+    publisher: RedisConnection,
     sub_client: Client,
+    /// Event streams of the games, on a connection of their own.
+    events: Option<EventLog>,
     registry: Arc<ConnectionRegistry>,
+    keys: Keys,
+    metrics: Arc<WsMetrics>,
+    follower: Follower,
 }
 
 impl PubSub {
-    pub async fn new(redis_url: &str, registry: Arc<ConnectionRegistry>) -> Result<Self> {
-        let pub_client =
-            Client::open(redis_url).context("Failed to create redis publisher client")?;
+    pub fn new(
+        redis_url: &str,
+        registry: Arc<ConnectionRegistry>,
+        game_manager: Arc<GameManager>,
+        metrics: Arc<WsMetrics>,
+        keys: Keys,
+        config: RedisConfig,
+        consume_events: bool,
+    ) -> Result<Self> {
+        let events = match consume_events {
+            true => Some(EventLog::new(
+                RedisConnection::open(redis_url, config.clone())
+                    .context("Failed to create redis client for game events")?,
+                keys.clone(),
+            )),
+            false => None,
+        };
+        let publisher = RedisConnection::open(redis_url, config)
+            .context("Failed to create redis publisher client")?;
         let sub_client =
             Client::open(redis_url).context("Failed to create redis subscriber client")?;
 
         Ok(Self {
-            pub_client,
+            publisher,
             sub_client,
+            events,
+            follower: Follower::new(
+                registry.clone(),
+                game_manager,
+                metrics.clone(),
+                keys.clone(),
+                "redis",
+            ),
             registry,
+            keys,
+            metrics,
         })
     }
 
-    pub async fn publish(
-        &self,
-        game_id: &str,
-        message: &str,
-        sender_session_id: &str,
-    ) -> Result<()> {
-        let channel = format!("game:{}", game_id);
+    /// `consume_events` when the games, and so their event streams, are
+    /// kept in the same Redis.
+    pub fn factory(
+        redis_url: &str,
+        keys: Keys,
+        config: RedisConfig,
+        consume_events: bool,
+    ) -> BroadcasterFactory {
+        let redis_url = redis_url.to_string();
+
+        Box::new(move |registry, game_manager, metrics| {
+            Ok(Arc::new(Self::new(
+                &redis_url,
+                registry,
+                game_manager,
+                metrics,
+                keys,
+                config,
+                consume_events,
+            )?))
+        })
+    }
 
-        let payload = MessagePayload {
-            sender_id: sender_session_id.to_string(),
-            message: message.to_string(),
-        };
+    #[tracing::instrument(skip(self, json_message))]
+    async fn publish(&self, channel: String, json_message: String) -> Result<()> {
+        let published = self.try_publish(channel, json_message).await;
+        if published.is_err() {
+            self.metrics.record_publish_error();
+        }
 
-        let json_message = serde_json::to_string(&payload)?;
+        published
+    }
 
+    async fn try_publish(&self, channel: String, json_message: String) -> Result<()> {
         let mut conn = self
-            .pub_client
-            .get_multiplexed_async_connection()
+            .publisher
+            .get()
             .await
             .context("Failed to get redis publisher connection")?;
 
@@ -60,43 +119,106 @@ impl PubSub {
         Ok(())
     }
 
-    pub async fn start_subscriber(&self) -> Result<()> {
-        let mut pubsub = self
+    async fn connect(&self) -> Result<RedisSubscription> {
+        let pubsub = self
             .sub_client
             .get_async_pubsub()
             .await
             .context("Failed to get Redis subscriber connection")?;
+        let (sink, stream) = pubsub.split();
 
-        pubsub
-            .psubscribe("game:*")
+        Ok(RedisSubscription { sink, stream })
+    }
+}
+
+/// A Redis pub/sub connection, split so channels can be added while
+/// receiving.
+struct RedisSubscription {
+    sink: PubSubSink,
+    stream: PubSubStream,
+}
+
+#[async_trait]
+impl Subscription for RedisSubscription {
+    async fn listen(&mut self, channel: &str) -> Result<()> {
+        self.sink
+            .subscribe(channel)
             .await
-            .context("Failed to get Redis subscriber connection")?;
+            .context("Failed to subscribe to Redis channel")
+    }
+
+    async fn unlisten(&mut self, channel: &str) -> Result<()> {
+        self.sink
+            .unsubscribe(channel)
+            .await
+            .context("Failed to unsubscribe from Redis channel")
+    }
 
-        let mut stream = pubsub.into_on_message();
-
-        while let Some(msg) = stream.next().await {
-            let json_payload = msg
-                .get_payload::<String>()
-                .context("Failed to get message payload")?;
-
-            let channel = msg.get_channel_name();
-
-            if let Some(game_id) = channel.strip_prefix("game:") {
-                match serde_json::from_str::<MessagePayload>(&json_payload) {
-                    Ok(payload) => {
-                        self.registry.broadcast_except(
-                            game_id,
-                            &payload.message,
-                            &payload.sender_id,
-                        );
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to parse message payload {}", e)
-                    }
+    async fn recv(&mut self) -> Result<Option<Notification>> {
+        while let Some(msg) = self.stream.next().await {
+            match msg.get_payload::<String>() {
+                Ok(payload) => {
+                    return Ok(Some(Notification {
+                        channel: msg.get_channel_name().to_string(),
+                        payload,
+                    }))
                 }
+                Err(e) => tracing::warn!(error = %e, "Failed to get message payload"),
             }
         }
 
-        Ok(())
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Broadcaster for PubSub {
+    /// Delivers to the local sessions right away, then fans out to the other
+    /// instances.
+    async fn broadcast(
+        &self,
+        game_id: &str,
+        message: &Outbound,
+        exclude_session_id: &str,
+    ) -> Result<()> {
+        self.registry
+            .broadcast_except(game_id, message, exclude_session_id);
+
+        let payload =
+            MessagePayload::new(self.follower.instance_id(), exclude_session_id, message)?;
+        self.publish(
+            self.keys.game_channel(game_id),
+            serde_json::to_string(&payload)?,
+        )
+        .await
+    }
+
+    async fn control(&self, message: &ControlMessage) -> Result<()> {
+        // Applied locally right away; the echo from Redis is then a no-op.
+        message.apply(&self.registry);
+
+        self.publish(self.keys.control_channel(), serde_json::to_string(message)?)
+            .await
+            .context("Failed to publish control message to Redis")
+    }
+
+    /// Whenever the connection to Redis fails or drops, reconnects with
+    /// exponential backoff and re-syncs the local games, since anything
+    /// published meanwhile was missed.
+    async fn run(&self, shutdown: watch::Receiver<bool>, game_events: UnboundedReceiver<String>) {
+        let follow = self
+            .follower
+            .run(|| self.connect(), shutdown.clone(), game_events);
+
+        match &self.events {
+            Some(events) => {
+                tokio::join!(follow, self.follower.consume(events, shutdown));
+            }
+            None => follow.await,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.follower.is_connected()
     }
 }
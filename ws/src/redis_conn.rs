@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    Client,
+};
+use tokio::sync::OnceCell;
+
+use crate::config::RedisConfig;
+
+/// One multiplexed Redis connection, opened on first use and kept for the
+/// life of the process. Every clone shares it, and it reconnects on its own
+/// when Redis drops it. Opening it lazily lets the server start while
+/// Redis is down.
+///
+/// A failed (re)connect is not retried in place: the command that needed it
+/// fails right away, and the next command tries again, so a Redis outage
+/// never stalls a session for longer than the connect timeout.
+#[derive(Clone)]
+pub struct RedisConnection {
+    client: Client,
+    config: RedisConfig,
+    manager: Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisConnection {
+    pub fn new(client: Client, config: RedisConfig) -> Self {
+        Self {
+            client,
+            config,
+            manager: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub fn open(redis_url: &str, config: RedisConfig) -> Result<Self> {
+        let client = Client::open(redis_url).context("Failed to create redis client")?;
+
+        Ok(Self::new(client, config))
+    }
+
+    /// A handle on the shared connection. Handles are cheap to clone and
+    /// pipeline their commands over the same socket.
+    pub async fn get(&self) -> Result<ConnectionManager> {
+        let manager = self
+            .manager
+            .get_or_try_init(|| {
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(self.config.connect_timeout)
+                    .set_response_timeout(self.config.response_timeout)
+                    .set_number_of_retries(0);
+                ConnectionManager::new_with_config(self.client.clone(), config)
+            })
+            .await
+            .context("Failed to connect to Redis")?;
+
+        Ok(manager.clone())
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    ops::Deref,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use actix_ws::{CloseCode, CloseReason};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::codec::Encoding;
+
+/// A JSON message that reads the same for every session it is sent to.
+/// Clones share one buffer, and each binary encoding of it is made once, by
+/// the first writer that needs it, however many sessions a broadcast
+/// reaches.
+#[derive(Clone)]
+pub struct SharedText(Arc<SharedTextInner>);
+
+struct SharedTextInner {
+    json: String,
+    msgpack: OnceLock<Vec<u8>>,
+    cbor: OnceLock<Vec<u8>>,
+}
+
+impl SharedText {
+    pub fn new(json: String) -> Self {
+        Self(Arc::new(SharedTextInner {
+            json,
+            msgpack: OnceLock::new(),
+            cbor: OnceLock::new(),
+        }))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0.json
+    }
+
+    /// The message in a binary `encoding`, encoded on first use.
+    pub fn encoded(&self, encoding: Encoding) -> anyhow::Result<Vec<u8>> {
+        let cache = match encoding {
+            Encoding::Json => return encoding.encode_binary(&self.0.json),
+            Encoding::MessagePack => &self.0.msgpack,
+            Encoding::Cbor => &self.0.cbor,
+        };
+
+        if let Some(bytes) = cache.get() {
+            return Ok(bytes.clone());
+        }
+        let bytes = encoding.encode_binary(&self.0.json)?;
+        // Writers racing on the first use encode twice, one copy is kept.
+        let _ = cache.set(bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Whether `encoding` of the message has been made already.
+    pub fn is_encoded(&self, encoding: Encoding) -> bool {
+        match encoding {
+            Encoding::Json => true,
+            Encoding::MessagePack => self.0.msgpack.get().is_some(),
+            Encoding::Cbor => self.0.cbor.get().is_some(),
+        }
+    }
+}
+
+impl Deref for SharedText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for SharedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl From<String> for SharedText {
+    fn from(json: String) -> Self {
+        Self::new(json)
+    }
+}
+
+impl From<&str> for SharedText {
+    fn from(json: &str) -> Self {
+        Self::new(json.to_string())
+    }
+}
+
+/// What the writer task of a session should do next.
+#[derive(Debug, Clone)]
+pub enum Outbound {
+    Text(SharedText),
+    /// A stored game state, projected per viewer by the writer. A newer one
+    /// supersedes any that is still queued.
+    State(String),
+    /// A game change as both a `move_applied` delta and the stored state it
+    /// results in. The writer sends whichever the session negotiated.
+    Delta {
+        delta: SharedText,
+        state: String,
+    },
+    /// A chat line or emote by the user `from`, skipped by sessions that
+    /// muted them.
+    Chat {
+        from: Uuid,
+        message: SharedText,
+    },
+    Close(CloseReason),
+}
+
+impl Outbound {
+    fn is_game_update(&self) -> bool {
+        matches!(self, Outbound::State(_) | Outbound::Delta { .. })
+    }
+}
+
+/// What to do when a session's outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued message to make room.
+    DropOldest,
+    /// Drop queued game states and deltas that a newer one supersedes, then
+    /// fall back to dropping the oldest message. Sessions applying deltas
+    /// see the gap in sequence numbers and ask for a snapshot.
+    CoalesceState,
+    /// Close the slow client's socket.
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(Self::DropOldest),
+            "coalesce" => Ok(Self::CoalesceState),
+            "disconnect" => Ok(Self::Disconnect),
+            other => anyhow::bail!(
+                "Unknown overflow policy {:?}, expected drop_oldest, coalesce or disconnect",
+                other
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PushOutcome {
+    Queued,
+    /// The message was queued after dropping this many older ones.
+    Dropped(usize),
+    /// The queue overflowed and the session is being closed.
+    Disconnected,
+    /// The session is already closed, the message was discarded.
+    Closed,
+}
+
+struct QueueState {
+    items: VecDeque<Outbound>,
+    closed: bool,
+}
+
+/// Bounded outbound queue between broadcasters and a session's writer task.
+pub struct SessionQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+}
+
+impl SessionQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn push(&self, msg: Outbound) -> PushOutcome {
+        let mut state = self.state.lock().unwrap();
+
+        if state.closed {
+            return PushOutcome::Closed;
+        }
+
+        // Close frames always go through so a full queue can still be shut.
+        if matches!(msg, Outbound::Close(_)) {
+            state.items.push_back(msg);
+            state.closed = true;
+            drop(state);
+            self.notify.notify_one();
+            return PushOutcome::Queued;
+        }
+
+        let mut dropped = 0;
+
+        if state.items.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Disconnect => {
+                    state.items.clear();
+                    state.items.push_back(Outbound::Close(CloseReason {
+                        code: CloseCode::Policy,
+                        description: Some("Client is too slow".to_string()),
+                    }));
+                    state.closed = true;
+                    drop(state);
+                    self.notify.notify_one();
+                    return PushOutcome::Disconnected;
+                }
+                OverflowPolicy::CoalesceState => {
+                    if msg.is_game_update() {
+                        let before = state.items.len();
+                        state.items.retain(|m| !m.is_game_update());
+                        dropped += before - state.items.len();
+                    }
+                }
+                OverflowPolicy::DropOldest => {}
+            }
+
+            while state.items.len() >= self.capacity {
+                state.items.pop_front();
+                dropped += 1;
+            }
+        }
+
+        state.items.push_back(msg);
+        drop(state);
+        self.notify.notify_one();
+
+        if dropped > 0 {
+            self.dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+            PushOutcome::Dropped(dropped)
+        } else {
+            PushOutcome::Queued
+        }
+    }
+
+    /// Waits for the next message. Returns `None` once the queue is closed
+    /// and everything queued before that has been handed out.
+    pub async fn pop(&self) -> Option<Outbound> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(msg) = state.items.pop_front() {
+                    return Some(msg);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    /// Stops accepting messages and wakes the writer so it can finish.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Messages dropped so far because of overflow.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,546 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch,
+};
+use uuid::Uuid;
+use ws::{
+    broadcast::{
+        resync_game, Broadcaster, ControlMessage, Follower, InProcessBroadcaster, MessagePayload,
+        Notification, Subscription,
+    },
+    connection_registry::ConnectionRegistry,
+    game::{
+        events::{EventSource, GroupEvent},
+        game_manager::GameManager,
+        messages::WsServerMessage,
+        store::MemoryGameStore,
+    },
+    keys::Keys,
+    metrics::WsMetrics,
+    session_queue::{Outbound, OverflowPolicy, SessionQueue},
+};
+
+fn queue() -> Arc<SessionQueue> {
+    Arc::new(SessionQueue::new(8, OverflowPolicy::DropOldest))
+}
+
+/// Waits until `check` passes or gives up after a few seconds.
+async fn eventually<T>(what: &str, mut check: impl FnMut() -> Option<T>) -> T {
+    for _ in 0..5000 {
+        if let Some(value) = check() {
+            return value;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    panic!("timed out waiting for {}", what);
+}
+
+/// A transport whose connections are channels, so tests can see what is
+/// listened on, send notifications and drop the connection.
+#[derive(Clone, Default)]
+struct FakeTransport {
+    listening: Arc<Mutex<HashSet<String>>>,
+    connection: Arc<Mutex<Option<UnboundedSender<Notification>>>>,
+    connects: Arc<AtomicUsize>,
+}
+
+impl FakeTransport {
+    async fn connect(&self) -> Result<FakeSubscription> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.listening.lock().unwrap().clear();
+        *self.connection.lock().unwrap() = Some(tx);
+        self.connects.fetch_add(1, Ordering::SeqCst);
+
+        Ok(FakeSubscription {
+            listening: self.listening.clone(),
+            rx,
+        })
+    }
+
+    fn is_listening(&self, channel: &str) -> bool {
+        self.listening.lock().unwrap().contains(channel)
+    }
+
+    fn send(&self, channel: String, payload: String) {
+        let connection = self.connection.lock().unwrap();
+        let notification = Notification { channel, payload };
+        connection
+            .as_ref()
+            .unwrap()
+            .send(notification)
+            .ok()
+            .unwrap();
+    }
+
+    fn drop_connection(&self) {
+        self.connection.lock().unwrap().take();
+    }
+}
+
+struct FakeSubscription {
+    listening: Arc<Mutex<HashSet<String>>>,
+    rx: UnboundedReceiver<Notification>,
+}
+
+#[async_trait]
+impl Subscription for FakeSubscription {
+    async fn listen(&mut self, channel: &str) -> Result<()> {
+        self.listening.lock().unwrap().insert(channel.to_string());
+        Ok(())
+    }
+
+    async fn unlisten(&mut self, channel: &str) -> Result<()> {
+        self.listening.lock().unwrap().remove(channel);
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Option<Notification>> {
+        Ok(self.rx.recv().await)
+    }
+}
+
+/// Consumer groups in memory: every group sees `entries` until it
+/// acknowledges them.
+#[derive(Clone, Default)]
+struct FakeEvents {
+    entries: Arc<Mutex<Vec<GroupEvent>>>,
+    joined: Arc<Mutex<HashSet<String>>>,
+    acked: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
+
+impl FakeEvents {
+    fn push(&self, game_id: &str, id: &str, seq: u64) {
+        self.entries.lock().unwrap().push(GroupEvent {
+            game_id: game_id.to_string(),
+            id: id.to_string(),
+            seq: Some(seq),
+        });
+    }
+
+    fn has_joined(&self, game_id: &str) -> bool {
+        self.joined.lock().unwrap().contains(game_id)
+    }
+
+    fn acked(&self, game_id: &str) -> Vec<String> {
+        let acked = self.acked.lock().unwrap();
+        acked.get(game_id).cloned().unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl EventSource for FakeEvents {
+    async fn join(&self, game_id: &str, _group: &str) -> Result<bool> {
+        self.joined.lock().unwrap().insert(game_id.to_string());
+        Ok(true)
+    }
+
+    async fn leave(&self, game_id: &str, _group: &str) -> Result<()> {
+        self.joined.lock().unwrap().remove(game_id);
+        Ok(())
+    }
+
+    async fn read(&self, _group: &str, game_ids: &[String]) -> Result<Vec<GroupEvent>> {
+        let acked = self.acked.lock().unwrap();
+        let entries = self.entries.lock().unwrap();
+
+        Ok(entries
+            .iter()
+            .filter(|entry| game_ids.contains(&entry.game_id))
+            .filter(|entry| {
+                let acked = acked.get(&entry.game_id);
+                !acked.is_some_and(|ids| ids.contains(&entry.id))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn ack(&self, game_id: &str, _group: &str, ids: &[String]) -> Result<()> {
+        let mut acked = self.acked.lock().unwrap();
+        acked
+            .entry(game_id.to_string())
+            .or_default()
+            .extend(ids.iter().cloned());
+        Ok(())
+    }
+}
+
+struct Running {
+    registry: Arc<ConnectionRegistry>,
+    games: Arc<GameManager>,
+    follower: Arc<Follower>,
+    transport: FakeTransport,
+    keys: Keys,
+    shutdown: watch::Sender<bool>,
+}
+
+/// A follower running over a `FakeTransport`, connected to the control
+/// channel.
+async fn follow() -> Running {
+    let (game_events, events) = mpsc::unbounded_channel();
+    let registry = Arc::new(ConnectionRegistry::with_game_events(game_events));
+    let games = Arc::new(GameManager::new(Arc::new(MemoryGameStore::new())));
+    let keys = Keys::new("test");
+    let follower = Arc::new(Follower::new(
+        registry.clone(),
+        games.clone(),
+        Arc::new(WsMetrics::new("ws-1").unwrap()),
+        keys.clone(),
+        "fake",
+    ));
+    let transport = FakeTransport::default();
+    let (shutdown, stop) = watch::channel(false);
+
+    tokio::spawn({
+        let follower = follower.clone();
+        let transport = transport.clone();
+        async move { follower.run(|| transport.connect(), stop, events).await }
+    });
+    let control = keys.control_channel();
+    eventually("the control channel", || {
+        transport.is_listening(&control).then_some(())
+    })
+    .await;
+    assert!(follower.is_connected());
+
+    Running {
+        registry,
+        games,
+        follower,
+        transport,
+        keys,
+        shutdown,
+    }
+}
+
+fn published(origin: &str, text: &str) -> String {
+    let payload = MessagePayload::new(origin, "other-session", &Outbound::Text(text.into()));
+    serde_json::to_string(&payload.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn resync_sends_the_stored_state_to_every_local_session() {
+    let games = GameManager::new(Arc::new(MemoryGameStore::new()));
+    let registry = ConnectionRegistry::new();
+    let alice = Uuid::new_v4();
+    games
+        .create_game(alice, "alice".into(), "s1".into(), "g1".into(), false)
+        .await
+        .unwrap();
+
+    let (player, spectator, elsewhere) = (queue(), queue(), queue());
+    registry.add("g1", "s1", alice, player.clone());
+    registry.add("g1", "s2", Uuid::new_v4(), spectator.clone());
+    registry.add("g2", "s3", Uuid::new_v4(), elsewhere.clone());
+
+    resync_game(&registry, &games, "g1").await;
+
+    for queue in [&player, &spectator] {
+        match queue.pop().await.unwrap() {
+            Outbound::State(state) => assert!(state.contains("\"g1\"")),
+            _ => panic!("expected the game state"),
+        }
+    }
+    assert!(elsewhere.is_empty());
+}
+
+#[tokio::test]
+async fn resync_skips_games_that_are_not_stored() {
+    let games = GameManager::new(Arc::new(MemoryGameStore::new()));
+    let registry = ConnectionRegistry::new();
+    let session = queue();
+    registry.add("gone", "s1", Uuid::new_v4(), session.clone());
+
+    resync_game(&registry, &games, "gone").await;
+
+    assert!(session.is_empty());
+}
+
+#[tokio::test]
+async fn follower_listens_on_the_games_with_local_sessions() {
+    let running = follow().await;
+    let channel = running.keys.game_channel("g1");
+
+    running.registry.add("g1", "s1", Uuid::new_v4(), queue());
+    eventually("a listen", || {
+        running.transport.is_listening(&channel).then_some(())
+    })
+    .await;
+
+    running.registry.remove("g1", "s1");
+    eventually("an unlisten", || {
+        (!running.transport.is_listening(&channel)).then_some(())
+    })
+    .await;
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn follower_delivers_what_other_instances_published() {
+    let running = follow().await;
+    let session = queue();
+    running
+        .registry
+        .add("g1", "s1", Uuid::new_v4(), session.clone());
+    let channel = running.keys.game_channel("g1");
+    eventually("a listen", || {
+        running.transport.is_listening(&channel).then_some(())
+    })
+    .await;
+
+    let own = running.follower.instance_id().to_string();
+    running
+        .transport
+        .send(channel.clone(), published(&own, "echo"));
+    running.transport.send(channel, published("ws-2", "hello"));
+
+    match session.pop().await.unwrap() {
+        Outbound::Text(text) => assert_eq!(&*text, "hello"),
+        _ => panic!("expected the published text"),
+    }
+    assert!(session.is_empty());
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn follower_applies_control_messages() {
+    let running = follow().await;
+    let user = Uuid::new_v4();
+    let session = queue();
+    running.registry.add("g1", "s1", user, session.clone());
+
+    let control = ControlMessage::DisconnectUser {
+        user_id: user,
+        reason: "Banned".to_string(),
+    };
+    running.transport.send(
+        running.keys.control_channel(),
+        serde_json::to_string(&control).unwrap(),
+    );
+
+    match session.pop().await.unwrap() {
+        Outbound::Close(reason) => assert_eq!(reason.description.as_deref(), Some("Banned")),
+        _ => panic!("expected a close frame"),
+    }
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn closing_a_game_closes_only_its_sockets() {
+    let running = follow().await;
+    let closed = queue();
+    let other = queue();
+    running
+        .registry
+        .add("g1", "s1", Uuid::new_v4(), closed.clone());
+    running
+        .registry
+        .add("g2", "s2", Uuid::new_v4(), other.clone());
+
+    let control = ControlMessage::CloseGame {
+        game_id: "g1".to_string(),
+        reason: "Game was deleted".to_string(),
+    };
+    running.transport.send(
+        running.keys.control_channel(),
+        serde_json::to_string(&control).unwrap(),
+    );
+
+    match closed.pop().await.unwrap() {
+        Outbound::Close(reason) => {
+            assert_eq!(reason.description.as_deref(), Some("Game was deleted"))
+        }
+        _ => panic!("expected a close frame"),
+    }
+    assert!(!running.registry.games.contains_key("g1"));
+    assert!(other.is_empty());
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn follower_reconnects_and_resyncs_after_losing_the_connection() {
+    let running = follow().await;
+    let alice = Uuid::new_v4();
+    running
+        .games
+        .create_game(alice, "alice".into(), "s1".into(), "g1".into(), false)
+        .await
+        .unwrap();
+    let session = queue();
+    running.registry.add("g1", "s1", alice, session.clone());
+    let channel = running.keys.game_channel("g1");
+    eventually("a listen", || {
+        running.transport.is_listening(&channel).then_some(())
+    })
+    .await;
+    // Following the game re-syncs it once.
+    assert!(matches!(session.pop().await, Some(Outbound::State(_))));
+
+    running.transport.drop_connection();
+
+    eventually("a second connection", || {
+        (running.transport.connects.load(Ordering::SeqCst) == 2
+            && running.transport.is_listening(&channel))
+        .then_some(())
+    })
+    .await;
+    assert!(matches!(session.pop().await, Some(Outbound::State(_))));
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn in_process_broadcasts_reach_the_other_sessions_of_the_game() {
+    let registry = Arc::new(ConnectionRegistry::new());
+    let broadcaster = InProcessBroadcaster::new(registry.clone());
+    let (sender, other, elsewhere) = (queue(), queue(), queue());
+    registry.add("g1", "s1", Uuid::new_v4(), sender.clone());
+    registry.add("g1", "s2", Uuid::new_v4(), other.clone());
+    registry.add("g2", "s3", Uuid::new_v4(), elsewhere.clone());
+
+    broadcaster
+        .broadcast("g1", &Outbound::Text("move".into()), "s1")
+        .await
+        .unwrap();
+
+    assert!(sender.is_empty());
+    assert!(elsewhere.is_empty());
+    match other.pop().await.unwrap() {
+        Outbound::Text(text) => assert_eq!(&*text, "move"),
+        _ => panic!("expected the broadcast"),
+    }
+    assert!(broadcaster.is_connected());
+}
+
+#[tokio::test]
+async fn in_process_control_messages_apply_locally() {
+    let registry = Arc::new(ConnectionRegistry::new());
+    let broadcaster = InProcessBroadcaster::new(registry.clone());
+    let user = Uuid::new_v4();
+    registry.add("g1", "s1", user, queue());
+    registry.add("g2", "s2", user, queue());
+
+    let control = ControlMessage::DisconnectUser {
+        user_id: user,
+        reason: "Banned".to_string(),
+    };
+    broadcaster.control(&control).await.unwrap();
+
+    assert!(registry.is_empty());
+}
+
+/// Starts consuming `events` on a running follower.
+fn consume(running: &Running, events: &FakeEvents) {
+    let follower = running.follower.clone();
+    let events = events.clone();
+    let stop = running.shutdown.subscribe();
+
+    tokio::spawn(async move { follower.consume(&events, stop).await });
+}
+
+/// A followed game `g1` with one session, past the state sent on follow.
+async fn followed_game(running: &Running) -> Arc<SessionQueue> {
+    let alice = Uuid::new_v4();
+    running
+        .games
+        .create_game(alice, "alice".into(), "s1".into(), "g1".into(), false)
+        .await
+        .unwrap();
+    let session = queue();
+    running.registry.add("g1", "s1", alice, session.clone());
+    let channel = running.keys.game_channel("g1");
+    eventually("a listen", || {
+        running.transport.is_listening(&channel).then_some(())
+    })
+    .await;
+    assert!(matches!(session.pop().await, Some(Outbound::State(_))));
+
+    session
+}
+
+#[tokio::test]
+async fn missed_events_resync_their_game_after_a_grace_period() {
+    let running = follow().await;
+    let session = followed_game(&running).await;
+    // Bob joins on another instance, and its message never arrives.
+    running
+        .games
+        .join_game("g1", Uuid::new_v4(), "bob".into(), "s2".into(), false)
+        .await
+        .unwrap();
+    let events = FakeEvents::default();
+    events.push("g1", "1-0", 1);
+
+    consume(&running, &events);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(session.is_empty());
+    assert!(events.acked("g1").is_empty());
+
+    match tokio::time::timeout(Duration::from_secs(5), session.pop()).await {
+        Ok(Some(Outbound::State(state))) => assert!(state.contains("\"seq\":1")),
+        _ => panic!("expected the re-synced state"),
+    }
+    eventually("the ack", || (events.acked("g1") == ["1-0"]).then_some(())).await;
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn delivered_events_are_acknowledged_without_a_resync() {
+    let running = follow().await;
+    let session = followed_game(&running).await;
+    let game = running
+        .games
+        .join_game("g1", Uuid::new_v4(), "bob".into(), "s2".into(), false)
+        .await
+        .unwrap();
+    let state = WsServerMessage::state_outbound(&game).unwrap();
+    let payload = MessagePayload::new("ws-2", "s2", &state).unwrap();
+    running.transport.send(
+        running.keys.game_channel("g1"),
+        serde_json::to_string(&payload).unwrap(),
+    );
+    assert!(matches!(session.pop().await, Some(Outbound::State(_))));
+
+    let events = FakeEvents::default();
+    events.push("g1", "1-0", 0);
+    events.push("g1", "2-0", 1);
+    consume(&running, &events);
+
+    eventually("the acks", || {
+        (events.acked("g1") == ["1-0", "2-0"]).then_some(())
+    })
+    .await;
+    assert!(session.is_empty());
+
+    running.shutdown.send(true).unwrap();
+}
+
+#[tokio::test]
+async fn follower_reads_the_events_of_games_with_local_sessions_only() {
+    let running = follow().await;
+    let events = FakeEvents::default();
+    consume(&running, &events);
+
+    running.registry.add("g1", "s1", Uuid::new_v4(), queue());
+    eventually("a join", || events.has_joined("g1").then_some(())).await;
+
+    running.registry.remove("g1", "s1");
+    eventually("a leave", || (!events.has_joined("g1")).then_some(())).await;
+
+    running.shutdown.send(true).unwrap();
+}
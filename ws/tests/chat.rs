@@ -0,0 +1,166 @@
+use std::{sync::Arc, time::Duration};
+
+use uuid::Uuid;
+use ws::{
+    config::ChatConfig,
+    game::{
+        chat::{ChatManager, ChatRecord, MemoryChatStore, RateLimiter},
+        messages::WsServerMessage,
+    },
+};
+
+fn chat(config: ChatConfig) -> ChatManager {
+    ChatManager::new(Arc::new(MemoryChatStore::new()), config)
+}
+
+fn line(sender: Uuid, text: &str) -> ChatRecord {
+    ChatRecord {
+        sender,
+        message: WsServerMessage::Chat {
+            from: "alice".to_string(),
+            text: text.to_string(),
+            sent_at: 0,
+        },
+    }
+}
+
+fn texts(history: &[ChatRecord]) -> Vec<&str> {
+    history
+        .iter()
+        .map(|record| match &record.message {
+            WsServerMessage::Chat { text, .. } => text.as_str(),
+            other => panic!("unexpected {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn blocklisted_words_are_masked() {
+    let chat = chat(ChatConfig {
+        blocklist: vec!["darn".to_string()],
+        ..ChatConfig::default()
+    });
+
+    assert_eq!(
+        chat.sanitize("  Darn, so close!  ").unwrap(),
+        "****, so close!"
+    );
+    assert_eq!(chat.sanitize("darnation").unwrap(), "darnation");
+    assert_eq!(chat.sanitize("darn!darn").unwrap(), "****!****");
+    assert_eq!(chat.sanitize("oh\tDARN\nit").unwrap(), "oh\t****\nit");
+    assert_eq!(chat.sanitize("\"darn\"...").unwrap(), "\"****\"...");
+}
+
+#[test]
+fn empty_and_long_lines_are_rejected() {
+    let chat = chat(ChatConfig {
+        max_length: 5,
+        ..ChatConfig::default()
+    });
+
+    assert!(chat.sanitize("   ").is_err());
+    assert!(chat.sanitize("123456").is_err());
+    assert_eq!(chat.sanitize("héllo").unwrap(), "héllo");
+}
+
+#[test]
+fn rate_limiter_allows_a_burst_per_window() {
+    let mut limiter = RateLimiter::new(2, Duration::from_millis(50));
+
+    assert!(limiter.check());
+    assert!(limiter.check());
+    assert!(!limiter.check());
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(limiter.check());
+}
+
+#[test]
+fn rate_limit_is_shared_by_the_sockets_of_a_user() {
+    let chat = chat(ChatConfig {
+        rate_limit: 2,
+        ..ChatConfig::default()
+    });
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    assert!(chat.allow(alice));
+    assert!(chat.allow(alice));
+    assert!(!chat.allow(alice));
+    assert!(chat.allow(bob));
+}
+
+#[tokio::test]
+async fn history_keeps_the_newest_lines() {
+    let chat = chat(ChatConfig {
+        history_len: 2,
+        ..ChatConfig::default()
+    });
+    let alice = Uuid::new_v4();
+
+    for text in ["one", "two", "three"] {
+        chat.append_history("g1", &line(alice, text)).await.unwrap();
+    }
+
+    assert_eq!(texts(&chat.history("g1").await.unwrap()), ["two", "three"]);
+    assert!(chat.history("g2").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn mutes_are_kept_per_muter_and_game_by_user_id() {
+    let chat = chat(ChatConfig::default());
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    chat.set_muted("g1", alice, bob, true).await.unwrap();
+    assert_eq!(
+        chat.muted("g1", alice)
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [bob]
+    );
+    assert!(chat.muted("g1", bob).await.unwrap().is_empty());
+    assert!(chat.muted("g2", alice).await.unwrap().is_empty());
+
+    chat.set_muted("g1", alice, bob, false).await.unwrap();
+    assert!(chat.muted("g1", alice).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn clearing_a_game_forgets_its_history_and_mutes() {
+    let chat = chat(ChatConfig::default());
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    for game_id in ["g1", "g2"] {
+        chat.append_history(game_id, &line(alice, "hi"))
+            .await
+            .unwrap();
+        chat.set_muted(game_id, alice, bob, true).await.unwrap();
+    }
+    chat.clear("g1").await.unwrap();
+
+    assert!(chat.history("g1").await.unwrap().is_empty());
+    assert!(chat.muted("g1", alice).await.unwrap().is_empty());
+    assert_eq!(texts(&chat.history("g2").await.unwrap()), ["hi"]);
+    assert!(chat.muted("g2", alice).await.unwrap().contains(&bob));
+}
+
+#[tokio::test]
+async fn loaded_mutes_are_known_until_forgotten() {
+    let chat = chat(ChatConfig::default());
+    let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    chat.set_muted("g1", alice, bob, true).await.unwrap();
+    assert!(chat.is_muted("g1", alice, bob));
+    assert!(!chat.is_muted("g1", alice, carol));
+    assert!(!chat.is_muted("g2", alice, bob));
+
+    chat.forget_muted("g1", alice);
+    assert!(!chat.is_muted("g1", alice, bob));
+
+    chat.muted("g1", alice).await.unwrap();
+    assert!(chat.is_muted("g1", alice, bob));
+
+    chat.set_muted("g1", alice, bob, false).await.unwrap();
+    assert!(!chat.is_muted("g1", alice, bob));
+}
@@ -0,0 +1,58 @@
+use serde_json::json;
+use ws::{
+    codec::{ClientFrame, Encoding},
+    session_queue::SharedText,
+};
+
+fn message() -> serde_json::Value {
+    json!({
+        "type": "move_applied",
+        "position": 4,
+        "symbol": "X",
+        "next_turn": "O",
+        "status": "InProgress",
+        "winner": null,
+        "seq": 3,
+    })
+}
+
+#[test]
+fn binary_encodings_round_trip() {
+    for encoding in [Encoding::MessagePack, Encoding::Cbor] {
+        let bytes = encoding.encode_binary(&message().to_string()).unwrap();
+        let decoded = encoding.decode(ClientFrame::Binary(&bytes)).unwrap();
+
+        assert_eq!(decoded, message(), "{}", encoding.name());
+    }
+}
+
+#[test]
+fn cbor_is_not_msgpack() {
+    let json = message().to_string();
+    let cbor = Encoding::Cbor.encode_binary(&json).unwrap();
+
+    assert_ne!(cbor, Encoding::MessagePack.encode_binary(&json).unwrap());
+    assert_ne!(
+        Encoding::MessagePack
+            .decode(ClientFrame::Binary(&cbor))
+            .ok(),
+        Some(message())
+    );
+}
+
+#[test]
+fn shared_text_is_encoded_once_for_all_its_clones() {
+    let text = SharedText::from(message().to_string());
+    let other_session = text.clone();
+    assert!(!other_session.is_encoded(Encoding::Cbor));
+
+    let bytes = text.encoded(Encoding::Cbor).unwrap();
+
+    assert!(other_session.is_encoded(Encoding::Cbor));
+    assert!(!other_session.is_encoded(Encoding::MessagePack));
+    assert_eq!(other_session.encoded(Encoding::Cbor).unwrap(), bytes);
+    assert_eq!(
+        Encoding::Cbor.decode(ClientFrame::Binary(&bytes)).unwrap(),
+        message()
+    );
+}
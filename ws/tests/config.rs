@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use ws::{config::WsConfig, keys::Keys, session_queue::OverflowPolicy};
+
+#[test]
+fn file_settings_use_units_in_their_names() {
+    let mut config: WsConfig = toml::from_str(
+        r#"
+        heartbeat_interval_secs = 10
+        client_timeout_secs = 30
+        overflow_policy = "disconnect"
+        key_prefix = "staging"
+        game_ttl_secs = 600
+
+        [chat]
+        history = 20
+        blocklist = [" Darn ", ""]
+
+        [redis]
+        response_timeout_ms = 500
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.heartbeat_interval, Duration::from_secs(10));
+    assert_eq!(config.client_timeout, Duration::from_secs(30));
+    assert_eq!(config.overflow_policy, OverflowPolicy::Disconnect);
+    assert_eq!(config.keys, Keys::new("staging:"));
+    assert_eq!(config.game_ttl, Duration::from_secs(600));
+    assert_eq!(config.chat.history_len, 20);
+    assert_eq!(config.chat.blocklist, vec!["darn".to_string()]);
+    assert_eq!(config.redis.response_timeout, Duration::from_millis(500));
+    // Unset values keep their defaults.
+    assert_eq!(config.outbound_queue_capacity, 64);
+    assert_eq!(config.redis.connect_timeout, Duration::from_secs(2));
+}
+
+#[test]
+fn unknown_and_inconsistent_settings_are_rejected() {
+    assert!(toml::from_str::<WsConfig>("heartbeat_secs = 5").is_err());
+    assert!(toml::from_str::<WsConfig>(r#"overflow_policy = "block""#).is_err());
+
+    let mut config: WsConfig = toml::from_str("client_timeout_secs = 5").unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn zero_is_rejected_wherever_a_positive_value_is_needed() {
+    for setting in [
+        "heartbeat_interval_secs = 0",
+        "outbound_queue_capacity = 0",
+        "game_ttl_secs = 0",
+        "chat.max_length = 0",
+        "chat.rate_limit = 0",
+        "chat.rate_window_secs = 0",
+        "chat.history = 0",
+        "redis.connect_timeout_ms = 0",
+        "redis.response_timeout_ms = 0",
+    ] {
+        let mut config: WsConfig = toml::from_str(setting).unwrap();
+        assert!(config.validate().is_err(), "{} was accepted", setting);
+    }
+}
+
+#[test]
+fn a_zero_heartbeat_is_rejected_before_it_reaches_an_interval() {
+    let mut config = WsConfig {
+        heartbeat_interval: Duration::ZERO,
+        ..WsConfig::default()
+    };
+
+    let error = config.validate().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "WS_HEARTBEAT_INTERVAL_SECS must be a positive number of seconds"
+    );
+}
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+use ws::game::{
+    events::GameEvent,
+    game_manager::GameManager,
+    game_state::{Board, GameStatus, Player},
+    store::{GameStore, MemoryGameStore},
+};
+
+fn games() -> (GameManager, Arc<MemoryGameStore>) {
+    let store = Arc::new(MemoryGameStore::new());
+    (GameManager::new(store.clone()), store)
+}
+
+#[tokio::test]
+async fn a_game_is_played_to_a_win() {
+    let (games, _) = games();
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    games
+        .create_game(alice, "alice".into(), "s1".into(), "g1".into(), false)
+        .await
+        .unwrap();
+    games
+        .join_game("g1", bob, "bob".into(), "s2".into(), false)
+        .await
+        .unwrap();
+
+    for (user, position) in [(alice, 0), (bob, 3), (alice, 1), (bob, 4)] {
+        games.make_move("g1", user, position).await.unwrap();
+    }
+    let game = games.make_move("g1", alice, 2).await.unwrap();
+
+    assert_eq!(game.status, GameStatus::Finished);
+    assert_eq!(game.winner, Some(alice));
+    assert_eq!(game.seq, 6);
+
+    let events = games.events("g1", Some("5")).await.unwrap();
+    assert_eq!(
+        events.iter().map(|e| e.event.clone()).collect::<Vec<_>>(),
+        vec![
+            GameEvent::Moved {
+                position: 4,
+                symbol: Player::O
+            },
+            GameEvent::Moved {
+                position: 2,
+                symbol: Player::X
+            },
+            GameEvent::Finished {
+                status: GameStatus::Finished,
+                winner: Some(Player::X)
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_rejected_move_leaves_the_game_untouched() {
+    let (games, store) = games();
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    games
+        .create_game(alice, "alice".into(), "s1".into(), "g1".into(), false)
+        .await
+        .unwrap();
+    games
+        .join_game("g1", bob, "bob".into(), "s2".into(), false)
+        .await
+        .unwrap();
+
+    let err = games.make_move("g1", bob, 0).await.unwrap_err();
+    assert_eq!(err.to_string(), "Not your turn");
+
+    let game = store.get("g1").await.unwrap().unwrap();
+    assert_eq!(game.seq, 1);
+    assert_eq!(game.board, Board::default());
+    assert_eq!(store.events("g1", None).await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn a_game_created_on_a_used_id_starts_a_new_log() {
+    let (games, store) = games();
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    games
+        .create_game(alice, "alice".into(), "s1".into(), "g1".into(), false)
+        .await
+        .unwrap();
+    games
+        .join_game("g1", bob, "bob".into(), "s2".into(), false)
+        .await
+        .unwrap();
+    games
+        .create_game(bob, "bob".into(), "s3".into(), "g1".into(), false)
+        .await
+        .unwrap();
+
+    let events = store.events("g1", None).await.unwrap();
+    assert_eq!(
+        events.iter().map(|e| e.event.clone()).collect::<Vec<_>>(),
+        vec![GameEvent::Created {
+            player: "bob".into(),
+            rated: false
+        }]
+    );
+    assert_eq!(events[0].id, "1");
+}
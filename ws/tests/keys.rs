@@ -0,0 +1,20 @@
+use ws::keys::Keys;
+
+#[test]
+fn state_and_channels_live_in_separate_namespaces() {
+    let keys = Keys::new("staging:");
+
+    assert_eq!(keys.game_state("g1"), "staging:state:game:g1");
+    assert_eq!(keys.game_channel("g1"), "staging:chan:game:g1");
+    assert_ne!(keys.game_state("g1"), keys.game_channel("g1"));
+
+    assert_eq!(
+        keys.game_id_from_channel(&keys.game_channel("g1")),
+        Some("g1")
+    );
+    assert_eq!(keys.game_id_from_channel(&keys.game_state("g1")), None);
+    assert_eq!(
+        Keys::default().game_id_from_channel(&keys.game_channel("g1")),
+        None
+    );
+}
@@ -0,0 +1,50 @@
+use std::{sync::Arc, time::Duration};
+
+use uuid::Uuid;
+use ws::{
+    connection_registry::ConnectionRegistry,
+    metrics::WsMetrics,
+    session_queue::{OverflowPolicy, SessionQueue},
+};
+
+fn queue() -> Arc<SessionQueue> {
+    Arc::new(SessionQueue::new(8, OverflowPolicy::DropOldest))
+}
+
+#[test]
+fn render_labels_every_series_with_the_instance() {
+    let metrics = WsMetrics::new("ws-1").unwrap();
+    let registry = ConnectionRegistry::new();
+    registry.add("g1", "s1", Uuid::new_v4(), queue());
+    registry.add("g1", "s2", Uuid::new_v4(), queue());
+    registry.add("g2", "s3", Uuid::new_v4(), queue());
+
+    metrics.record_message("make_move");
+    metrics.record_message("make_move");
+    metrics.record_message("chat");
+    metrics.observe_move(Duration::from_millis(3));
+    metrics.record_publish_error();
+
+    let text = metrics.render(&registry).unwrap();
+
+    assert!(text.contains(r#"ws_connections{instance="ws-1"} 3"#));
+    assert!(text.contains(r#"ws_games{instance="ws-1"} 2"#));
+    assert!(text.contains(r#"ws_messages_total{type="make_move",instance="ws-1"} 2"#));
+    assert!(text.contains(r#"ws_messages_total{type="chat",instance="ws-1"} 1"#));
+    assert!(text.contains(r#"ws_move_duration_seconds_count{instance="ws-1"} 1"#));
+    assert!(text.contains(r#"ws_broadcast_errors_total{operation="publish",instance="ws-1"} 1"#));
+}
+
+#[test]
+fn gauges_follow_the_registry() {
+    let metrics = WsMetrics::new("ws-1").unwrap();
+    let registry = ConnectionRegistry::new();
+    registry.add("g1", "s1", Uuid::new_v4(), queue());
+    metrics.render(&registry).unwrap();
+
+    registry.remove("g1", "s1");
+    let text = metrics.render(&registry).unwrap();
+
+    assert!(text.contains(r#"ws_connections{instance="ws-1"} 0"#));
+    assert!(text.contains(r#"ws_games{instance="ws-1"} 0"#));
+}
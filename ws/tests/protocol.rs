@@ -0,0 +1,72 @@
+use uuid::Uuid;
+use ws::{codec::Encoding, game::game_state::GameState, protocol::SessionProtocol};
+
+fn game() -> GameState {
+    let mut game = GameState::new(
+        "game-1".to_string(),
+        Uuid::new_v4(),
+        "alice".to_string(),
+        "alice-session".to_string(),
+        false,
+    );
+    game.player2_id = Some(Uuid::new_v4());
+    game.player2_name = Some("bob".to_string());
+    game.player2_session = Some("bob-session".to_string());
+    game.current_turn = game.player2_id;
+    game
+}
+
+/// The `game` object a version 1 session gets for `viewer`.
+fn render_v1(game: &GameState, viewer: Uuid) -> serde_json::Value {
+    let protocol = SessionProtocol::new(Encoding::Json);
+    let state = serde_json::to_string(game).unwrap();
+    let json = protocol.render_state(&state, viewer).unwrap();
+
+    let message: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(message["type"], "game_state");
+    message["game"].clone()
+}
+
+#[test]
+fn v1_players_see_only_their_own_user_id() {
+    let game = game();
+    let alice = game.player1_id;
+    let bob = game.player2_id.unwrap();
+
+    let view = render_v1(&game, alice);
+    let json = view.to_string();
+
+    assert_eq!(view["player1_id"], alice.to_string());
+    assert!(!json.contains(&bob.to_string()));
+    assert!(!json.contains("alice-session") && !json.contains("bob-session"));
+    // Turn and seat still line up, as v1 clients compare them.
+    assert_eq!(view["current_turn"], view["player2_id"]);
+    assert_eq!(view["player1_session"], "");
+    assert_eq!(view["player2_session"], "");
+}
+
+#[test]
+fn v1_spectators_see_no_user_ids() {
+    let game = game();
+    let spectator = Uuid::new_v4();
+
+    let view = render_v1(&game, spectator);
+    let json = view.to_string();
+
+    assert!(!json.contains(&game.player1_id.to_string()));
+    assert!(!json.contains(&game.player2_id.unwrap().to_string()));
+    assert_ne!(view["player1_id"], view["player2_id"]);
+    assert_eq!(view["current_turn"], view["player2_id"]);
+}
+
+#[test]
+fn v1_pseudonyms_are_stable_across_viewers() {
+    let game = game();
+
+    let for_alice = render_v1(&game, game.player1_id);
+    let for_spectator = render_v1(&game, Uuid::new_v4());
+    let again = render_v1(&game, Uuid::new_v4());
+
+    assert_eq!(for_alice["player2_id"], for_spectator["player2_id"]);
+    assert_eq!(for_spectator["player1_id"], again["player1_id"]);
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use ws::{
+    connection_registry::ConnectionRegistry,
+    session_queue::{OverflowPolicy, SessionQueue},
+};
+
+fn queue() -> Arc<SessionQueue> {
+    Arc::new(SessionQueue::new(8, OverflowPolicy::DropOldest))
+}
+
+#[test]
+fn game_events_fire_on_first_and_last_local_session() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let registry = ConnectionRegistry::with_game_events(tx);
+    let user = Uuid::new_v4();
+
+    registry.add("g1", "s1", user, queue());
+    registry.add("g1", "s2", user, queue());
+    assert_eq!(rx.try_recv().unwrap(), "g1");
+    assert!(rx.try_recv().is_err());
+
+    registry.remove("g1", "s1");
+    assert!(rx.try_recv().is_err());
+
+    registry.remove("g1", "s2");
+    assert_eq!(rx.try_recv().unwrap(), "g1");
+    assert!(!registry.games.contains_key("g1"));
+}
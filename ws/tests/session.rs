@@ -0,0 +1,1029 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::web::Bytes;
+use actix_ws::{CloseCode, CloseReason, Closed, Message, ProtocolError};
+use futures_util::stream;
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+use uuid::Uuid;
+use ws::{
+    broadcast::InProcessBroadcaster,
+    codec::Encoding,
+    config::{RedisConfig, WsConfig},
+    game::{
+        chat::{ChatRecord, MemoryChatStore},
+        game_state::{GameState, Player},
+        messages::WsServerMessage,
+        store::MemoryGameStore,
+    },
+    handler::{run_session, SessionSink, SessionUser},
+    keys::Keys,
+    manager::WsManager,
+    pubsub::PubSub,
+    session_queue::{Outbound, OverflowPolicy, SessionQueue},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping,
+    Pong(Vec<u8>),
+    Close(Option<CloseReason>),
+}
+
+/// Records every frame the server writes instead of sending it anywhere.
+#[derive(Clone, Default)]
+struct RecordingSink(Arc<Mutex<Vec<Frame>>>);
+
+impl RecordingSink {
+    fn frames(&self) -> Vec<Frame> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Text frames parsed as JSON, in the order they were written.
+    fn texts(&self) -> Vec<serde_json::Value> {
+        self.frames()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                Frame::Text(json) => serde_json::from_str(&json).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Waits until the writer has sent at least `count` text frames.
+    async fn wait_for_texts(&self, count: usize) -> Vec<serde_json::Value> {
+        eventually("text frames", || {
+            let texts = self.texts();
+            (texts.len() >= count).then_some(texts)
+        })
+        .await
+    }
+
+    /// Waits until the server has closed the socket.
+    async fn wait_for_close(&self) -> CloseReason {
+        eventually("a close frame", || self.first_close()).await
+    }
+
+    fn first_close(&self) -> Option<CloseReason> {
+        self.frames().into_iter().find_map(|frame| match frame {
+            Frame::Close(reason) => reason,
+            _ => None,
+        })
+    }
+}
+
+impl SessionSink for RecordingSink {
+    async fn text(&mut self, msg: String) -> Result<(), Closed> {
+        self.0.lock().unwrap().push(Frame::Text(msg));
+        Ok(())
+    }
+
+    async fn binary(&mut self, msg: Vec<u8>) -> Result<(), Closed> {
+        self.0.lock().unwrap().push(Frame::Binary(msg));
+        Ok(())
+    }
+
+    async fn ping(&mut self, _msg: &[u8]) -> Result<(), Closed> {
+        self.0.lock().unwrap().push(Frame::Ping);
+        Ok(())
+    }
+
+    async fn pong(&mut self, msg: &[u8]) -> Result<(), Closed> {
+        self.0.lock().unwrap().push(Frame::Pong(msg.to_vec()));
+        Ok(())
+    }
+
+    async fn close(self, reason: Option<CloseReason>) -> Result<(), Closed> {
+        self.0.lock().unwrap().push(Frame::Close(reason));
+        Ok(())
+    }
+}
+
+/// Polls `check` until it returns something, failing the test after a few
+/// seconds instead of hanging it.
+async fn eventually<T>(what: &str, mut check: impl FnMut() -> Option<T>) -> T {
+    let poll = async {
+        loop {
+            if let Some(value) = check() {
+                return value;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), poll)
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting for {}", what))
+}
+
+/// A manager pointed at a Redis that isn't there: everything local works,
+/// cross-instance publishing just fails.
+async fn manager(config: WsConfig) -> Arc<WsManager> {
+    let store = Arc::new(MemoryGameStore::new());
+    Arc::new(
+        WsManager::new(
+            config,
+            store,
+            Arc::new(MemoryChatStore::new()),
+            PubSub::factory(
+                "redis://127.0.0.1:1",
+                Keys::default(),
+                RedisConfig::default(),
+                true,
+            ),
+        )
+        .await
+        .unwrap(),
+    )
+}
+
+fn user() -> SessionUser {
+    SessionUser {
+        user_id: Uuid::new_v4(),
+        username: "alice".to_string(),
+        is_guest: false,
+    }
+}
+
+/// Registers a second local socket in the game and returns its queue.
+fn observer(manager: &WsManager, game_id: &str) -> Arc<SessionQueue> {
+    let queue = Arc::new(SessionQueue::new(16, OverflowPolicy::DropOldest));
+    manager
+        .registry
+        .add(game_id, "observer", Uuid::new_v4(), queue.clone());
+    queue
+}
+
+/// Stores a game with `player` holding X.
+async fn seat(manager: &WsManager, game_id: &str, player: &SessionUser) {
+    manager
+        .game_manager
+        .create_game(
+            player.user_id,
+            player.username.clone(),
+            "seat".to_string(),
+            game_id.to_string(),
+            false,
+        )
+        .await
+        .unwrap();
+}
+
+fn frames(
+    messages: Vec<Message>,
+) -> impl futures_util::Stream<Item = Result<Message, ProtocolError>> {
+    stream::iter(messages.into_iter().map(Ok))
+}
+
+/// A client that stays connected until told otherwise, so the writer gets
+/// to flush before the session ends.
+fn live_client() -> (
+    UnboundedSender<Message>,
+    impl futures_util::Stream<Item = Result<Message, ProtocolError>> + Unpin,
+) {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let incoming = Box::pin(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|msg| (Ok(msg), rx))
+    }));
+    (tx, incoming)
+}
+
+async fn hang_up(client: UnboundedSender<Message>, session: JoinHandle<()>) {
+    client.send(Message::Close(None)).unwrap();
+    drop(client);
+    tokio::time::timeout(Duration::from_secs(5), session)
+        .await
+        .expect("session should end")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn client_close_is_echoed_and_session_unregistered() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let reason = CloseReason {
+        code: CloseCode::Normal,
+        description: Some("bye".to_string()),
+    };
+
+    run_session(
+        manager.clone(),
+        "game-1".to_string(),
+        "session-1".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        frames(vec![Message::Close(Some(reason.clone()))]),
+    )
+    .await;
+
+    assert_eq!(sink.first_close(), Some(reason));
+    assert!(manager.registry.games.get("game-1").is_none());
+}
+
+#[tokio::test]
+async fn dropped_connection_notifies_remaining_players() {
+    let manager = manager(WsConfig::default()).await;
+    let observer = observer(&manager, "game-2");
+    let sink = RecordingSink::default();
+    let leaving = user();
+    seat(&manager, "game-2", &leaving).await;
+
+    run_session(
+        manager.clone(),
+        "game-2".to_string(),
+        "session-2".to_string(),
+        leaving.clone(),
+        Encoding::Json,
+        sink.clone(),
+        frames(vec![]),
+    )
+    .await;
+
+    let close = sink.first_close().expect("session should be closed");
+    assert_eq!(close.code, CloseCode::Away);
+    assert_eq!(close.description.as_deref(), Some("Connection lost"));
+
+    let game = manager.registry.games.get("game-2").unwrap();
+    assert!(game.get("session-2").is_none());
+    assert!(game.get("observer").is_some());
+    drop(game);
+
+    let Some(Outbound::Text(json)) = observer.pop().await else {
+        panic!("observer should have been notified");
+    };
+    let msg: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(msg["type"], "player_disconnected");
+    assert_eq!(msg["username"], leaving.username);
+    assert!(msg.get("user_id").is_none());
+}
+
+#[tokio::test]
+async fn players_with_another_socket_and_spectators_leave_silently() {
+    let manager = manager(WsConfig::default()).await;
+    let observer = observer(&manager, "game-12");
+    let player = user();
+    seat(&manager, "game-12", &player).await;
+    let other_tab = Arc::new(SessionQueue::new(16, OverflowPolicy::DropOldest));
+    manager
+        .registry
+        .add("game-12", "other-tab", player.user_id, other_tab);
+
+    for (session_id, leaving) in [("session-12", player), ("spectator-12", user())] {
+        run_session(
+            manager.clone(),
+            "game-12".to_string(),
+            session_id.to_string(),
+            leaving,
+            Encoding::Json,
+            RecordingSink::default(),
+            frames(vec![]),
+        )
+        .await;
+    }
+
+    assert!(observer.is_empty());
+}
+
+#[tokio::test]
+async fn replies_queued_before_the_close_are_flushed() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+
+    run_session(
+        manager.clone(),
+        "game-13".to_string(),
+        "session-13".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        frames(vec![
+            Message::Text(r#"{"type":"rewind"}"#.into()),
+            Message::Close(None),
+        ]),
+    )
+    .await;
+
+    let frames = sink.frames();
+    let Some(Frame::Text(json)) = frames.first() else {
+        panic!("the error reply should be sent before the close");
+    };
+    let reply: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(reply["type"], "error");
+    assert!(matches!(frames.last(), Some(Frame::Close(_))));
+}
+
+#[tokio::test]
+async fn client_pings_are_answered() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+
+    run_session(
+        manager,
+        "game-3".to_string(),
+        "session-3".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        frames(vec![
+            Message::Ping(Bytes::from_static(b"hello")),
+            Message::Close(None),
+        ]),
+    )
+    .await;
+
+    assert!(sink.frames().contains(&Frame::Pong(b"hello".to_vec())));
+    assert_eq!(sink.first_close().unwrap().code, CloseCode::Normal);
+}
+
+#[tokio::test]
+async fn silent_clients_are_reaped() {
+    let manager = manager(WsConfig {
+        heartbeat_interval: Duration::from_millis(10),
+        client_timeout: Duration::from_millis(50),
+        ..WsConfig::default()
+    })
+    .await;
+    let sink = RecordingSink::default();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_session(
+            manager.clone(),
+            "game-4".to_string(),
+            "session-4".to_string(),
+            user(),
+            Encoding::Json,
+            sink.clone(),
+            stream::pending(),
+        ),
+    )
+    .await
+    .expect("silent session should be reaped");
+
+    assert!(sink.frames().contains(&Frame::Ping));
+    assert_eq!(sink.first_close().unwrap().code, CloseCode::Away);
+    assert_eq!(manager.metrics.reaped_connections(), 1);
+    assert!(manager.registry.games.get("game-4").is_none());
+}
+
+#[tokio::test]
+async fn server_side_close_goes_through_the_writer() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let banned = user();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-5".to_string(),
+        "session-5".to_string(),
+        banned.clone(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    eventually("the session to register", || {
+        manager.registry.games.get("game-5").map(|_| ())
+    })
+    .await;
+
+    let reason = CloseReason {
+        code: CloseCode::Policy,
+        description: Some("banned".to_string()),
+    };
+    assert_eq!(manager.registry.disconnect_user(banned.user_id, &reason), 1);
+
+    sink.wait_for_close().await;
+
+    // The client answers the close frame and goes away.
+    hang_up(client, session).await;
+
+    assert_eq!(sink.first_close(), Some(reason));
+    assert!(manager.registry.games.get("game-5").is_none());
+}
+
+#[tokio::test]
+async fn hello_negotiates_version_and_unknown_types_are_answered() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-6".to_string(),
+        "session-6".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    for text in [
+        r#"{"type":"hello","version":99,"capabilities":["presence","teleport"]}"#,
+        r#"{"type":"rewind","turns":2}"#,
+    ] {
+        client.send(Message::Text(text.into())).unwrap();
+    }
+
+    let replies = sink.wait_for_texts(2).await;
+    assert_eq!(replies[0]["type"], "welcome");
+    assert_eq!(replies[0]["version"], ws::protocol::CURRENT_VERSION);
+    assert_eq!(replies[0]["capabilities"], serde_json::json!(["presence"]));
+    assert_eq!(replies[1]["type"], "error");
+    assert!(replies[1]["message"].as_str().unwrap().contains("rewind"));
+    assert!(sink.first_close().is_none());
+
+    hang_up(client, session).await;
+}
+
+#[tokio::test]
+async fn replies_echo_the_request_id() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager,
+        "game-7".to_string(),
+        "session-7".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    for text in [
+        r#"{"type":"hello","version":2,"request_id":"r1"}"#,
+        // There is no game (nor Redis) behind this socket, so the move fails.
+        r#"{"type":"make_move","position":4,"request_id":"r2"}"#,
+    ] {
+        client.send(Message::Text(text.into())).unwrap();
+    }
+
+    let replies = sink.wait_for_texts(3).await;
+    assert_eq!(
+        replies[0],
+        serde_json::json!({"type": "ack", "request_id": "r1"})
+    );
+    assert_eq!(replies[1]["type"], "welcome");
+    assert_eq!(replies[2]["type"], "error");
+    assert_eq!(replies[2]["request_id"], "r2");
+
+    hang_up(client, session).await;
+}
+
+#[tokio::test]
+async fn msgpack_sessions_talk_in_binary_frames() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager,
+        "game-8".to_string(),
+        "session-8".to_string(),
+        user(),
+        Encoding::MessagePack,
+        sink.clone(),
+        incoming,
+    ));
+
+    let hello = serde_json::json!({"type": "hello", "version": 2, "request_id": "r1"});
+    let bytes = rmp_serde::to_vec_named(&hello).unwrap();
+    client.send(Message::Binary(bytes.into())).unwrap();
+
+    let replies = eventually("binary replies", || {
+        let replies: Vec<serde_json::Value> = sink
+            .frames()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                Frame::Binary(bytes) => rmp_serde::from_slice(&bytes).ok(),
+                _ => None,
+            })
+            .collect();
+        (replies.len() >= 2).then_some(replies)
+    })
+    .await;
+
+    assert_eq!(
+        replies[0],
+        serde_json::json!({"type": "ack", "request_id": "r1"})
+    );
+    assert_eq!(replies[1]["type"], "welcome");
+    assert!(sink.texts().is_empty());
+
+    hang_up(client, session).await;
+}
+
+/// Binary frames the sink received, decoded as CBOR.
+fn cbor_replies(sink: &RecordingSink) -> Vec<serde_json::Value> {
+    sink.frames()
+        .into_iter()
+        .filter_map(|frame| match frame {
+            Frame::Binary(bytes) => ciborium::from_reader(bytes.as_slice()).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn cbor_sessions_share_the_encoding_of_a_broadcast() {
+    let manager = manager(WsConfig::default()).await;
+    let observer = observer(&manager, "game-14");
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-14".to_string(),
+        "session-14".to_string(),
+        user(),
+        Encoding::Cbor,
+        sink.clone(),
+        incoming,
+    ));
+
+    let hello = serde_json::json!({"type": "hello", "version": 2, "request_id": "r1"});
+    let mut bytes = Vec::new();
+    ciborium::into_writer(&hello, &mut bytes).unwrap();
+    client.send(Message::Binary(bytes.into())).unwrap();
+    eventually("the welcome", || {
+        (cbor_replies(&sink).len() >= 2).then_some(())
+    })
+    .await;
+
+    let notice = WsServerMessage::Error {
+        message: "for everyone".to_string(),
+        request_id: None,
+    };
+    manager
+        .registry
+        .broadcast_except("game-14", &notice.to_outbound().unwrap(), "nobody");
+
+    let replies = eventually("the broadcast", || {
+        let replies = cbor_replies(&sink);
+        (replies.len() >= 3).then_some(replies)
+    })
+    .await;
+    assert_eq!(replies[0]["type"], "ack");
+    assert_eq!(replies[1]["type"], "welcome");
+    assert_eq!(replies[2]["message"], "for everyone");
+    assert!(sink.texts().is_empty());
+
+    // The observer holds the same message, already encoded by the writer.
+    let Some(Outbound::Text(shared)) = observer.pop().await else {
+        panic!("observer should have the broadcast");
+    };
+    assert!(shared.is_encoded(Encoding::Cbor));
+
+    hang_up(client, session).await;
+}
+
+#[tokio::test]
+async fn moves_reach_delta_sessions_as_move_applied() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-9".to_string(),
+        "session-9".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    let hello = r#"{"type":"hello","version":2,"capabilities":["deltas"]}"#;
+    client.send(Message::Text(hello.into())).unwrap();
+    sink.wait_for_texts(1).await;
+
+    let mut game = GameState::new(
+        "game-9".to_string(),
+        Uuid::new_v4(),
+        "bob".to_string(),
+        "s".to_string(),
+        false,
+    );
+    game.seq = 3;
+    let update = WsServerMessage::move_outbound(&game, 4, Player::X).unwrap();
+    manager.registry.send("game-9", "session-9", update);
+
+    let replies = sink.wait_for_texts(2).await;
+    assert_eq!(replies[1]["type"], "move_applied");
+    assert_eq!(replies[1]["position"], 4);
+    assert_eq!(replies[1]["symbol"], "X");
+    assert_eq!(replies[1]["seq"], 3);
+
+    hang_up(client, session).await;
+}
+
+#[tokio::test]
+async fn game_states_are_projected_for_each_viewer() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+    let player = user();
+
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-10".to_string(),
+        "session-10".to_string(),
+        player.clone(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    let hello = r#"{"type":"hello","version":2}"#;
+    client.send(Message::Text(hello.into())).unwrap();
+    sink.wait_for_texts(1).await;
+
+    let game = GameState::new(
+        "game-10".to_string(),
+        player.user_id,
+        player.username.clone(),
+        "session-10".to_string(),
+        false,
+    );
+    let update = WsServerMessage::state_outbound(&game).unwrap();
+    manager.registry.send("game-10", "session-10", update);
+
+    let replies = sink.wait_for_texts(2).await;
+    let view = &replies[1]["game"];
+    assert_eq!(replies[1]["type"], "game_state");
+    assert_eq!(view["role"], "player");
+    assert_eq!(view["you"], "X");
+    assert_eq!(view["turn"], "X");
+    assert_eq!(
+        view["players"],
+        serde_json::json!([{"symbol": "X", "username": "alice"}])
+    );
+    assert!(view.get("player1_session").is_none());
+    assert!(view.get("player1_id").is_none());
+
+    hang_up(client, session).await;
+}
+
+/// A chat line by bob, as another session of the game would broadcast it.
+fn chat_line(from: Uuid, text: &str) -> Outbound {
+    Outbound::Chat {
+        from,
+        message: serde_json::json!({"type": "chat", "from": "bob", "text": text, "sent_at": 0})
+            .to_string()
+            .into(),
+    }
+}
+
+#[tokio::test]
+async fn chat_only_reaches_sessions_that_asked_for_it() {
+    for (capabilities, expect_chat) in [("[]", false), (r#"["chat"]"#, true)] {
+        let manager = manager(WsConfig::default()).await;
+        let sink = RecordingSink::default();
+        let (client, incoming) = live_client();
+
+        let session = tokio::spawn(run_session(
+            manager.clone(),
+            "game-11".to_string(),
+            "session-11".to_string(),
+            user(),
+            Encoding::Json,
+            sink.clone(),
+            incoming,
+        ));
+
+        let hello = format!(
+            r#"{{"type":"hello","version":2,"capabilities":{}}}"#,
+            capabilities
+        );
+        client.send(Message::Text(hello.into())).unwrap();
+        sink.wait_for_texts(1).await;
+        manager
+            .registry
+            .send("game-11", "session-11", chat_line(Uuid::new_v4(), "gl hf"));
+
+        // A snapshot of this unsaved game fails, but its error marks the
+        // point by which the chat line above has been written or skipped.
+        let snapshot = r#"{"type":"request_snapshot"}"#;
+        client.send(Message::Text(snapshot.into())).unwrap();
+
+        let replies = sink.wait_for_texts(if expect_chat { 3 } else { 2 }).await;
+        let chat: Vec<_> = replies
+            .iter()
+            .filter(|reply| reply["type"] == "chat")
+            .collect();
+        assert_eq!(chat.len(), usize::from(expect_chat));
+        assert_eq!(replies.last().unwrap()["type"], "error");
+
+        hang_up(client, session).await;
+    }
+}
+
+/// Texts of the `chat` messages among `replies`, live or in a history.
+fn chat_texts(replies: &[serde_json::Value]) -> Vec<String> {
+    replies
+        .iter()
+        .flat_map(|reply| match reply["type"].as_str() {
+            Some("chat") => vec![reply.clone()],
+            Some("chat_history") => reply["messages"].as_array().unwrap().clone(),
+            _ => vec![],
+        })
+        .map(|chat| chat["text"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn muting_hides_live_chat_and_history_of_that_user() {
+    let manager = manager(WsConfig::default()).await;
+    let alice = user();
+    let bob = SessionUser {
+        user_id: Uuid::new_v4(),
+        username: "bob".to_string(),
+        is_guest: false,
+    };
+    seat(&manager, "game-15", &alice).await;
+    manager
+        .game_manager
+        .join_game(
+            "game-15",
+            bob.user_id,
+            bob.username.clone(),
+            "b".to_string(),
+            false,
+        )
+        .await
+        .unwrap();
+    for (sender, text) in [(bob.user_id, "from bob"), (alice.user_id, "from alice")] {
+        let record = ChatRecord {
+            sender,
+            message: WsServerMessage::Chat {
+                from: "someone".to_string(),
+                text: text.to_string(),
+                sent_at: 0,
+            },
+        };
+        manager
+            .chat
+            .append_history("game-15", &record)
+            .await
+            .unwrap();
+    }
+
+    let hello = r#"{"type":"hello","version":2,"capabilities":["chat"]}"#;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-15".to_string(),
+        "session-15".to_string(),
+        alice.clone(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+    client.send(Message::Text(hello.into())).unwrap();
+    let replies = sink.wait_for_texts(2).await;
+    assert_eq!(chat_texts(&replies), ["from bob", "from alice"]);
+
+    let mute = r#"{"type":"mute","request_id":"m1"}"#;
+    client.send(Message::Text(mute.into())).unwrap();
+    sink.wait_for_texts(3).await;
+    let registry = &manager.registry;
+    registry.send("game-15", "session-15", chat_line(bob.user_id, "muted"));
+    registry.send("game-15", "session-15", chat_line(alice.user_id, "heard"));
+    // The snapshot marks the point by which both lines were handled.
+    let snapshot = r#"{"type":"request_snapshot"}"#;
+    client.send(Message::Text(snapshot.into())).unwrap();
+    let replies = sink.wait_for_texts(5).await;
+    assert_eq!(replies[2]["request_id"], "m1");
+    assert_eq!(chat_texts(&replies[3..]), ["heard"]);
+    assert_eq!(replies[4]["type"], "game_state");
+    hang_up(client, session).await;
+
+    // A new socket loads the mute before the history.
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-15".to_string(),
+        "session-16".to_string(),
+        alice,
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+    client.send(Message::Text(hello.into())).unwrap();
+    let replies = sink.wait_for_texts(2).await;
+    assert_eq!(chat_texts(&replies), ["from alice"]);
+
+    let unmute = r#"{"type":"unmute","request_id":"u1"}"#;
+    client.send(Message::Text(unmute.into())).unwrap();
+    sink.wait_for_texts(3).await;
+    let registry = &manager.registry;
+    registry.send("game-15", "session-16", chat_line(bob.user_id, "back"));
+    let replies = sink.wait_for_texts(4).await;
+    assert_eq!(chat_texts(&replies[3..]), ["back"]);
+    hang_up(client, session).await;
+}
+
+#[tokio::test]
+async fn shutdown_warns_sessions_and_waits_for_them_to_close() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-12".to_string(),
+        "session-12".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    let hello = r#"{"type":"hello","version":2}"#;
+    client.send(Message::Text(hello.into())).unwrap();
+    sink.wait_for_texts(1).await;
+
+    let shutdown = tokio::spawn({
+        let manager = manager.clone();
+        async move { manager.shutdown().await }
+    });
+
+    sink.wait_for_close().await;
+    assert!(!manager.is_accepting());
+    assert!(!shutdown.is_finished());
+
+    let replies = sink.texts();
+    assert_eq!(replies[1]["type"], "server_shutdown");
+    assert_eq!(replies[1]["reconnect_after_ms"], 2000);
+    assert_eq!(sink.first_close().unwrap().code, CloseCode::Restart);
+
+    hang_up(client, session).await;
+    tokio::time::timeout(Duration::from_secs(5), shutdown)
+        .await
+        .expect("shutdown should finish once the session is gone")
+        .unwrap();
+    assert!(manager.registry.is_empty());
+}
+
+#[tokio::test]
+async fn shutdown_notice_reaches_v1_sessions() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    // No hello: the session speaks version 1.
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-16".to_string(),
+        "session-16".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+    eventually("the session to register", || {
+        manager.registry.games.get("game-16").map(|_| ())
+    })
+    .await;
+
+    let shutdown = tokio::spawn({
+        let manager = manager.clone();
+        async move { manager.shutdown().await }
+    });
+
+    assert_eq!(sink.wait_for_close().await.code, CloseCode::Restart);
+    let replies = sink.texts();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["type"], "server_shutdown");
+    assert_eq!(replies[0]["reconnect_after_ms"], 2000);
+
+    hang_up(client, session).await;
+    tokio::time::timeout(Duration::from_secs(5), shutdown)
+        .await
+        .expect("shutdown should finish once the session is gone")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn not_ready_while_redis_is_unreachable() {
+    let manager = manager(WsConfig::default()).await;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(manager.is_accepting());
+    assert!(!manager.broadcaster.is_connected());
+    assert!(!manager.is_ready());
+
+    tokio::time::timeout(Duration::from_secs(5), manager.shutdown())
+        .await
+        .expect("the reconnect loop should stop on shutdown");
+}
+
+#[tokio::test]
+async fn in_process_broadcaster_is_always_ready() {
+    let store = Arc::new(MemoryGameStore::new());
+    let manager = WsManager::new(
+        WsConfig::default(),
+        store,
+        Arc::new(MemoryChatStore::new()),
+        InProcessBroadcaster::factory(),
+    )
+    .await
+    .unwrap();
+
+    assert!(manager.broadcaster.is_connected());
+    assert!(manager.is_ready());
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn replaying_events_needs_the_events_capability() {
+    let manager = manager(WsConfig::default()).await;
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+
+    let session = tokio::spawn(run_session(
+        manager,
+        "game-14".to_string(),
+        "session-14".to_string(),
+        user(),
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+
+    let hello = r#"{"type":"hello","version":2,"capabilities":["deltas"]}"#;
+    client.send(Message::Text(hello.into())).unwrap();
+    let replay = r#"{"type":"replay_events","request_id":"r-1"}"#;
+    client.send(Message::Text(replay.into())).unwrap();
+
+    let replies = sink.wait_for_texts(2).await;
+    let error = &replies[1];
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["request_id"], "r-1");
+    assert_eq!(
+        error["message"],
+        "Replaying events needs the events capability"
+    );
+
+    hang_up(client, session).await;
+}
+
+#[tokio::test]
+async fn events_are_replayed_from_the_start_or_after_an_entry() {
+    let manager = manager(WsConfig::default()).await;
+    let alice = user();
+    seat(&manager, "game-18", &alice).await;
+    manager
+        .game_manager
+        .join_game("game-18", Uuid::new_v4(), "bob".into(), "b".into(), false)
+        .await
+        .unwrap();
+
+    let sink = RecordingSink::default();
+    let (client, incoming) = live_client();
+    let session = tokio::spawn(run_session(
+        manager.clone(),
+        "game-18".to_string(),
+        "session-18".to_string(),
+        alice,
+        Encoding::Json,
+        sink.clone(),
+        incoming,
+    ));
+    let replies = || {
+        sink.texts()
+            .into_iter()
+            .filter(|reply| reply["type"] == "game_events")
+            .collect::<Vec<_>>()
+    };
+
+    let hello = r#"{"type":"hello","version":2,"capabilities":["events"]}"#;
+    client.send(Message::Text(hello.into())).unwrap();
+    let replay = r#"{"type":"replay_events"}"#;
+    client.send(Message::Text(replay.into())).unwrap();
+    let all = eventually("a replay", || replies().pop()).await;
+    let events = all["events"].as_array().unwrap();
+    assert_eq!(events[0]["event"]["type"], "created");
+    assert_eq!(events[1]["event"]["type"], "joined");
+    assert_eq!(events[1]["event"]["player"], "bob");
+    assert_eq!(events.len(), 2);
+
+    let resume = serde_json::json!({"type": "replay_events", "after": events[0]["id"]});
+    client
+        .send(Message::Text(resume.to_string().into()))
+        .unwrap();
+    let rest = eventually("a resumed replay", || replies().get(1).cloned()).await;
+    assert_eq!(rest["events"], serde_json::json!([events[1]]));
+
+    hang_up(client, session).await;
+}
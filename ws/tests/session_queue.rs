@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use actix_ws::{CloseCode, CloseReason};
+use tokio::time::timeout;
+use ws::session_queue::{Outbound, OverflowPolicy, PushOutcome, SessionQueue};
+
+fn text(s: &str) -> Outbound {
+    Outbound::Text(s.into())
+}
+
+fn state(s: &str) -> Outbound {
+    Outbound::State(s.to_string())
+}
+
+fn delta(d: &str, s: &str) -> Outbound {
+    Outbound::Delta {
+        delta: d.into(),
+        state: s.to_string(),
+    }
+}
+
+/// Everything currently queued, without waiting for more.
+async fn drain(queue: &SessionQueue) -> Vec<String> {
+    let mut items = Vec::new();
+    while !queue.is_empty() {
+        items.push(match queue.pop().await.unwrap() {
+            Outbound::Text(s) => s.to_string(),
+            Outbound::State(s) => s,
+            Outbound::Delta { delta, .. } => delta.to_string(),
+            Outbound::Chat { message, .. } => message.to_string(),
+            Outbound::Close(reason) => format!("close:{:?}", reason.code),
+        });
+    }
+    items
+}
+
+#[tokio::test]
+async fn drop_oldest_makes_room_for_the_newest_message() {
+    let queue = SessionQueue::new(2, OverflowPolicy::DropOldest);
+
+    assert_eq!(queue.push(text("a")), PushOutcome::Queued);
+    assert_eq!(queue.push(text("b")), PushOutcome::Queued);
+    assert_eq!(queue.push(text("c")), PushOutcome::Dropped(1));
+
+    assert_eq!(drain(&queue).await, ["b", "c"]);
+    assert_eq!(queue.dropped(), 1);
+}
+
+#[tokio::test]
+async fn coalesce_drops_superseded_states_and_keeps_other_messages() {
+    let queue = SessionQueue::new(3, OverflowPolicy::CoalesceState);
+
+    queue.push(state("s1"));
+    queue.push(text("chat"));
+    queue.push(state("s2"));
+    assert_eq!(queue.push(state("s3")), PushOutcome::Dropped(2));
+
+    assert_eq!(drain(&queue).await, ["chat", "s3"]);
+}
+
+#[tokio::test]
+async fn coalesce_lets_a_state_supersede_queued_deltas() {
+    let queue = SessionQueue::new(2, OverflowPolicy::CoalesceState);
+
+    queue.push(delta("d1", "s1"));
+    queue.push(delta("d2", "s2"));
+    assert_eq!(queue.push(state("s3")), PushOutcome::Dropped(2));
+
+    assert_eq!(drain(&queue).await, ["s3"]);
+}
+
+#[tokio::test]
+async fn coalesce_falls_back_to_dropping_the_oldest() {
+    let queue = SessionQueue::new(2, OverflowPolicy::CoalesceState);
+
+    queue.push(state("s1"));
+    queue.push(text("a"));
+    assert_eq!(queue.push(text("b")), PushOutcome::Dropped(1));
+
+    assert_eq!(drain(&queue).await, ["a", "b"]);
+}
+
+#[tokio::test]
+async fn disconnect_replaces_a_full_queue_with_a_close_frame() {
+    let queue = SessionQueue::new(2, OverflowPolicy::Disconnect);
+
+    queue.push(text("a"));
+    queue.push(text("b"));
+    assert_eq!(queue.push(text("c")), PushOutcome::Disconnected);
+    assert_eq!(queue.push(text("d")), PushOutcome::Closed);
+
+    assert_eq!(
+        drain(&queue).await,
+        [format!("close:{:?}", CloseCode::Policy)]
+    );
+    assert!(queue.pop().await.is_none());
+}
+
+#[tokio::test]
+async fn close_frames_go_through_a_full_queue() {
+    let queue = SessionQueue::new(1, OverflowPolicy::DropOldest);
+
+    queue.push(text("a"));
+    let close = Outbound::Close(CloseReason::from(CloseCode::Away));
+    assert_eq!(queue.push(close), PushOutcome::Queued);
+
+    assert_eq!(
+        drain(&queue).await,
+        ["a".to_string(), format!("close:{:?}", CloseCode::Away)]
+    );
+}
+
+#[tokio::test]
+async fn close_wakes_a_waiting_writer_after_the_queue_drains() {
+    let queue = std::sync::Arc::new(SessionQueue::new(4, OverflowPolicy::DropOldest));
+    queue.push(text("last"));
+
+    let writer = tokio::spawn({
+        let queue = queue.clone();
+        async move {
+            let mut popped = Vec::new();
+            while let Some(msg) = queue.pop().await {
+                popped.push(msg);
+            }
+            popped.len()
+        }
+    });
+
+    tokio::task::yield_now().await;
+    queue.close();
+    assert_eq!(queue.push(text("late")), PushOutcome::Closed);
+
+    let popped = timeout(Duration::from_secs(1), writer)
+        .await
+        .expect("writer was not woken by close")
+        .unwrap();
+    assert_eq!(popped, 1);
+}
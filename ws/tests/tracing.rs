@@ -0,0 +1,37 @@
+use opentelemetry::{
+    global,
+    trace::{TraceContextExt, TracerProvider},
+};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use ws::{broadcast::MessagePayload, session_queue::Outbound};
+
+#[test]
+fn broadcast_payload_carries_the_publishing_trace() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("publish");
+        let trace_id = span.context().span().span_context().trace_id();
+
+        let payload = span.in_scope(|| {
+            MessagePayload::new("instance", "session", &Outbound::Text("hi".into())).unwrap()
+        });
+        let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+        let traceparent = json["trace"]["traceparent"].as_str().unwrap();
+
+        assert!(traceparent.contains(&trace_id.to_string()));
+    });
+}
+
+#[test]
+fn broadcast_payload_without_a_span_has_no_trace() {
+    let payload = MessagePayload::new("instance", "session", &Outbound::Text("hi".into())).unwrap();
+    let json: serde_json::Value = serde_json::to_value(&payload).unwrap();
+
+    assert!(json.get("trace").is_none());
+}